@@ -0,0 +1,209 @@
+//! Ambient light filtering and mapping to brightness, independent of the ADC.
+//!
+//! The LDR pulls its ADC input down as the light gets brighter, so readings are inverted
+//! to a light level in percent. Levels are smoothed by an exponential moving average and
+//! only followed once they move past a hysteresis band, so flicker and noise don't make
+//! the backlight hunt.
+
+use cyd_encoder::settings::BrightnessCurve;
+
+/// Full scale of a 12-bit ADC reading
+pub const MAX_READING: u16 = 4095;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FilterConfig {
+    /// Each reading moves the average by 1/2^`smoothing` of the difference
+    pub smoothing: u32,
+    /// Light level change in percent needed to change the brightness
+    pub hysteresis: u8,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            smoothing: 3,
+            hysteresis: 5,
+        }
+    }
+}
+
+// Fraction bits of the average
+const SCALE_BITS: u32 = 8;
+
+/// Follows the ambient light and maps it to brightness.
+///
+/// Call [`AutoBrightness::update`] with each ADC reading.
+pub struct AutoBrightness {
+    config: FilterConfig,
+    curve: BrightnessCurve,
+    // Light level scaled by 2^SCALE_BITS
+    average: Option<u32>,
+    // Light level the brightness follows
+    level: Option<u8>,
+}
+
+impl AutoBrightness {
+    /// Map light to brightness by `curve`
+    pub fn new(config: FilterConfig, curve: BrightnessCurve) -> Self {
+        Self {
+            config,
+            curve,
+            average: None,
+            level: None,
+        }
+    }
+
+    /// Smoothed light level in percent
+    pub fn light(&self) -> Option<u8> {
+        self.average
+            .map(|average| ((average + (1 << (SCALE_BITS - 1))) >> SCALE_BITS) as u8)
+    }
+
+    /// Add an ADC reading, returns the new brightness when it changes
+    pub fn update(&mut self, reading: u16) -> Option<u8> {
+        let sample = (light(reading) as u32) << SCALE_BITS;
+        self.average = Some(match self.average {
+            // Round towards the sample so the average can reach it
+            Some(average) if sample >= average => {
+                average + (sample - average).div_ceil(1 << self.config.smoothing)
+            }
+            Some(average) => average - (average - sample).div_ceil(1 << self.config.smoothing),
+            None => sample,
+        });
+        let light = self.light()?;
+        // The ends of the range are always reached, noise can't go past them
+        let end = light == 0 || light == 100;
+        if self.level.is_some_and(|level| {
+            level == light || (level.abs_diff(light) < self.config.hysteresis && !end)
+        }) {
+            return None;
+        }
+        let previous = self
+            .level
+            .map(|level| brightness(self.curve.points(), level));
+        self.level = Some(light);
+        let brightness = brightness(self.curve.points(), light);
+        (previous != Some(brightness)).then_some(brightness)
+    }
+}
+
+/// Light level in percent of an ADC reading
+pub fn light(reading: u16) -> u8 {
+    let reading = reading.min(MAX_READING) as u32;
+    ((MAX_READING as u32 - reading) * 100 / MAX_READING as u32) as u8
+}
+
+/// Brightness at `light` on `curve`, full brightness without points
+pub fn brightness(curve: &[(u8, u8)], light: u8) -> u8 {
+    let (Some(&(first_light, first)), Some(&(_, last))) = (curve.first(), curve.last()) else {
+        return 100;
+    };
+    if light <= first_light {
+        return first;
+    }
+    curve
+        .windows(2)
+        .find(|pair| light <= pair[1].0)
+        .map_or(last, |pair| {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            let (x0, y0, x1, y1) = (x0 as i32, y0 as i32, x1 as i32, y1 as i32);
+            (y0 + (y1 - y0) * (light as i32 - x0) / (x1 - x0)) as u8
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ADC reading of `light` percent
+    fn reading(light: u8) -> u16 {
+        (MAX_READING as u32 * (100 - light as u32) / 100) as u16
+    }
+
+    /// Brightness changes while `readings` readings of `light` come in
+    fn feed(auto: &mut AutoBrightness, light: u8, readings: usize) -> Vec<u8> {
+        (0..readings)
+            .filter_map(|_| auto.update(reading(light)))
+            .collect()
+    }
+
+    fn filter() -> AutoBrightness {
+        AutoBrightness::new(FilterConfig::default(), BrightnessCurve::default())
+    }
+
+    #[test]
+    fn light_levels() {
+        assert_eq!(light(MAX_READING), 0);
+        assert_eq!(light(u16::MAX), 0);
+        assert_eq!(light(0), 100);
+        for level in 0..=100 {
+            assert_eq!(light(reading(level)), level);
+        }
+    }
+
+    #[test]
+    fn curve_interpolation() {
+        let curve = BrightnessCurve::default();
+        let curve = curve.points();
+        let brightness: Vec<_> = [0, 15, 30, 50, 69, 70, 100]
+            .into_iter()
+            .map(|light| brightness(curve, light))
+            .collect();
+        assert_eq!(brightness, [10, 30, 50, 75, 98, 100, 100]);
+        assert_eq!(super::brightness(&[(20, 40)], 0), 40);
+        assert_eq!(super::brightness(&[(20, 40)], 90), 40);
+        assert_eq!(super::brightness(&[], 50), 100);
+    }
+
+    #[test]
+    fn first_reading_sets_the_brightness() {
+        let mut auto = filter();
+        assert_eq!(auto.light(), None);
+        assert_eq!(feed(&mut auto, 50, 1), [75]);
+        assert_eq!(auto.light(), Some(50));
+        assert_eq!(feed(&mut auto, 50, 10), []);
+    }
+
+    #[test]
+    fn smooths_steps() {
+        let mut auto = filter();
+        feed(&mut auto, 0, 1);
+        // An eighth of the way per reading
+        assert_eq!(feed(&mut auto, 30, 1), []);
+        assert_eq!(auto.light(), Some(4));
+        let changes = feed(&mut auto, 30, 100);
+        assert!(changes.len() > 1 && changes.is_sorted());
+        // The brightness stopped following within the hysteresis of the light it settled at
+        assert_eq!(changes.last(), Some(&46));
+        assert_eq!(auto.light(), Some(30));
+        // A single dark reading among bright ones hardly moves the brightness
+        let mut auto = filter();
+        feed(&mut auto, 50, 1);
+        feed(&mut auto, 0, 1);
+        assert_eq!(auto.light(), Some(44));
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut auto = filter();
+        feed(&mut auto, 40, 1);
+        // Smaller changes are ignored however long they last
+        assert_eq!(feed(&mut auto, 44, 100), []);
+        assert_eq!(feed(&mut auto, 36, 100), []);
+        // Followed once the light moved past the hysteresis, at 45
+        assert_eq!(feed(&mut auto, 46, 100), [68]);
+        assert_eq!(feed(&mut auto, 42, 100), []);
+    }
+
+    #[test]
+    fn ends_are_always_reached() {
+        let mut auto = filter();
+        feed(&mut auto, 3, 1);
+        assert_eq!(feed(&mut auto, 0, 100), [10]);
+        assert_eq!(auto.light(), Some(0));
+        let mut auto = filter();
+        feed(&mut auto, 97, 1);
+        feed(&mut auto, 100, 100);
+        assert_eq!(auto.light(), Some(100));
+    }
+}
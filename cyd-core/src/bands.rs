@@ -0,0 +1,494 @@
+//! Ring of big-endian RGB565 pixel bands handed from a decoding core to the display core.
+//!
+//! Single producer, single consumer and lock-free, the producer only writes `head`
+//! and the consumer only writes `tail`. Independent of the target, so both ends can
+//! run on host threads.
+
+use core::{
+    cell::UnsafeCell,
+    convert::Infallible,
+    hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use crate::blit::{BlitTarget, pack, unpack};
+use embedded_graphics::{Pixel, pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+
+/// Pixels of a rectangular area, or the end of a frame
+pub struct Band<const PIXELS: usize> {
+    pub area: Rectangle,
+    /// Marks the end of a frame, `area` is empty
+    pub end: bool,
+    pixels: [[u8; 2]; PIXELS],
+}
+
+impl<const PIXELS: usize> Band<PIXELS> {
+    const fn new() -> Self {
+        Self {
+            area: Rectangle::zero(),
+            end: false,
+            pixels: [[0; 2]; PIXELS],
+        }
+    }
+
+    /// Packed pixels of `area` in row-major order
+    pub fn bytes(&self) -> &[u8] {
+        self.pixels[..(self.area.size.width * self.area.size.height) as usize].as_flattened()
+    }
+}
+
+/// `N` bands of up to `PIXELS` pixels each
+pub struct BandRing<const N: usize, const PIXELS: usize> {
+    bands: [UnsafeCell<Band<PIXELS>>; N],
+    // Number of bands committed by the producer
+    head: AtomicUsize,
+    // Number of bands released by the consumer
+    tail: AtomicUsize,
+    split: AtomicBool,
+}
+
+// Bands are only accessed by the end that owns them according to `head` and `tail`
+unsafe impl<const N: usize, const PIXELS: usize> Sync for BandRing<N, PIXELS> {}
+
+impl<const N: usize, const PIXELS: usize> Default for BandRing<N, PIXELS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const PIXELS: usize> BandRing<N, PIXELS> {
+    pub const fn new() -> Self {
+        Self {
+            bands: [const { UnsafeCell::new(Band::new()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            split: AtomicBool::new(false),
+        }
+    }
+
+    /// The producer and consumer ends, only the first call returns them
+    pub fn split(&self) -> Option<(Producer<'_, N, PIXELS>, Consumer<'_, N, PIXELS>)> {
+        if self.split.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        Some((Producer { ring: self }, Consumer { ring: self }))
+    }
+}
+
+/// Fills and commits bands
+pub struct Producer<'a, const N: usize, const PIXELS: usize> {
+    ring: &'a BandRing<N, PIXELS>,
+}
+
+impl<const N: usize, const PIXELS: usize> Producer<'_, N, PIXELS> {
+    /// The next band to fill, `None` while the ring is full
+    pub fn try_grant(&mut self) -> Option<&mut Band<PIXELS>> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) == N {
+            return None;
+        }
+        // The consumer does not access this band until it is committed
+        Some(unsafe { &mut *self.ring.bands[head % N].get() })
+    }
+
+    /// The next band to fill, spins while the ring is full
+    pub fn grant(&mut self) -> &mut Band<PIXELS> {
+        while self.try_grant().is_none() {
+            hint::spin_loop();
+        }
+        self.try_grant().unwrap()
+    }
+
+    /// Hand the granted band to the consumer
+    pub fn commit(&mut self) {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        self.ring
+            .head
+            .store(head.wrapping_add(1), Ordering::Release);
+    }
+}
+
+/// Reads and releases bands
+pub struct Consumer<'a, const N: usize, const PIXELS: usize> {
+    ring: &'a BandRing<N, PIXELS>,
+}
+
+impl<const N: usize, const PIXELS: usize> Consumer<'_, N, PIXELS> {
+    /// The oldest committed band, `None` while the ring is empty
+    pub fn try_peek(&mut self) -> Option<&Band<PIXELS>> {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = self.ring.head.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        // The producer does not access this band until it is released
+        Some(unsafe { &*self.ring.bands[tail % N].get() })
+    }
+
+    /// The oldest committed band, spins while the ring is empty
+    pub fn peek(&mut self) -> &Band<PIXELS> {
+        while self.try_peek().is_none() {
+            hint::spin_loop();
+        }
+        self.try_peek().unwrap()
+    }
+
+    /// Hand the peeked band back to the producer
+    pub fn release(&mut self) {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        self.ring
+            .tail
+            .store(tail.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Draw bands to `target` up to the end of the frame.
+    /// All bands of the frame are consumed even if drawing fails, the first error is returned.
+    pub fn draw_frame<T: BlitTarget>(&mut self, target: &mut T) -> Result<(), T::Error> {
+        let mut result = Ok(());
+        while !self.draw_ready(target, &mut result) {
+            hint::spin_loop();
+        }
+        result
+    }
+
+    /// Draw the bands committed so far to `target` without waiting for more,
+    /// returns whether the end of the frame was reached.
+    /// Bands are consumed even if drawing fails, the first error is kept in `result`.
+    pub fn draw_ready<T: BlitTarget>(
+        &mut self,
+        target: &mut T,
+        result: &mut Result<(), T::Error>,
+    ) -> bool {
+        while let Some(band) = self.try_peek() {
+            if band.end {
+                self.release();
+                return true;
+            }
+            if result.is_ok() {
+                *result = target.blit(&band.area, band.bytes());
+            }
+            self.release();
+        }
+        false
+    }
+
+    /// Consume bands up to the end of the frame without drawing them
+    pub fn discard_frame(&mut self) {
+        loop {
+            let end = self.peek().end;
+            self.release();
+            if end {
+                return;
+            }
+        }
+    }
+}
+
+/// Draw target of `size` that splits drawing into bands
+pub struct BandWriter<'a, const N: usize, const PIXELS: usize> {
+    producer: Producer<'a, N, PIXELS>,
+    size: Size,
+}
+
+impl<'a, const N: usize, const PIXELS: usize> BandWriter<'a, N, PIXELS> {
+    pub fn new(producer: Producer<'a, N, PIXELS>, size: Size) -> Self {
+        Self { producer, size }
+    }
+
+    /// Mark the end of the frame
+    pub fn finish(&mut self) {
+        let band = self.producer.grant();
+        band.area = Rectangle::zero();
+        band.end = true;
+        self.producer.commit();
+    }
+
+    fn push(&mut self, area: Rectangle, pixels: &mut impl Iterator<Item = [u8; 2]>) {
+        let band = self.producer.grant();
+        band.area = area;
+        band.end = false;
+        let len = (area.size.width * area.size.height) as usize;
+        for (pixel, packed) in band.pixels[..len].iter_mut().zip(pixels) {
+            *pixel = packed;
+        }
+        self.producer.commit();
+    }
+
+    /// Whether `area` can be split into bands of whole rows
+    fn fits(&self, area: &Rectangle) -> bool {
+        area.size.width as usize <= PIXELS && self.bounding_box().intersection(area) == *area
+    }
+
+    /// Split `area` into bands of as many rows as fit
+    fn fill(&mut self, area: &Rectangle, mut pixels: impl Iterator<Item = [u8; 2]>) {
+        let rows = (PIXELS / area.size.width as usize) as u32;
+        let mut y = 0;
+        while y < area.size.height {
+            let height = rows.min(area.size.height - y);
+            let band = Rectangle::new(
+                area.top_left + Point::new(0, y as i32),
+                Size::new(area.size.width, height),
+            );
+            self.push(band, &mut pixels);
+            y += height;
+        }
+    }
+}
+
+impl<const N: usize, const PIXELS: usize> OriginDimensions for BandWriter<'_, N, PIXELS> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl<const N: usize, const PIXELS: usize> DrawTarget for BandWriter<'_, N, PIXELS> {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    // Single pixels are slow, drawing images uses `fill_contiguous`
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let bounding_box = self.bounding_box();
+        for Pixel(point, color) in pixels {
+            if bounding_box.contains(point) {
+                self.push(
+                    Rectangle::new(point, Size::new(1, 1)),
+                    &mut core::iter::once(pack(color)),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+        if !self.fits(area) {
+            return self.draw_iter(
+                area.points()
+                    .zip(colors)
+                    .map(|(point, color)| Pixel(point, color)),
+            );
+        }
+        self.fill(area, colors.into_iter().map(pack));
+        Ok(())
+    }
+}
+
+impl<const N: usize, const PIXELS: usize> BlitTarget for BandWriter<'_, N, PIXELS> {
+    fn blit(&mut self, area: &Rectangle, bytes: &[u8]) -> Result<(), Self::Error> {
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+        if !self.fits(area) {
+            return self.draw_iter(
+                area.points()
+                    .zip(unpack(bytes))
+                    .map(|(point, color)| Pixel(point, color)),
+            );
+        }
+        self.fill(
+            area,
+            bytes.chunks_exact(2).map(|pixel| [pixel[0], pixel[1]]),
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::mock_display::MockDisplay;
+    use std::thread;
+
+    /// Mock display drawing packed pixels without a fast path
+    struct Mock(MockDisplay<Rgb565>);
+
+    impl Mock {
+        fn new() -> Self {
+            let mut display = MockDisplay::new();
+            display.set_allow_overdraw(true);
+            Self(display)
+        }
+    }
+
+    impl OriginDimensions for Mock {
+        fn size(&self) -> Size {
+            self.0.size()
+        }
+    }
+
+    impl DrawTarget for Mock {
+        type Color = Rgb565;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            self.0.draw_iter(pixels)
+        }
+    }
+
+    impl BlitTarget for Mock {
+        fn blit(&mut self, area: &Rectangle, bytes: &[u8]) -> Result<(), Self::Error> {
+            crate::blit::fill_bytes(self, area, bytes)
+        }
+    }
+
+    fn color(i: u32, frame: u32) -> Rgb565 {
+        Rgb565::new(((i + frame) % 32) as u8, (i % 64) as u8, (frame % 32) as u8)
+    }
+
+    #[test]
+    fn splits_once() {
+        let ring = BandRing::<2, 4>::new();
+        assert!(ring.split().is_some());
+        assert!(ring.split().is_none());
+    }
+
+    #[test]
+    fn bands_in_order_until_full() {
+        let ring = BandRing::<3, 4>::new();
+        let (mut producer, mut consumer) = ring.split().unwrap();
+        assert!(consumer.try_peek().is_none());
+        for round in 0..3 {
+            for i in 0..3 {
+                let band = producer.try_grant().expect("room for a band");
+                band.area = Rectangle::new(Point::new(round, i), Size::new(1, 1));
+                producer.commit();
+            }
+            assert!(producer.try_grant().is_none());
+            for i in 0..3 {
+                let band = consumer.try_peek().expect("a committed band");
+                assert_eq!(band.area.top_left, Point::new(round, i));
+                consumer.release();
+            }
+            assert!(consumer.try_peek().is_none());
+        }
+    }
+
+    #[test]
+    fn writer_splits_into_whole_rows() {
+        let ring = BandRing::<8, 10>::new();
+        let (producer, mut consumer) = ring.split().unwrap();
+        let mut writer = BandWriter::new(producer, Size::new(4, 8));
+        let area = Rectangle::new(Point::new(0, 1), Size::new(4, 7));
+        writer
+            .fill_contiguous(&area, (0..28).map(|i| color(i, 0)))
+            .unwrap();
+        writer.finish();
+        let mut areas = Vec::new();
+        loop {
+            let band = consumer.peek();
+            if band.end {
+                break;
+            }
+            areas.push(band.area);
+            assert_eq!(
+                band.bytes().len(),
+                (band.area.size.width * band.area.size.height * 2) as usize
+            );
+            consumer.release();
+        }
+        assert_eq!(
+            areas,
+            [
+                Rectangle::new(Point::new(0, 1), Size::new(4, 2)),
+                Rectangle::new(Point::new(0, 3), Size::new(4, 2)),
+                Rectangle::new(Point::new(0, 5), Size::new(4, 2)),
+                Rectangle::new(Point::new(0, 7), Size::new(4, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn areas_outside_are_clipped() {
+        let ring = BandRing::<64, 8>::new();
+        let (producer, mut consumer) = ring.split().unwrap();
+        let mut writer = BandWriter::new(producer, Size::new(4, 4));
+        let area = Rectangle::new(Point::new(-1, 2), Size::new(3, 3));
+        let colors: Vec<_> = (0..9).map(|i| color(i, 1)).collect();
+        writer
+            .fill_contiguous(&area, colors.iter().copied())
+            .unwrap();
+        writer.finish();
+        let mut display = Mock::new();
+        consumer.draw_frame(&mut display).unwrap();
+        let mut expected = Mock::new();
+        expected
+            .clipped(&Rectangle::new(Point::zero(), Size::new(4, 4)))
+            .fill_contiguous(&area, colors)
+            .unwrap();
+        display.0.assert_eq(&expected.0);
+    }
+
+    #[test]
+    fn frames_across_threads() {
+        const FRAMES: u32 = 50;
+        const SIZE: Size = Size::new(32, 24);
+        let ring = BandRing::<3, 64>::new();
+        let (producer, mut consumer) = ring.split().unwrap();
+        let area = Rectangle::new(Point::zero(), SIZE);
+        let pixels = SIZE.width * SIZE.height;
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut writer = BandWriter::new(producer, SIZE);
+                for frame in 0..FRAMES {
+                    let colors = (0..pixels).map(|i| color(i, frame));
+                    if frame % 2 == 0 {
+                        writer.fill_contiguous(&area, colors).unwrap();
+                    } else {
+                        let bytes: Vec<u8> = colors.flat_map(pack).collect();
+                        writer.blit(&area, &bytes).unwrap();
+                    }
+                    writer.finish();
+                }
+            });
+            for frame in 0..FRAMES {
+                let mut display = Mock::new();
+                if frame % 3 == 0 {
+                    let mut result = Ok(());
+                    while !consumer.draw_ready(&mut display, &mut result) {}
+                    result.unwrap();
+                } else {
+                    consumer.draw_frame(&mut display).unwrap();
+                }
+                for (i, point) in area.points().enumerate() {
+                    assert_eq!(display.0.get_pixel(point), Some(color(i as u32, frame)));
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn discards_frames() {
+        let ring = BandRing::<2, 16>::new();
+        let (producer, mut consumer) = ring.split().unwrap();
+        let size = Size::new(4, 16);
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut writer = BandWriter::new(producer, size);
+                for frame in 0..2 {
+                    let area = Rectangle::new(Point::zero(), size);
+                    writer
+                        .fill_contiguous(&area, (0..64).map(|i| color(i, frame)))
+                        .unwrap();
+                    writer.finish();
+                }
+            });
+            consumer.discard_frame();
+            let mut display = Mock::new();
+            consumer.draw_frame(&mut display).unwrap();
+            assert_eq!(display.0.get_pixel(Point::new(3, 15)), Some(color(63, 1)));
+        });
+        assert!(consumer.try_peek().is_none());
+    }
+}
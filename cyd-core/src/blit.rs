@@ -0,0 +1,242 @@
+//! Drawing pre-packed big-endian RGB565 pixels.
+//!
+//! Decoders pack pixels into the display's byte order once, targets with a fast path
+//! send the bytes as they are instead of converting every pixel through an iterator.
+
+use embedded_graphics::{
+    pixelcolor::{Rgb565, raw::RawU16},
+    prelude::*,
+    primitives::Rectangle,
+};
+use mipidsi::interface::Interface;
+
+// MIPI DCS commands to write an area of pixels
+const SET_COLUMN_ADDRESS: u8 = 0x2A;
+const SET_PAGE_ADDRESS: u8 = 0x2B;
+const WRITE_MEMORY_START: u8 = 0x2C;
+
+/// Draw target that accepts packed pixels
+pub trait BlitTarget: DrawTarget<Color = Rgb565> {
+    /// Draw `area` from big-endian RGB565 `bytes` in row-major order
+    fn blit(&mut self, area: &Rectangle, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Image that can be drawn as packed pixels
+pub trait Blit {
+    /// Draw with the top left corner at `top_left`
+    fn blit<T: BlitTarget>(&self, target: &mut T, top_left: Point) -> Result<(), T::Error>;
+}
+
+/// Pack `color` as big-endian RGB565
+#[inline(always)]
+pub fn pack(color: Rgb565) -> [u8; 2] {
+    RawU16::from(color).into_inner().to_be_bytes()
+}
+
+/// Colors of big-endian RGB565 `bytes`
+pub fn unpack(bytes: &[u8]) -> impl Iterator<Item = Rgb565> + '_ {
+    bytes
+        .chunks_exact(2)
+        .map(|pixel| RawU16::new(u16::from_be_bytes([pixel[0], pixel[1]])).into())
+}
+
+/// Draw `bytes` with `fill_contiguous`, for targets without a fast path
+pub fn fill_bytes<D>(target: &mut D, area: &Rectangle, bytes: &[u8]) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    target.fill_contiguous(area, unpack(bytes))
+}
+
+/// Start writing `area` of a MIPI DCS display, its packed pixels follow as data.
+/// Sends nothing for an empty area.
+///
+/// Only writes pixels like `mipidsi::Display::set_pixels`, which leaves the display state
+/// unchanged. The display applies the orientation, address offsets are not added.
+pub fn write_memory_start<DI>(interface: &mut DI, area: &Rectangle) -> Result<(), DI::Error>
+where
+    DI: Interface<Word = u8>,
+{
+    let Some(bottom_right) = area.bottom_right() else {
+        return Ok(());
+    };
+    let window = |start: i32, end: i32| {
+        let [start_high, start_low] = (start as u16).to_be_bytes();
+        let [end_high, end_low] = (end as u16).to_be_bytes();
+        [start_high, start_low, end_high, end_low]
+    };
+    interface.send_command(SET_COLUMN_ADDRESS, &window(area.top_left.x, bottom_right.x))?;
+    interface.send_command(SET_PAGE_ADDRESS, &window(area.top_left.y, bottom_right.y))?;
+    interface.send_command(WRITE_MEMORY_START, &[])
+}
+
+/// Target that only draws rows above `end`, the rest of every area is dropped
+pub struct RowsAbove<'a, T> {
+    target: &'a mut T,
+    end: i32,
+}
+
+impl<'a, T: BlitTarget> RowsAbove<'a, T> {
+    pub fn new(target: &'a mut T, end: i32) -> Self {
+        Self { target, end }
+    }
+
+    /// Rows of `area` above `end`
+    fn visible(&self, area: &Rectangle) -> Rectangle {
+        let rows = (self.end - area.top_left.y).clamp(0, area.size.height as i32);
+        Rectangle::new(area.top_left, Size::new(area.size.width, rows as u32))
+    }
+}
+
+impl<T: BlitTarget> Dimensions for RowsAbove<'_, T> {
+    fn bounding_box(&self) -> Rectangle {
+        self.target.bounding_box()
+    }
+}
+
+impl<T: BlitTarget> DrawTarget for RowsAbove<'_, T> {
+    type Color = Rgb565;
+    type Error = T::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let end = self.end;
+        self.target
+            .draw_iter(pixels.into_iter().filter(|Pixel(point, _)| point.y < end))
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        // Rows are in order, so the visible ones come first
+        let visible = self.visible(area);
+        let pixels = (visible.size.width * visible.size.height) as usize;
+        self.target
+            .fill_contiguous(&visible, colors.into_iter().take(pixels))
+    }
+}
+
+impl<T: BlitTarget> BlitTarget for RowsAbove<'_, T> {
+    fn blit(&mut self, area: &Rectangle, bytes: &[u8]) -> Result<(), Self::Error> {
+        let visible = self.visible(area);
+        let len = (visible.size.width * visible.size.height) as usize * 2;
+        self.target.blit(&visible, &bytes[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, convert::Infallible, rc::Rc};
+
+    use embedded_hal::{
+        delay::DelayNs,
+        digital::{ErrorType as DigitalErrorType, OutputPin},
+        spi::{ErrorType as SpiErrorType, Operation, SpiDevice},
+    };
+    use mipidsi::{Builder, interface::SpiInterface, models::ILI9341Rgb565};
+
+    use super::*;
+
+    /// Records the bytes sent to the display
+    #[derive(Clone, Default)]
+    struct Bus(Rc<RefCell<Sent>>);
+
+    #[derive(Default)]
+    struct Sent {
+        dc: bool,
+        // Each with whether DC was high
+        bytes: Vec<(bool, u8)>,
+    }
+
+    impl Bus {
+        fn write(&self, data: &[u8]) {
+            let sent = &mut *self.0.borrow_mut();
+            sent.bytes.extend(data.iter().map(|&byte| (sent.dc, byte)));
+        }
+
+        fn take(&self) -> Vec<(bool, u8)> {
+            std::mem::take(&mut self.0.borrow_mut().bytes)
+        }
+    }
+
+    impl SpiErrorType for Bus {
+        type Error = Infallible;
+    }
+
+    impl SpiDevice for Bus {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+            for operation in operations {
+                if let Operation::Write(data) = operation {
+                    Bus::write(self, data);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct Dc(Bus);
+
+    impl DigitalErrorType for Dc {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Dc {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.0.borrow_mut().dc = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.0.borrow_mut().dc = true;
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn packed_pixels_are_sent_like_colors() {
+        let bus = Bus::default();
+        let mut buffer = [0; 64];
+        let interface = SpiInterface::new(bus.clone(), Dc(bus.clone()), &mut buffer);
+        let mut display = Builder::new(ILI9341Rgb565, interface)
+            .init(&mut NoDelay)
+            .unwrap();
+        let area = Rectangle::new(Point::new(3, 300), Size::new(7, 5));
+        let colors: Vec<Rgb565> = (0..35u16)
+            .map(|i| RawU16::new(i.wrapping_mul(0x9E37)).into())
+            .collect();
+        bus.take();
+
+        display
+            .fill_contiguous(&area, colors.iter().copied())
+            .unwrap();
+        let drawn = bus.take();
+        write_memory_start(unsafe { display.dcs() }, &area).unwrap();
+        let packed: Vec<u8> = colors.iter().flat_map(|&color| pack(color)).collect();
+        bus.write(&packed);
+        assert_eq!(bus.take(), drawn);
+        assert!(unpack(&packed).eq(colors));
+    }
+
+    #[test]
+    fn nothing_is_sent_for_empty_areas() {
+        let bus = Bus::default();
+        let mut buffer = [0; 64];
+        let interface = SpiInterface::new(bus.clone(), Dc(bus.clone()), &mut buffer);
+        let mut display = Builder::new(ILI9341Rgb565, interface)
+            .init(&mut NoDelay)
+            .unwrap();
+        bus.take();
+        let area = Rectangle::new(Point::new(3, 4), Size::new(0, 5));
+        write_memory_start(unsafe { display.dcs() }, &area).unwrap();
+        assert!(bus.take().is_empty());
+    }
+}
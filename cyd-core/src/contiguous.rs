@@ -0,0 +1,491 @@
+//! Read files stored in consecutive clusters straight from the block device.
+//!
+//! `VolumeManager` reads files one block at a time through its block cache. A file
+//! whose cluster chain is contiguous occupies a single run of blocks, so aligned reads
+//! can be issued as one multi-block read (CMD18) into the caller's buffer.
+
+use core::{mem, slice};
+
+use embedded_io::{ErrorType, Read, Seek, SeekFrom};
+use embedded_sdmmc::{Block, BlockDevice, BlockIdx, DirEntry, Error};
+
+// MBR, FAT16/FAT32 boot sector and directory entry offsets
+const PARTITION_TABLE: usize = 446;
+const PARTITION_TYPE: usize = 4;
+const PARTITION_START: usize = 8;
+const SIGNATURE: usize = 510;
+const BYTES_PER_SECTOR: usize = 11;
+const SECTORS_PER_CLUSTER: usize = 13;
+const RESERVED_SECTORS: usize = 14;
+const NUM_FATS: usize = 16;
+const ROOT_ENTRIES: usize = 17;
+const TOTAL_SECTORS_16: usize = 19;
+const FAT_SIZE_16: usize = 22;
+const TOTAL_SECTORS_32: usize = 32;
+const FAT_SIZE_32: usize = 36;
+const ENTRY_CLUSTER_HIGH: usize = 20;
+const ENTRY_CLUSTER_LOW: usize = 26;
+const ENTRY_SIZE: usize = 28;
+
+// Clusters below are FAT12, which embedded-sdmmc does not support either
+const MIN_FAT16_CLUSTERS: u32 = 4085;
+const MIN_FAT32_CLUSTERS: u32 = 65525;
+// FAT16 and FAT32 partition types that embedded-sdmmc mounts
+const FAT_PARTITION_TYPES: [u8; 5] = [0x04, 0x06, 0x0B, 0x0C, 0x0E];
+
+/// Where clusters of the first partition are on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatLayout {
+    fat_start: u32,
+    data_start: u32,
+    blocks_per_cluster: u32,
+    fat32: bool,
+}
+
+impl FatLayout {
+    /// Read the layout from the boot sector of the first partition,
+    /// `None` if it isn't FAT16 or FAT32 with 512 byte sectors
+    pub fn read<D: BlockDevice>(device: &D) -> Result<Option<Self>, Error<D::Error>> {
+        let mut block = [Block::new()];
+        device
+            .read(&mut block, BlockIdx(0))
+            .map_err(Error::DeviceError)?;
+        let mbr = &block[0][..];
+        if !signed(mbr) || !FAT_PARTITION_TYPES.contains(&mbr[PARTITION_TABLE + PARTITION_TYPE]) {
+            return Ok(None);
+        }
+        let partition_start = u32_at(mbr, PARTITION_TABLE + PARTITION_START);
+        device
+            .read(&mut block, BlockIdx(partition_start))
+            .map_err(Error::DeviceError)?;
+        let boot = &block[0][..];
+
+        let blocks_per_cluster = boot[SECTORS_PER_CLUSTER] as u32;
+        if !signed(boot)
+            || u16_at(boot, BYTES_PER_SECTOR) as usize != Block::LEN
+            || blocks_per_cluster == 0
+        {
+            return Ok(None);
+        }
+        let reserved = u16_at(boot, RESERVED_SECTORS) as u32;
+        let root_blocks = (u16_at(boot, ROOT_ENTRIES) as u32 * 32).div_ceil(Block::LEN as u32);
+        let fat_size = match u16_at(boot, FAT_SIZE_16) {
+            0 => u32_at(boot, FAT_SIZE_32),
+            size => size as u32,
+        };
+        let total = match u16_at(boot, TOTAL_SECTORS_16) {
+            0 => u32_at(boot, TOTAL_SECTORS_32),
+            total => total as u32,
+        };
+        let system_blocks = reserved + boot[NUM_FATS] as u32 * fat_size + root_blocks;
+        let clusters = total.saturating_sub(system_blocks) / blocks_per_cluster;
+        if clusters < MIN_FAT16_CLUSTERS {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            fat_start: partition_start + reserved,
+            data_start: partition_start + system_blocks,
+            blocks_per_cluster,
+            fat32: clusters >= MIN_FAT32_CLUSTERS,
+        }))
+    }
+
+    /// First block of the file of `entry` if all its clusters are consecutive
+    pub fn contiguous<D: BlockDevice>(
+        &self,
+        device: &D,
+        entry: &DirEntry,
+    ) -> Result<Option<u32>, Error<D::Error>> {
+        self.contiguous_at(device, entry.entry_block, entry.entry_offset as usize)
+    }
+
+    /// First block of the file of the directory entry at `offset` in `entry_block`
+    /// if all its clusters are consecutive
+    fn contiguous_at<D: BlockDevice>(
+        &self,
+        device: &D,
+        entry_block: BlockIdx,
+        offset: usize,
+    ) -> Result<Option<u32>, Error<D::Error>> {
+        let mut block = [Block::new()];
+        device
+            .read(&mut block, entry_block)
+            .map_err(Error::DeviceError)?;
+        let raw = &block[0][offset..];
+        let first =
+            (u16_at(raw, ENTRY_CLUSTER_HIGH) as u32) << 16 | u16_at(raw, ENTRY_CLUSTER_LOW) as u32;
+        let size = u32_at(raw, ENTRY_SIZE);
+        if size == 0 || first < 2 {
+            return Ok(None);
+        }
+
+        let cluster_size = self.blocks_per_cluster * Block::LEN as u32;
+        let clusters = size.div_ceil(cluster_size);
+        let entry_size = if self.fat32 { 4 } else { 2 };
+        let mut cached = None;
+        for cluster in first..first + clusters - 1 {
+            let offset = cluster * entry_size;
+            let fat_block = self.fat_start + offset / Block::LEN as u32;
+            if cached != Some(fat_block) {
+                device
+                    .read(&mut block, BlockIdx(fat_block))
+                    .map_err(Error::DeviceError)?;
+                cached = Some(fat_block);
+            }
+            let offset = (offset % Block::LEN as u32) as usize;
+            let next = if self.fat32 {
+                u32_at(&block[0][..], offset) & 0x0FFF_FFFF
+            } else {
+                u16_at(&block[0][..], offset) as u32
+            };
+            if next != cluster + 1 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(
+            self.data_start + (first - 2) * self.blocks_per_cluster,
+        ))
+    }
+}
+
+/// Whether a boot record ends with the boot signature
+fn signed(boot: &[u8]) -> bool {
+    boot[SIGNATURE..SIGNATURE + 2] == [0x55, 0xAA]
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Read-only file in consecutive blocks starting at `first_block`
+pub struct ContiguousFile<D> {
+    device: D,
+    first_block: u32,
+    length: u32,
+    offset: u32,
+    // Last partially read block
+    block: Block,
+    cached: Option<u32>,
+}
+
+impl<D: BlockDevice> ContiguousFile<D> {
+    pub fn new(device: D, first_block: u32, length: u32) -> Self {
+        Self {
+            device,
+            first_block,
+            length,
+            offset: 0,
+            block: Block::new(),
+            cached: None,
+        }
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+}
+
+impl<D: BlockDevice> ErrorType for ContiguousFile<D> {
+    type Error = Error<D::Error>;
+}
+
+impl<D: BlockDevice> Read for ContiguousFile<D> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = buf.len().min((self.length - self.offset) as usize);
+        let block = self.first_block + self.offset / Block::LEN as u32;
+        let block_offset = self.offset as usize % Block::LEN;
+        let read = if block_offset == 0 && len >= Block::LEN {
+            // Whole blocks go straight into `buf` with a single multi-block read
+            let blocks = as_blocks(&mut buf[..len - len % Block::LEN]);
+            self.device
+                .read(blocks, BlockIdx(block))
+                .map_err(Error::DeviceError)?;
+            blocks.len() * Block::LEN
+        } else {
+            if self.cached != Some(block) {
+                self.device
+                    .read(slice::from_mut(&mut self.block), BlockIdx(block))
+                    .map_err(Error::DeviceError)?;
+                self.cached = Some(block);
+            }
+            let len = len.min(Block::LEN - block_offset);
+            buf[..len].copy_from_slice(&self.block[block_offset..block_offset + len]);
+            len
+        };
+        self.offset += read as u32;
+        Ok(read)
+    }
+}
+
+impl<D: BlockDevice> Seek for ContiguousFile<D> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let offset = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.length as i64 + offset,
+            SeekFrom::Current(offset) => self.offset as i64 + offset,
+        };
+        if !(0..=self.length as i64).contains(&offset) {
+            return Err(Error::InvalidOffset);
+        }
+        self.offset = offset as u32;
+        Ok(self.offset as u64)
+    }
+}
+
+/// View whole blocks of `buf` as blocks to read into
+fn as_blocks(buf: &mut [u8]) -> &mut [Block] {
+    const {
+        assert!(mem::size_of::<Block>() == Block::LEN && mem::align_of::<Block>() == 1);
+    }
+    // A block is just its bytes, any contents are valid
+    unsafe { slice::from_raw_parts_mut(buf.as_mut_ptr().cast(), buf.len() / Block::LEN) }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    use embedded_sdmmc::{BlockCount, Mode, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+
+    use super::*;
+
+    const PARTITION: u32 = 8;
+    const RESERVED: u32 = 1;
+    const FAT_BLOCKS: u32 = 20;
+    const ROOT_ENTRIES: u32 = 512;
+    const CLUSTERS: u32 = 5000;
+    const ROOT_START: u32 = PARTITION + RESERVED + 2 * FAT_BLOCKS;
+    const DATA_START: u32 = ROOT_START + ROOT_ENTRIES * 32 / Block::LEN as u32;
+    const ENTRY_ATTRIBUTES: usize = 11;
+    const ARCHIVE: u8 = 0x20;
+
+    /// Sparse image in memory, unwritten blocks read as zeros
+    #[derive(Default)]
+    pub(crate) struct Image {
+        blocks: RefCell<BTreeMap<u32, Block>>,
+        reads: RefCell<Vec<(u32, usize)>>,
+    }
+
+    impl Image {
+        /// FAT16 partition with a root directory and no files
+        pub(crate) fn fat16(partition_type: u8) -> Self {
+            let mut image = Self::default();
+            let mbr = image.block(0);
+            mbr[PARTITION_TABLE + PARTITION_TYPE] = partition_type;
+            mbr[PARTITION_TABLE + PARTITION_START..][..4].copy_from_slice(&PARTITION.to_le_bytes());
+            mbr[SIGNATURE..][..2].copy_from_slice(&[0x55, 0xAA]);
+            let boot = image.block(PARTITION);
+            boot[BYTES_PER_SECTOR..][..2].copy_from_slice(&(Block::LEN as u16).to_le_bytes());
+            boot[SECTORS_PER_CLUSTER] = 1;
+            boot[RESERVED_SECTORS..][..2].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+            boot[NUM_FATS] = 2;
+            boot[super::ROOT_ENTRIES..][..2].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+            let total = (DATA_START - PARTITION + CLUSTERS) as u16;
+            boot[TOTAL_SECTORS_16..][..2].copy_from_slice(&total.to_le_bytes());
+            boot[FAT_SIZE_16..][..2].copy_from_slice(&(FAT_BLOCKS as u16).to_le_bytes());
+            boot[SIGNATURE..][..2].copy_from_slice(&[0x55, 0xAA]);
+            image
+        }
+
+        fn block(&mut self, index: u32) -> &mut Block {
+            self.blocks.get_mut().entry(index).or_default()
+        }
+
+        /// Add a root directory entry at `offset` for `contents` in `clusters`
+        fn add_file(&mut self, offset: usize, name: &[u8; 11], clusters: &[u32], contents: &[u8]) {
+            let entry = &mut self.block(ROOT_START)[offset..offset + 32];
+            entry[..11].copy_from_slice(name);
+            entry[ENTRY_ATTRIBUTES] = ARCHIVE;
+            entry[ENTRY_CLUSTER_LOW..][..2].copy_from_slice(&(clusters[0] as u16).to_le_bytes());
+            entry[ENTRY_SIZE..][..4].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+            let ends = clusters.iter().skip(1).copied().chain([0xFFFF]);
+            for (&cluster, next) in clusters.iter().zip(ends) {
+                let offset = cluster as usize * 2;
+                let fat = self.block(PARTITION + RESERVED + (offset / Block::LEN) as u32);
+                fat[offset % Block::LEN..][..2].copy_from_slice(&(next as u16).to_le_bytes());
+            }
+            for (&cluster, chunk) in clusters.iter().zip(contents.chunks(Block::LEN)) {
+                self.block(DATA_START + cluster - 2)[..chunk.len()].copy_from_slice(chunk);
+            }
+        }
+    }
+
+    impl BlockDevice for Image {
+        type Error = ();
+
+        fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), ()> {
+            let start = start_block_idx.0;
+            self.reads.borrow_mut().push((start, blocks.len()));
+            for (index, block) in (start..).zip(blocks) {
+                *block = self
+                    .blocks
+                    .borrow()
+                    .get(&index)
+                    .cloned()
+                    .unwrap_or_default();
+            }
+            Ok(())
+        }
+
+        fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), ()> {
+            let mut image = self.blocks.borrow_mut();
+            for (index, block) in (start_block_idx.0..).zip(blocks) {
+                image.insert(index, block.clone());
+            }
+            Ok(())
+        }
+
+        fn num_blocks(&self) -> Result<BlockCount, ()> {
+            Ok(BlockCount(PARTITION + DATA_START + CLUSTERS))
+        }
+    }
+
+    // Shared with a volume manager, like the card in the player
+    impl BlockDevice for &Image {
+        type Error = ();
+
+        fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), ()> {
+            (**self).read(blocks, start_block_idx)
+        }
+
+        fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), ()> {
+            (**self).write(blocks, start_block_idx)
+        }
+
+        fn num_blocks(&self) -> Result<BlockCount, ()> {
+            (**self).num_blocks()
+        }
+    }
+
+    pub(crate) struct Epoch;
+
+    impl TimeSource for Epoch {
+        fn get_timestamp(&self) -> Timestamp {
+            Timestamp::from_fat(0, 0)
+        }
+    }
+
+    fn contents(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn layout() {
+        let layout = FatLayout::read(&Image::fat16(0x06)).unwrap();
+        assert_eq!(
+            layout,
+            Some(FatLayout {
+                fat_start: PARTITION + RESERVED,
+                data_start: DATA_START,
+                blocks_per_cluster: 1,
+                fat32: false,
+            })
+        );
+    }
+
+    #[test]
+    fn other_partitions_are_not_read() {
+        let mut unsigned = Image::fat16(0x06);
+        unsigned.block(0)[SIGNATURE] = 0;
+        let mut unsigned_boot = Image::fat16(0x06);
+        unsigned_boot.block(PARTITION)[SIGNATURE] = 0;
+        let mut fat12 = Image::fat16(0x06);
+        fat12.block(PARTITION)[TOTAL_SECTORS_16..][..2].copy_from_slice(&100u16.to_le_bytes());
+        for image in [
+            Image::default(),
+            unsigned,
+            unsigned_boot,
+            Image::fat16(0x83),
+            Image::fat16(0x07),
+            fat12,
+        ] {
+            assert_eq!(FatLayout::read(&image).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn reads_contiguous_files_as_stored() {
+        let mut image = Image::fat16(0x0E);
+        let contents = contents(4 * Block::LEN + 100);
+        image.add_file(64, b"VIDEO   MJP", &[10, 11, 12, 13, 14], &contents);
+        let layout = FatLayout::read(&image).unwrap().unwrap();
+        let first = layout.contiguous_at(&image, BlockIdx(ROOT_START), 64);
+        assert_eq!(first.unwrap(), Some(DATA_START + 8));
+
+        let mut file = ContiguousFile::new(image, DATA_START + 8, contents.len() as u32);
+        let mut read = Vec::new();
+        // Unaligned reads, a multi-block read and the partial end
+        for len in [1, 600, 2 * Block::LEN + 10, 3000] {
+            let mut buf = vec![0; len];
+            let n = file.read(&mut buf).unwrap();
+            read.extend_from_slice(&buf[..n]);
+        }
+        while read.len() < contents.len() {
+            let mut buf = [0; 100];
+            let n = file.read(&mut buf).unwrap();
+            read.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(read, contents);
+        assert_eq!(file.read(&mut [0; 10]).unwrap(), 0);
+        assert!(file.device.reads.borrow().contains(&(DATA_START + 9, 2)));
+
+        file.seek(SeekFrom::Start(1000)).unwrap();
+        let mut buf = [0; 50];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], contents[1000..1050]);
+        assert!(file.seek(SeekFrom::End(1)).is_err());
+    }
+
+    #[test]
+    fn fragmented_and_empty_files_are_not_contiguous() {
+        let mut image = Image::fat16(0x06);
+        image.add_file(0, b"FRAGMENTMJP", &[20, 21, 23], &contents(3 * Block::LEN));
+        image.add_file(32, b"EMPTY   MJP", &[30, 31], &[]);
+        let layout = FatLayout::read(&image).unwrap().unwrap();
+        for offset in [0, 32] {
+            let first = layout.contiguous_at(&image, BlockIdx(ROOT_START), offset);
+            assert_eq!(first.unwrap(), None);
+        }
+    }
+
+    fn read_at<F: Read + Seek>(file: &mut F, offset: u64, len: usize) -> Vec<u8> {
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        let mut buf = vec![0; len];
+        file.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn reads_what_the_volume_manager_reads() {
+        let mut image = Image::fat16(0x06);
+        let contents = contents(6 * Block::LEN + 300);
+        image.add_file(0, b"VIDEO   MJP", &[40, 41, 42, 43, 44, 45, 46], &contents);
+
+        let volume_manager = VolumeManager::new(&image, Epoch);
+        let volume = volume_manager.open_volume(VolumeIdx(0)).unwrap();
+        let root = volume.open_root_dir().unwrap();
+        let entry = root.find_directory_entry("VIDEO.MJP").unwrap();
+        let layout = FatLayout::read(&&image).unwrap().unwrap();
+        let first = layout.contiguous(&&image, &entry).unwrap().unwrap();
+        let mut fast = ContiguousFile::new(&image, first, entry.size);
+        let mut file = root.open_file_in_dir("VIDEO.MJP", Mode::ReadOnly).unwrap();
+
+        // Clusters are one block: aligned, across a cluster boundary, unaligned and
+        // multi-block, and the partial end
+        for (offset, len) in [
+            (0, Block::LEN),
+            (Block::LEN - 12, 100),
+            (2 * Block::LEN, 3 * Block::LEN),
+            (37, 4 * Block::LEN + 5),
+            (contents.len() - 100, 100),
+        ] {
+            let expected = &contents[offset..offset + len];
+            assert_eq!(read_at(&mut file, offset as u64, len), expected);
+            assert_eq!(read_at(&mut fast, offset as u64, len), expected);
+        }
+    }
+}
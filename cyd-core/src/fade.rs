@@ -0,0 +1,136 @@
+//! Brightness levels and fades, independent of the PWM.
+//!
+//! Brightness is in percent as perceived. The eye is more sensitive to changes in dim light,
+//! so the duty cycle grows with the square of the brightness and fades are linear in
+//! brightness.
+
+/// Brightness steps of the brightness up and down actions
+pub const LEVELS: [u8; 7] = [5, 10, 20, 35, 50, 75, 100];
+
+/// The next level brighter than `brightness`
+pub fn brighter(brightness: u8) -> u8 {
+    LEVELS
+        .into_iter()
+        .find(|&level| level > brightness)
+        .unwrap_or(100)
+}
+
+/// The next level dimmer than `brightness`, the lowest level doesn't turn the backlight off
+pub fn dimmer(brightness: u8) -> u8 {
+    LEVELS
+        .into_iter()
+        .rev()
+        .find(|&level| level < brightness)
+        .unwrap_or(LEVELS[0])
+}
+
+/// Duty cycle for `brightness`, where `max_duty` is fully on
+pub fn duty(brightness: u8, max_duty: u32) -> u32 {
+    let brightness = brightness.min(100) as u32;
+    // Any brightness above zero stays visible
+    (max_duty * brightness * brightness / (100 * 100)).max(brightness.min(1))
+}
+
+/// Fade between two brightness levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fade {
+    from: u8,
+    to: u8,
+    start_ms: u32,
+    duration_ms: u32,
+}
+
+impl Fade {
+    /// Fade from `from` to `to` starting at `start_ms`, taking `full_ms` from off to
+    /// fully on and proportionally less for smaller changes
+    pub fn new(from: u8, to: u8, start_ms: u32, full_ms: u32) -> Self {
+        Self {
+            from,
+            to,
+            start_ms,
+            duration_ms: full_ms * from.abs_diff(to) as u32 / 100,
+        }
+    }
+
+    /// Brightness the fade ends at
+    pub fn target(&self) -> u8 {
+        self.to
+    }
+
+    /// Brightness at `now_ms`
+    pub fn brightness(&self, now_ms: u32) -> u8 {
+        let elapsed = now_ms.wrapping_sub(self.start_ms);
+        if self.is_done(now_ms) {
+            return self.to;
+        }
+        let (from, to) = (self.from as i32, self.to as i32);
+        (from + (to - from) * elapsed as i32 / self.duration_ms as i32) as u8
+    }
+
+    pub fn is_done(&self, now_ms: u32) -> bool {
+        now_ms.wrapping_sub(self.start_ms) >= self.duration_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duty_grows_with_the_square() {
+        assert_eq!(duty(0, 8191), 0);
+        assert_eq!(duty(50, 8191), 2047);
+        assert_eq!(duty(100, 8191), 8191);
+        assert_eq!(duty(200, 8191), 8191);
+        // Dim but not off
+        assert_eq!(duty(1, 8191), 1);
+        assert_eq!(duty(1, 255), 1);
+        let duties: Vec<_> = (0..=100).map(|brightness| duty(brightness, 1023)).collect();
+        assert!(duties.is_sorted());
+    }
+
+    #[test]
+    fn fades_linearly() {
+        let fade = Fade::new(0, 100, 1000, 500);
+        assert_eq!(fade.target(), 100);
+        let brightness: Vec<_> = [1000, 1100, 1250, 1499, 1500, 2000]
+            .into_iter()
+            .map(|now| fade.brightness(now))
+            .collect();
+        assert_eq!(brightness, [0, 20, 50, 99, 100, 100]);
+        assert!(!fade.is_done(1499));
+        assert!(fade.is_done(1500));
+    }
+
+    #[test]
+    fn shorter_changes_fade_faster() {
+        let fade = Fade::new(80, 60, 0, 500);
+        assert_eq!(fade.brightness(50), 70);
+        assert!(fade.is_done(100));
+        assert_eq!(fade.brightness(100), 60);
+        let unchanged = Fade::new(40, 40, 0, 500);
+        assert!(unchanged.is_done(0));
+        assert_eq!(unchanged.brightness(0), 40);
+    }
+
+    #[test]
+    fn fades_across_the_timer_wrapping() {
+        let fade = Fade::new(100, 0, u32::MAX - 99, 400);
+        assert_eq!(fade.brightness(u32::MAX - 99), 100);
+        assert_eq!(fade.brightness(100), 50);
+        assert!(fade.is_done(300));
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(brighter(0), 5);
+        assert_eq!(brighter(5), 10);
+        assert_eq!(brighter(42), 50);
+        assert_eq!(brighter(100), 100);
+        assert_eq!(dimmer(100), 75);
+        assert_eq!(dimmer(42), 35);
+        assert_eq!(dimmer(10), 5);
+        assert_eq!(dimmer(5), 5);
+        assert_eq!(dimmer(0), 5);
+    }
+}
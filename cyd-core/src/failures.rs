@@ -0,0 +1,81 @@
+//! Videos that failed to play since one last played.
+//!
+//! Failures are counted per video, so a video picked again by shuffle, repeat or going
+//! back doesn't count twice towards all videos failing.
+
+/// Distinct videos that failed, up to `N`. Beyond that it is unknown whether
+/// all videos failed, so collections of more than `N` videos keep skipping.
+pub struct Failures<T, const N: usize> {
+    failed: [Option<T>; N],
+    len: usize,
+}
+
+impl<T: Copy + PartialEq, const N: usize> Failures<T, N> {
+    pub fn new() -> Self {
+        Self {
+            failed: [None; N],
+            len: 0,
+        }
+    }
+
+    /// Count `video` as failed unless it already is
+    pub fn add(&mut self, video: T) {
+        if self.len < N && !self.failed[..self.len].contains(&Some(video)) {
+            self.failed[self.len] = Some(video);
+            self.len += 1;
+        }
+    }
+
+    /// A video played, so not all of them fail
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Whether each of the `videos` videos failed
+    pub fn all_failed(&self, videos: usize) -> bool {
+        videos > 0 && videos <= N && self.len >= videos
+    }
+}
+
+impl<T: Copy + PartialEq, const N: usize> Default for Failures<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_each_video_once() {
+        let mut failures = Failures::<u32, 4>::new();
+        for video in [1, 2, 1, 1, 2] {
+            failures.add(video);
+        }
+        assert!(!failures.all_failed(3));
+        failures.add(3);
+        assert!(failures.all_failed(3));
+        assert!(!failures.all_failed(0));
+    }
+
+    #[test]
+    fn cleared_when_a_video_plays() {
+        let mut failures = Failures::<u32, 4>::new();
+        failures.add(1);
+        failures.add(2);
+        failures.clear();
+        failures.add(1);
+        assert!(!failures.all_failed(2));
+    }
+
+    #[test]
+    fn more_videos_than_fit_never_all_fail() {
+        let mut failures = Failures::<u32, 4>::new();
+        for video in 0..10 {
+            failures.add(video);
+        }
+        assert!(!failures.all_failed(5));
+        assert!(!failures.all_failed(10));
+    }
+}
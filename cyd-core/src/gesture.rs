@@ -0,0 +1,364 @@
+use cyd_encoder::settings::TouchSettings;
+use embedded_graphics::prelude::{Point, Size};
+
+pub use cyd_encoder::settings::Action;
+
+/// Direction of a swipe, in screen coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    Tap(Point),
+    DoubleTap(Point),
+    LongPress(Point),
+    Swipe(Direction),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GestureConfig {
+    /// Maximum time between two taps to be recognized as a double tap
+    pub double_tap_ms: u32,
+    /// Minimum time a touch must be held to be a long press
+    pub long_press_ms: u32,
+    /// Maximum distance a tap may wander and still be a tap
+    pub tap_slop: u32,
+    /// Minimum distance along the dominant axis for a swipe
+    pub swipe_distance: u32,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            double_tap_ms: 300,
+            long_press_ms: 800,
+            tap_slop: 12,
+            swipe_distance: 60,
+        }
+    }
+}
+
+enum State {
+    Idle,
+    Pressed {
+        start_ms: u32,
+        start: Point,
+        last: Point,
+    },
+    // Long press already reported, wait for release
+    Held,
+}
+
+/// Recognizes gestures from timestamped touch samples.
+///
+/// Call [`GestureRecognizer::update`] regularly with the current time and
+/// touch position (`None` when not touched).
+/// Single taps are only reported once the double tap window has expired.
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    state: State,
+    pending_tap: Option<(u32, Point)>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            state: State::Idle,
+            pending_tap: None,
+        }
+    }
+
+    /// True if a touch is in progress or a tap is awaiting a possible second tap
+    pub fn is_active(&self) -> bool {
+        !matches!(self.state, State::Idle) || self.pending_tap.is_some()
+    }
+
+    pub fn update(&mut self, now_ms: u32, position: Option<Point>) -> Option<Gesture> {
+        match (&mut self.state, position) {
+            (State::Idle, Some(position)) => {
+                self.state = State::Pressed {
+                    start_ms: now_ms,
+                    start: position,
+                    last: position,
+                };
+                None
+            }
+            (State::Idle, None) => self.expire_pending_tap(now_ms),
+            (
+                State::Pressed {
+                    start_ms,
+                    start,
+                    last,
+                },
+                Some(position),
+            ) => {
+                *last = position;
+                if now_ms.wrapping_sub(*start_ms) >= self.config.long_press_ms
+                    && distance(*start, position) <= self.config.tap_slop
+                {
+                    let start = *start;
+                    self.state = State::Held;
+                    self.pending_tap = None;
+                    return Some(Gesture::LongPress(start));
+                }
+                None
+            }
+            (State::Pressed { start, last, .. }, None) => {
+                let (start, end) = (*start, *last);
+                self.state = State::Idle;
+                self.released(now_ms, start, end)
+            }
+            (State::Held, Some(_)) => None,
+            (State::Held, None) => {
+                self.state = State::Idle;
+                None
+            }
+        }
+    }
+
+    fn released(&mut self, now_ms: u32, start: Point, end: Point) -> Option<Gesture> {
+        let delta = end - start;
+        let (dx, dy) = (delta.x.unsigned_abs(), delta.y.unsigned_abs());
+        if dx.max(dy) >= self.config.swipe_distance {
+            self.pending_tap = None;
+            let direction = if dx >= dy {
+                if delta.x > 0 {
+                    Direction::Right
+                } else {
+                    Direction::Left
+                }
+            } else if delta.y > 0 {
+                Direction::Down
+            } else {
+                Direction::Up
+            };
+            return Some(Gesture::Swipe(direction));
+        }
+        if distance(start, end) > self.config.tap_slop {
+            // Neither a tap nor a swipe
+            return self
+                .pending_tap
+                .take()
+                .map(|(_, point)| Gesture::Tap(point));
+        }
+        match self.pending_tap.take() {
+            Some((tap_ms, point))
+                if now_ms.wrapping_sub(tap_ms) <= self.config.double_tap_ms
+                    && distance(point, start) <= self.config.tap_slop =>
+            {
+                Some(Gesture::DoubleTap(point))
+            }
+            previous => {
+                self.pending_tap = Some((now_ms, start));
+                previous.map(|(_, point)| Gesture::Tap(point))
+            }
+        }
+    }
+
+    fn expire_pending_tap(&mut self, now_ms: u32) -> Option<Gesture> {
+        match self.pending_tap {
+            Some((tap_ms, point)) if now_ms.wrapping_sub(tap_ms) > self.config.double_tap_ms => {
+                self.pending_tap = None;
+                Some(Gesture::Tap(point))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn distance(a: Point, b: Point) -> u32 {
+    let delta = a - b;
+    delta.x.unsigned_abs().max(delta.y.unsigned_abs())
+}
+
+/// Maps gestures to actions.
+///
+/// Taps are split into three vertical zones, `left` and `right` are the zone widths
+/// in pixels and the center zone is what remains.
+#[derive(Debug, Clone, Copy)]
+pub struct Bindings {
+    pub screen: Size,
+    pub left: u32,
+    pub right: u32,
+    pub tap_left: Action,
+    pub tap_center: Action,
+    pub tap_right: Action,
+    pub double_tap: Action,
+    pub long_press: Action,
+    pub swipe_left: Action,
+    pub swipe_right: Action,
+    pub swipe_up: Action,
+    pub swipe_down: Action,
+}
+
+impl Bindings {
+    pub fn new(screen: Size) -> Self {
+        Self {
+            screen,
+            left: screen.width / 3,
+            right: screen.width / 3,
+            tap_left: Action::Previous,
+            tap_center: Action::TogglePause,
+            tap_right: Action::Next,
+            double_tap: Action::None,
+            long_press: Action::Stop,
+            swipe_left: Action::Next,
+            swipe_right: Action::Previous,
+            swipe_up: Action::BrightnessUp,
+            swipe_down: Action::BrightnessDown,
+        }
+    }
+
+    /// Override the default bindings with those from the settings
+    pub fn apply(&mut self, settings: &TouchSettings) {
+        let overrides = [
+            (&mut self.tap_left, settings.tap_left),
+            (&mut self.tap_center, settings.tap_center),
+            (&mut self.tap_right, settings.tap_right),
+            (&mut self.double_tap, settings.double_tap),
+            (&mut self.long_press, settings.long_press),
+            (&mut self.swipe_left, settings.swipe_left),
+            (&mut self.swipe_right, settings.swipe_right),
+            (&mut self.swipe_up, settings.swipe_up),
+            (&mut self.swipe_down, settings.swipe_down),
+        ];
+        for (binding, action) in overrides {
+            if let Some(action) = action {
+                *binding = action;
+            }
+        }
+    }
+
+    pub fn action(&self, gesture: Gesture) -> Action {
+        match gesture {
+            Gesture::Tap(point) => {
+                if point.x < self.left as i32 {
+                    self.tap_left
+                } else if point.x >= self.screen.width.saturating_sub(self.right) as i32 {
+                    self.tap_right
+                } else {
+                    self.tap_center
+                }
+            }
+            Gesture::DoubleTap(_) => self.double_tap,
+            Gesture::LongPress(_) => self.long_press,
+            Gesture::Swipe(Direction::Left) => self.swipe_left,
+            Gesture::Swipe(Direction::Right) => self.swipe_right,
+            Gesture::Swipe(Direction::Up) => self.swipe_up,
+            Gesture::Swipe(Direction::Down) => self.swipe_down,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tap(recognizer: &mut GestureRecognizer, now_ms: u32, point: Point) -> Option<Gesture> {
+        assert_eq!(recognizer.update(now_ms, Some(point)), None);
+        recognizer.update(now_ms + 50, None)
+    }
+
+    #[test]
+    fn single_tap_after_double_tap_window() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let point = Point::new(100, 100);
+        assert_eq!(tap(&mut recognizer, 0, point), None);
+        assert!(recognizer.is_active());
+        assert_eq!(recognizer.update(300, None), None);
+        assert_eq!(recognizer.update(351, None), Some(Gesture::Tap(point)));
+        assert!(!recognizer.is_active());
+    }
+
+    #[test]
+    fn double_tap() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        assert_eq!(tap(&mut recognizer, 0, Point::new(100, 100)), None);
+        assert_eq!(
+            tap(&mut recognizer, 200, Point::new(105, 95)),
+            Some(Gesture::DoubleTap(Point::new(100, 100)))
+        );
+        assert_eq!(recognizer.update(1000, None), None);
+    }
+
+    #[test]
+    fn taps_apart_are_not_a_double_tap() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let (first, second) = (Point::new(40, 100), Point::new(80, 100));
+        assert_eq!(tap(&mut recognizer, 0, first), None);
+        assert_eq!(tap(&mut recognizer, 200, second), Some(Gesture::Tap(first)));
+        assert_eq!(recognizer.update(600, None), Some(Gesture::Tap(second)));
+    }
+
+    #[test]
+    fn long_press() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let point = Point::new(10, 20);
+        assert_eq!(recognizer.update(0, Some(point)), None);
+        assert_eq!(recognizer.update(799, Some(point + Point::new(3, 0))), None);
+        assert_eq!(
+            recognizer.update(800, Some(point)),
+            Some(Gesture::LongPress(point))
+        );
+        assert_eq!(recognizer.update(2000, Some(point)), None);
+        assert_eq!(recognizer.update(2100, None), None);
+        assert_eq!(recognizer.update(3000, None), None);
+    }
+
+    #[test]
+    fn swipes() {
+        let cases = [
+            (Point::new(200, 100), Point::new(120, 110), Direction::Left),
+            (Point::new(120, 100), Point::new(200, 90), Direction::Right),
+            (Point::new(100, 200), Point::new(110, 120), Direction::Up),
+            (Point::new(100, 120), Point::new(90, 200), Direction::Down),
+        ];
+        for (start, end, direction) in cases {
+            let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+            assert_eq!(recognizer.update(0, Some(start)), None);
+            assert_eq!(recognizer.update(100, Some(end)), None);
+            assert_eq!(
+                recognizer.update(150, None),
+                Some(Gesture::Swipe(direction))
+            );
+        }
+    }
+
+    #[test]
+    fn short_drag_is_ignored() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        assert_eq!(recognizer.update(0, Some(Point::new(100, 100))), None);
+        assert_eq!(recognizer.update(100, Some(Point::new(130, 100))), None);
+        assert_eq!(recognizer.update(150, None), None);
+        assert_eq!(recognizer.update(1000, None), None);
+    }
+
+    #[test]
+    fn timestamps_wrap() {
+        let mut recognizer = GestureRecognizer::new(GestureConfig::default());
+        let point = Point::new(100, 100);
+        assert_eq!(tap(&mut recognizer, u32::MAX - 100, point), None);
+        assert_eq!(
+            tap(&mut recognizer, 50, point),
+            Some(Gesture::DoubleTap(point))
+        );
+    }
+
+    #[test]
+    fn tap_zones() {
+        let bindings = Bindings::new(Size::new(320, 240));
+        let action = |x| bindings.action(Gesture::Tap(Point::new(x, 120)));
+        assert_eq!(action(0), Action::Previous);
+        assert_eq!(action(105), Action::Previous);
+        assert_eq!(action(106), Action::TogglePause);
+        assert_eq!(action(213), Action::TogglePause);
+        assert_eq!(action(214), Action::Next);
+        assert_eq!(action(319), Action::Next);
+    }
+}
@@ -0,0 +1,527 @@
+//! Finding JPEG frames in an MJPEG stream and decompressing them with tjpgdec.
+//!
+//! Frames are read into a buffer owned by the caller and decompressed in an arena carved
+//! from the same buffer, so nothing is allocated per frame.
+
+use core::{
+    cell::{Cell, RefCell},
+    mem,
+    ops::Range,
+};
+
+use memchr::memmem;
+
+use crate::blit::{Blit, BlitTarget, pack};
+use embedded_graphics::{
+    geometry::Point, image::ImageDrawable, pixelcolor::Rgb565, prelude::*,
+    primitives::Rectangle as GraphicsRectangle,
+};
+use embedded_io::Read;
+use tjpgdec_rs::{JpegDecoder, MINIMUM_POOL_SIZE, MemoryPool};
+
+/// Finds complete frames between SOI and EOI markers, skipping corrupt and oversize ones
+pub struct FrameReader {
+    soi_finder: memmem::Finder<'static>,
+    eoi_finder: memmem::Finder<'static>,
+    valid: Range<usize>,
+    skipped: Skipped,
+    // Bytes read and frames found to estimate the number of frames
+    read: u64,
+    found: u32,
+}
+
+/// Data dropped while looking for the next complete frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Skipped {
+    pub bytes: u64,
+    /// Frames without an end or larger than the buffer
+    pub frames: u32,
+}
+
+/// Result of looking for a frame in the buffer
+enum Scan {
+    Frame(Range<usize>),
+    /// Another frame starts at `next` before the first one ends
+    Truncated {
+        next: usize,
+    },
+    /// The end of the frame at `start` hasn't been read yet
+    Partial(usize),
+    Missing,
+}
+
+// Largest MCU of baseline JPEGs is 16x16 pixels with 4:2:0 subsampling, 4 Y + Cb + Cr blocks
+const MCU_BUFFER_SIZE: usize = 6 * 64;
+// RGB output of the largest MCU with room to spare
+const WORK_BUFFER_SIZE: usize = 1024;
+// What tjpgdec asks for the largest MCU: its luma blocks plus two, and its RGB888 pixels
+const _: () = assert!(MCU_BUFFER_SIZE >= (2 * 2 + 2) * 64 && WORK_BUFFER_SIZE >= 16 * 16 * 3);
+
+// tjpgdec aligns allocations to 8 bytes from the start of the pool, so the pool must be too
+const POOL_ALIGN: usize = 8;
+
+/// Pool, MCU and work buffers to decode a JPG
+pub const ARENA_SIZE: usize = POOL_ALIGN - 1
+    + MINIMUM_POOL_SIZE
+    + WORK_BUFFER_SIZE
+    + (MCU_BUFFER_SIZE + 1) * mem::size_of::<i16>();
+
+mod markers {
+    pub const SOI: &[u8; 2] = &[0xFF, 0xD8];
+    pub const EOI: &[u8; 2] = &[0xFF, 0xD9];
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self {
+            soi_finder: memmem::Finder::new(markers::SOI),
+            eoi_finder: memmem::Finder::new(markers::EOI),
+            valid: 0..0,
+            skipped: Skipped::default(),
+            read: 0,
+            found: 0,
+        }
+    }
+
+    /// Data skipped so far because of corrupt or oversize frames
+    pub fn skipped(&self) -> Skipped {
+        self.skipped
+    }
+
+    /// Frames found so far and the bytes read up to the end of the last one
+    pub fn progress(&self) -> (u32, u64) {
+        (self.found, self.read - self.valid.len() as u64)
+    }
+
+    fn scan(&self, buffer: &[u8]) -> Scan {
+        let Some(start) = self.soi_finder.find(buffer) else {
+            return Scan::Missing;
+        };
+        let data_start = start + markers::SOI.len();
+        let data = &buffer[data_start..];
+        let eoi_pos = self.eoi_finder.find(data);
+        // SOI can't occur inside a frame, so the frame is missing its end
+        if let Some(next) = self.soi_finder.find(&data[..eoi_pos.unwrap_or(data.len())]) {
+            return Scan::Truncated {
+                next: data_start + next,
+            };
+        }
+        match eoi_pos {
+            Some(eoi_pos) => Scan::Frame(start..data_start + eoi_pos + markers::EOI.len()),
+            None => Scan::Partial(start),
+        }
+    }
+
+    /// Read from `reader` up to the next complete frame, at most `chunk` bytes at a time,
+    /// calling `between_reads` after each read. Returns where the frame is in `buffer`,
+    /// `None` at the end of the file.
+    /// Pass the same buffer every time, it holds data read ahead of the frame.
+    pub fn read_frame<R: Read>(
+        &mut self,
+        reader: &mut R,
+        buffer: &mut [u8],
+        chunk: usize,
+        between_reads: &mut dyn FnMut(),
+    ) -> Result<Option<Range<usize>>, R::Error> {
+        let buffer_len = buffer.len();
+        loop {
+            // Shift valid contents to beginning
+            if self.valid.start > 0 {
+                buffer.copy_within(self.valid.clone(), 0);
+                self.valid = 0..self.valid.len();
+            }
+            // Read into remaining unused buffer
+            let valid_end = self.valid.end;
+            let full = valid_end == buffer_len;
+            let read_len = if full {
+                0
+            } else {
+                let read_end = buffer_len.min(valid_end.saturating_add(chunk));
+                let read_len = reader.read(&mut buffer[valid_end..read_end])?;
+                between_reads();
+                read_len
+            };
+            self.read += read_len as u64;
+            let end_of_file = !full && read_len == 0;
+            let valid_end = valid_end + read_len;
+            self.valid.end = valid_end;
+
+            let partial = match self.scan(&buffer[..valid_end]) {
+                Scan::Frame(jpeg_range) => {
+                    self.discard(jpeg_range.start, false);
+                    self.valid = jpeg_range.end..valid_end;
+                    self.found += 1;
+                    return Ok(Some(jpeg_range));
+                }
+                Scan::Truncated { next } => {
+                    log::warn!("Skipping frame without end of image");
+                    self.discard(next, true);
+                    continue;
+                }
+                Scan::Partial(0) if full => {
+                    // The rest of the frame is dropped as it contains no SOI
+                    log::warn!("Skipping frame larger than {buffer_len} bytes");
+                    self.discard(valid_end, true);
+                    false
+                }
+                Scan::Partial(start) => {
+                    self.discard(start, false);
+                    true
+                }
+                Scan::Missing => {
+                    // Keep the last byte, it may be the first half of the next SOI
+                    self.discard(valid_end.saturating_sub(1), false);
+                    false
+                }
+            };
+
+            if end_of_file {
+                // The last frame is cut off
+                self.discard(self.valid.len(), partial);
+                if self.skipped.bytes > 0 {
+                    log::warn!(
+                        "Skipped {} bytes and {} incomplete frames",
+                        self.skipped.bytes,
+                        self.skipped.frames
+                    );
+                }
+                return Ok(None);
+            }
+        }
+    }
+
+    fn discard(&mut self, bytes: usize, frame: bool) {
+        self.skipped.bytes += bytes as u64;
+        self.skipped.frames += frame as u32;
+        self.valid.start += bytes;
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct JpegDrawable<'a> {
+    jpeg_data: &'a [u8],
+    decoder: RefCell<JpegDecoder<'a>>,
+    buffers: RefCell<Buffers<'a>>,
+    scale: u8,
+    prepared: bool,
+    // Drawing can only return display errors
+    error: Cell<Option<tjpgdec_rs::Error>>,
+}
+
+/// MCU and work buffers for decompressing, carved from the arena
+struct Buffers<'a> {
+    mcu: &'a mut [i16],
+    work: &'a mut [u8],
+}
+
+// tjpgdec can scale output down by 1/2, 1/4 or 1/8
+pub const MAX_SCALE: u8 = 3;
+
+impl<'a> JpegDrawable<'a> {
+    /// Decode scaled down by `1 / 2^scale`
+    pub fn with_scale(
+        arena: &'a mut [u8; ARENA_SIZE],
+        jpeg_data: &'a [u8],
+        scale: u8,
+    ) -> Result<Self, tjpgdec_rs::Error> {
+        let frame = Self::prepare(arena, jpeg_data, scale);
+        match frame.take_error() {
+            Some(e) => Err(e),
+            None => Ok(frame),
+        }
+    }
+
+    /// Like [`JpegDrawable::with_scale`], but a frame that can't be decoded draws nothing
+    /// and returns the error from [`JpegDrawable::take_error`]
+    pub fn prepare(arena: &'a mut [u8; ARENA_SIZE], jpeg_data: &'a [u8], scale: u8) -> Self {
+        let offset = arena.as_ptr().align_offset(POOL_ALIGN);
+        let (pool_buffer, rest) = arena[offset..].split_at_mut(MINIMUM_POOL_SIZE);
+        let (work, mcu) = rest.split_at_mut(WORK_BUFFER_SIZE);
+        // Any two bytes are a valid i16, the arena has room to align
+        let (_, mcu, _) = unsafe { mcu.align_to_mut::<i16>() };
+        let mcu = &mut mcu[..MCU_BUFFER_SIZE];
+
+        let mut pool = MemoryPool::new(pool_buffer);
+        let mut decoder = JpegDecoder::new();
+        let error = decoder.prepare(jpeg_data, &mut pool).err();
+        Self {
+            jpeg_data,
+            decoder: RefCell::new(decoder),
+            buffers: RefCell::new(Buffers { mcu, work }),
+            scale: scale.min(MAX_SCALE),
+            prepared: error.is_none(),
+            error: Cell::new(error),
+        }
+    }
+
+    /// The compressed image
+    pub fn data(&self) -> &'a [u8] {
+        self.jpeg_data
+    }
+
+    /// The error decompressing the image when it was last drawn
+    pub fn take_error(&self) -> Option<tjpgdec_rs::Error> {
+        self.error.take()
+    }
+
+    /// Decompress, calling `output` with each area and its RGB888 pixels
+    fn render<E>(
+        &self,
+        mut output: impl FnMut(GraphicsRectangle, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if !self.prepared {
+            return Ok(());
+        }
+        let display_error: Cell<Option<E>> = Cell::new(None);
+        let mut decoder = self.decoder.borrow_mut();
+        let mut buffers = self.buffers.borrow_mut();
+        let Buffers { mcu, work } = &mut *buffers;
+        let mcu_size = decoder.mcu_buffer_size();
+        let work_size = decoder.work_buffer_size();
+        let (Some(mcu_buffer), Some(work_buffer)) =
+            (mcu.get_mut(..mcu_size), work.get_mut(..work_size))
+        else {
+            log::error!("JPG needs {mcu_size} MCU and {work_size} bytes work buffer");
+            self.error.set(Some(tjpgdec_rs::Error::InsufficientMemory));
+            return Ok(());
+        };
+        if let Err(e) = decoder.decompress(
+            self.jpeg_data,
+            self.scale,
+            mcu_buffer,
+            work_buffer,
+            &mut |_decoder, bitmap, jpeg_rect| {
+                let target_rect = GraphicsRectangle::with_corners(
+                    Point::new(jpeg_rect.left as i32, jpeg_rect.top as i32),
+                    Point::new(jpeg_rect.right as i32, jpeg_rect.bottom as i32),
+                );
+                // We can't return custom errors from the output function
+                // https://docs.rs/tjpgdec-rs/0.4.0/tjpgdec_rs/type.OutputCallback.html
+                if let Err(e) = output(target_rect, bitmap) {
+                    display_error.set(Some(e));
+                    return Ok(false);
+                }
+                Ok(true)
+            },
+        ) {
+            if let Some(e) = display_error.take() {
+                return Err(e);
+            }
+            // Part of the image may have been drawn
+            self.error.set(Some(e));
+        }
+        Ok(())
+    }
+}
+
+fn rgb565(pixel: &[u8]) -> Rgb565 {
+    Rgb565::new(pixel[0] >> 3, pixel[1] >> 2, pixel[2] >> 3)
+}
+
+impl Blit for JpegDrawable<'_> {
+    fn blit<T: BlitTarget>(&self, target: &mut T, top_left: Point) -> Result<(), T::Error> {
+        // Output is at most an MCU, which fits the work buffer as RGB888
+        let mut packed = [0u8; WORK_BUFFER_SIZE];
+        self.render(|area, bitmap| {
+            let len = bitmap.len() / 3 * 2;
+            for (packed, pixel) in packed.chunks_exact_mut(2).zip(bitmap.chunks_exact(3)) {
+                packed.copy_from_slice(&pack(rgb565(pixel)));
+            }
+            target.blit(&area.translate(top_left), &packed[..len])
+        })
+    }
+}
+
+impl ImageDrawable for JpegDrawable<'_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        self.render(|area, bitmap| {
+            target.fill_contiguous(&area, bitmap.chunks_exact(3).map(rgb565))
+        })
+    }
+
+    fn draw_sub_image<D>(
+        &self,
+        target: &mut D,
+        area: &GraphicsRectangle,
+    ) -> Result<(), <D as DrawTarget>::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        self.draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+impl OriginDimensions for JpegDrawable<'_> {
+    fn size(&self) -> Size {
+        if !self.prepared {
+            return Size::zero();
+        }
+        let decoder = self.decoder.borrow();
+        let scale = |dimension: u32| dimension.div_ceil(1 << self.scale);
+        Size::new(
+            scale(decoder.width() as u32),
+            scale(decoder.height() as u32),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::mock_display::MockDisplay;
+
+    use super::*;
+
+    /// Baseline 8x8 grayscale JPEG with all quantization values 1 and one Huffman code
+    /// per table, so the scan is a DC difference of 0 and the end of block
+    #[rustfmt::skip]
+    const JPEG: &[u8] = &[
+        0xFF, 0xD8,
+        // DQT
+        0xFF, 0xDB, 0x00, 0x43, 0x00,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        // SOF0
+        0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x08, 0x00, 0x08, 0x01, 0x01, 0x11, 0x00,
+        // DHT, DC and AC tables with a single code for 0
+        0xFF, 0xC4, 0x00, 0x14, 0x00, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00,
+        0xFF, 0xC4, 0x00, 0x14, 0x10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00,
+        // SOS
+        0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00,
+        0x3F,
+        0xFF, 0xD9,
+    ];
+    /// Baseline 16x16 JPEG with 4:2:0 subsampling, the largest MCU, built like [`JPEG`]
+    #[rustfmt::skip]
+    const JPEG_420: &[u8] = &[
+        0xFF, 0xD8,
+        0xFF, 0xDB, 0x00, 0x43, 0x00,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        // SOF0, Y sampled 2x2 and Cb, Cr 1x1
+        0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0x10, 0x00, 0x10, 0x03,
+        0x01, 0x22, 0x00, 0x02, 0x11, 0x00, 0x03, 0x11, 0x00,
+        0xFF, 0xC4, 0x00, 0x14, 0x00, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00,
+        0xFF, 0xC4, 0x00, 0x14, 0x10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00,
+        // Chroma tables
+        0xFF, 0xC4, 0x00, 0x14, 0x01, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00,
+        0xFF, 0xC4, 0x00, 0x14, 0x11, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00,
+        0xFF, 0xDA, 0x00, 0x0C, 0x03, 0x01, 0x00, 0x02, 0x11, 0x03, 0x11, 0x00, 0x3F, 0x00,
+        // Six blocks of two bits
+        0x00, 0x0F,
+        0xFF, 0xD9,
+    ];
+    // Cut off before the frame header
+    const TRUNCATED: &[u8] = &[0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x43, 0x00, 1, 1, 0xFF, 0xD9];
+
+    // Room for a few frames
+    const BUFFER_SIZE: usize = 1024;
+
+    fn stream(frames: &[&[u8]]) -> Vec<u8> {
+        frames.concat()
+    }
+
+    /// Each frame found until the end
+    fn frames(reader: &mut FrameReader, mut stream: &[u8]) -> Vec<Vec<u8>> {
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut frames = Vec::new();
+        while let Some(range) = reader
+            .read_frame(&mut stream, &mut buffer, usize::MAX, &mut || {})
+            .unwrap()
+        {
+            frames.push(buffer[range].to_vec());
+        }
+        frames
+    }
+
+    /// Size and decode error of `jpeg` drawn
+    fn draw(jpeg: &[u8]) -> (Size, Option<tjpgdec_rs::Error>) {
+        let mut arena = [0; ARENA_SIZE];
+        let frame = JpegDrawable::prepare(&mut arena, jpeg, 0);
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        frame.draw(&mut display).unwrap();
+        (frame.size(), frame.take_error())
+    }
+
+    #[test]
+    fn frames_between_markers() {
+        let mut reader = FrameReader::new();
+        let stream = stream(&[&[1, 2, 0xFF], JPEG, &[3], JPEG, &[4]]);
+        assert_eq!(frames(&mut reader, &stream), [JPEG, JPEG]);
+        assert_eq!(
+            reader.skipped(),
+            Skipped {
+                bytes: 5,
+                frames: 0
+            }
+        );
+        assert_eq!(reader.progress(), (2, stream.len() as u64));
+    }
+
+    #[test]
+    fn frames_without_end_are_skipped() {
+        let without_end = &JPEG[..JPEG.len() - 2];
+        let mut reader = FrameReader::new();
+        let stream = stream(&[without_end, JPEG, without_end]);
+        assert_eq!(frames(&mut reader, &stream), [JPEG]);
+        assert_eq!(reader.skipped().frames, 2);
+    }
+
+    #[test]
+    fn frames_larger_than_the_buffer_are_skipped() {
+        let mut large = JPEG[..JPEG.len() - 2].to_vec();
+        large.resize(BUFFER_SIZE, 0);
+        large.extend_from_slice(markers::EOI);
+        let mut reader = FrameReader::new();
+        let stream = stream(&[JPEG, &large, JPEG]);
+        assert_eq!(frames(&mut reader, &stream), [JPEG, JPEG]);
+        assert_eq!(reader.skipped().frames, 1);
+    }
+
+    #[test]
+    fn reads_in_chunks() {
+        let mut reader = FrameReader::new();
+        let stream = stream(&[JPEG, JPEG]);
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut reads = 0;
+        let range = reader
+            .read_frame(&mut stream.as_slice(), &mut buffer, 16, &mut || reads += 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer[range], JPEG);
+        assert_eq!(reads, JPEG.len().div_ceil(16));
+    }
+
+    #[test]
+    fn draws_frames() {
+        assert_eq!(draw(JPEG), (Size::new(8, 8), None));
+    }
+
+    #[test]
+    fn largest_mcu_fits_the_buffers() {
+        assert_eq!(draw(JPEG_420), (Size::new(16, 16), None));
+    }
+
+    #[test]
+    fn frames_that_fail_to_prepare_draw_nothing() {
+        let (size, error) = draw(TRUNCATED);
+        assert_eq!(size, Size::zero());
+        assert!(error.is_some());
+        let mut arena = [0; ARENA_SIZE];
+        assert!(JpegDrawable::with_scale(&mut arena, TRUNCATED, 0).is_err());
+    }
+}
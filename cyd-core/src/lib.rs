@@ -0,0 +1,14 @@
+#![cfg_attr(not(test), no_std)]
+pub mod ambient;
+pub mod bands;
+pub mod blit;
+pub mod contiguous;
+pub mod fade;
+pub mod failures;
+pub mod gesture;
+#[cfg(feature = "mjpeg")]
+pub mod jpeg;
+pub mod log_file;
+pub mod pipeline;
+pub mod stats;
+pub mod yuv;
@@ -0,0 +1,120 @@
+//! Logs on the card that are moved aside when they grow too large.
+
+use embedded_sdmmc::{Block, BlockDevice, Directory, Error, Mode, TimeSource};
+
+/// Append the line `bytes` to the file `name` in `directory`, starting a new file with the
+/// line `header` if there is one.
+///
+/// A file larger than `max` bytes is first copied to `old_name`, replacing the previous
+/// one, and started over. The FAT library can't rename files.
+pub fn append_rotating<
+    D: BlockDevice,
+    T: TimeSource,
+    const MAX_DIRS: usize,
+    const MAX_FILES: usize,
+    const MAX_VOLUMES: usize,
+>(
+    directory: &Directory<D, T, MAX_DIRS, MAX_FILES, MAX_VOLUMES>,
+    name: &str,
+    old_name: &str,
+    max: u32,
+    header: Option<&[u8]>,
+    bytes: &[u8],
+) -> Result<(), Error<D::Error>> {
+    let mut file = directory.open_file_in_dir(name, Mode::ReadWriteCreateOrAppend)?;
+    if file.length() > max {
+        file.seek_from_start(0)?;
+        let old = directory.open_file_in_dir(old_name, Mode::ReadWriteCreateOrTruncate)?;
+        let mut buffer = [0; Block::LEN];
+        while !file.is_eof() {
+            let n = file.read(&mut buffer)?;
+            old.write(&buffer[..n])?;
+        }
+        old.close()?;
+        file.close()?;
+        file = directory.open_file_in_dir(name, Mode::ReadWriteCreateOrTruncate)?;
+    }
+    if let Some(header) = header
+        && file.length() == 0
+    {
+        file.write(header)?;
+        file.write(b"\n")?;
+    }
+    file.write(bytes)?;
+    file.write(b"\n")?;
+    file.close()
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_sdmmc::{VolumeIdx, VolumeManager};
+
+    use super::*;
+    use crate::contiguous::tests::{Epoch, Image};
+
+    const MAX: u32 = 1000;
+
+    fn line(i: usize) -> Vec<u8> {
+        format!("{i:099}").into_bytes()
+    }
+
+    /// Lines `range` as they are in the file
+    fn lines(range: core::ops::Range<usize>) -> Vec<u8> {
+        range
+            .flat_map(|i| format!("{i:099}\n").into_bytes())
+            .collect()
+    }
+
+    fn contents<D: BlockDevice, T: TimeSource>(
+        directory: &Directory<D, T, 4, 4, 1>,
+        name: &str,
+    ) -> Vec<u8> {
+        let file = directory.open_file_in_dir(name, Mode::ReadOnly).unwrap();
+        let mut contents = vec![0; file.length() as usize];
+        let mut read = 0;
+        while read < contents.len() {
+            read += file.read(&mut contents[read..]).unwrap();
+        }
+        file.close().unwrap();
+        contents
+    }
+
+    #[test]
+    fn appends_until_the_log_is_too_large() {
+        let image = Image::fat16(0x06);
+        let volume_manager = VolumeManager::new(image, Epoch);
+        let volume = volume_manager.open_volume(VolumeIdx(0)).unwrap();
+        let root = volume.open_root_dir().unwrap();
+        let append = |i| append_rotating(&root, "LOG.TXT", "LOG.1", MAX, Some(b"header"), &line(i));
+
+        for i in 0..10 {
+            append(i).unwrap();
+        }
+        let expected = [&b"header\n"[..], &lines(0..10)].concat();
+        assert_eq!(contents(&root, "LOG.TXT"), expected);
+        assert!(root.find_directory_entry("LOG.1").is_err());
+
+        append(10).unwrap();
+        assert_eq!(contents(&root, "LOG.1"), expected);
+        assert_eq!(
+            contents(&root, "LOG.TXT"),
+            [&b"header\n"[..], &lines(10..11)].concat()
+        );
+    }
+
+    #[test]
+    fn replaces_the_old_log() {
+        let image = Image::fat16(0x06);
+        let volume_manager = VolumeManager::new(image, Epoch);
+        let volume = volume_manager.open_volume(VolumeIdx(0)).unwrap();
+        let root = volume.open_root_dir().unwrap();
+        let append = |i| append_rotating(&root, "LOG.TXT", "LOG.1", MAX, None, &line(i));
+
+        for i in 0..30 {
+            append(i).unwrap();
+        }
+        // Rotated after lines 0..11 and 11..22
+        assert_eq!(contents(&root, "LOG.1"), lines(11..22));
+        assert_eq!(contents(&root, "LOG.TXT"), lines(22..30));
+    }
+}
@@ -0,0 +1,204 @@
+//! Double-buffered output that overlaps sending one band with filling the next.
+//!
+//! The scheduling is independent of the hardware, a [`BackgroundBus`] with fake
+//! timings can drive a [`Pipeline`] on the host.
+
+/// Number of band buffers, one is sent while the other is filled
+pub const SLOTS: usize = 2;
+
+/// Bus that sends a buffer in the background, e.g. with DMA
+pub trait BackgroundBus {
+    type Error;
+
+    /// Buffer `slot` to fill, never the one being sent
+    fn buffer(&mut self, slot: usize) -> &mut [u8];
+
+    /// Start sending the first `len` bytes of buffer `slot`, no transfer is in flight
+    fn start(&mut self, slot: usize, len: usize) -> Result<(), Self::Error>;
+
+    /// Wait until the transfer in flight has finished
+    fn wait(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Queues writes into bands and sends each band while the next is filled
+pub struct Pipeline<B> {
+    bus: B,
+    // Slot being filled
+    slot: usize,
+    filled: usize,
+    in_flight: bool,
+}
+
+impl<B: BackgroundBus> Pipeline<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus,
+            slot: 0,
+            filled: 0,
+            in_flight: false,
+        }
+    }
+
+    /// Queue `data`, sending each band as soon as it is full
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), B::Error> {
+        while !data.is_empty() {
+            let buffer = self.bus.buffer(self.slot);
+            let capacity = buffer.len();
+            let len = (capacity - self.filled).min(data.len());
+            buffer[self.filled..self.filled + len].copy_from_slice(&data[..len]);
+            self.filled += len;
+            data = &data[len..];
+            if self.filled == capacity {
+                self.submit()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start sending the queued data without waiting for it to be sent.
+    /// Waits for the previous band if it is still in flight.
+    pub fn submit(&mut self) -> Result<(), B::Error> {
+        if self.filled == 0 {
+            return Ok(());
+        }
+        if self.in_flight {
+            self.bus.wait()?;
+        }
+        self.bus.start(self.slot, self.filled)?;
+        self.in_flight = true;
+        self.slot = (self.slot + 1) % SLOTS;
+        self.filled = 0;
+        Ok(())
+    }
+
+    /// The bus, for other transfers once flushed
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Send the queued data and wait until everything has been sent
+    pub fn flush(&mut self) -> Result<(), B::Error> {
+        self.submit()?;
+        if self.in_flight {
+            self.bus.wait()?;
+            self.in_flight = false;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BAND: usize = 16;
+    // Fake microseconds to send a byte
+    const BYTE_TIME: u32 = 1;
+
+    /// Sends instantly but reports a transfer busy for [`BYTE_TIME`] per byte
+    #[derive(Default)]
+    struct FakeBus {
+        buffers: [[u8; BAND]; SLOTS],
+        in_flight: Option<usize>,
+        sent: Vec<u8>,
+        transfers: Vec<usize>,
+        waits: usize,
+        now: u32,
+        busy_until: u32,
+    }
+
+    impl BackgroundBus for FakeBus {
+        type Error = ();
+
+        fn buffer(&mut self, slot: usize) -> &mut [u8] {
+            assert_ne!(self.in_flight, Some(slot), "filling the band in flight");
+            &mut self.buffers[slot]
+        }
+
+        fn start(&mut self, slot: usize, len: usize) -> Result<(), ()> {
+            assert_eq!(self.in_flight, None, "transfer already in flight");
+            self.in_flight = Some(slot);
+            self.sent.extend_from_slice(&self.buffers[slot][..len]);
+            self.transfers.push(len);
+            self.busy_until = self.now + len as u32 * BYTE_TIME;
+            Ok(())
+        }
+
+        fn wait(&mut self) -> Result<(), ()> {
+            assert!(self.in_flight.take().is_some(), "nothing in flight");
+            self.now = self.now.max(self.busy_until);
+            self.waits += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sends_everything_in_order() {
+        let mut pipeline = Pipeline::new(FakeBus::default());
+        let data: Vec<u8> = (0..100).collect();
+        for chunk in data.chunks(7) {
+            pipeline.write(chunk).unwrap();
+        }
+        pipeline.flush().unwrap();
+        let bus = pipeline.bus_mut();
+        assert_eq!(bus.sent, data);
+        assert_eq!(bus.transfers, [16, 16, 16, 16, 16, 16, 4]);
+        assert_eq!(bus.in_flight, None);
+        assert_eq!(bus.waits, bus.transfers.len());
+    }
+
+    #[test]
+    fn submit_does_not_wait() {
+        let mut pipeline = Pipeline::new(FakeBus::default());
+        pipeline.submit().unwrap();
+        assert!(pipeline.bus_mut().transfers.is_empty());
+        pipeline.write(&[1, 2, 3]).unwrap();
+        pipeline.submit().unwrap();
+        let bus = pipeline.bus_mut();
+        assert_eq!(bus.in_flight, Some(0));
+        assert_eq!(bus.waits, 0);
+        // The next band fills the other slot while the first is sent
+        pipeline.write(&[4; BAND]).unwrap();
+        let bus = pipeline.bus_mut();
+        assert_eq!(bus.in_flight, Some(1));
+        assert_eq!(bus.waits, 1);
+        pipeline.flush().unwrap();
+        pipeline.flush().unwrap();
+        assert_eq!(pipeline.bus_mut().waits, 2);
+    }
+
+    #[test]
+    fn bound_by_sending() {
+        // Filling a band takes less time than sending it
+        const FILL_TIME: u32 = 10;
+        const BANDS: u32 = 10;
+        let mut pipeline = Pipeline::new(FakeBus::default());
+        for band in 0..BANDS {
+            pipeline.bus_mut().now += FILL_TIME;
+            pipeline.write(&[band as u8; BAND]).unwrap();
+        }
+        pipeline.flush().unwrap();
+        let send_time = BAND as u32 * BYTE_TIME;
+        assert!(FILL_TIME < send_time);
+        // Only the first band is filled before anything is sent
+        assert_eq!(pipeline.bus_mut().now, FILL_TIME + BANDS * send_time);
+    }
+
+    #[test]
+    fn bound_by_filling() {
+        // Filling a band takes longer than sending it
+        const FILL_TIME: u32 = 40;
+        const BANDS: u32 = 10;
+        let mut pipeline = Pipeline::new(FakeBus::default());
+        for band in 0..BANDS {
+            pipeline.bus_mut().now += FILL_TIME;
+            pipeline.write(&[band as u8; BAND]).unwrap();
+        }
+        pipeline.flush().unwrap();
+        // Only the last band is sent after everything is filled
+        assert_eq!(
+            pipeline.bus_mut().now,
+            BANDS * FILL_TIME + BAND as u32 * BYTE_TIME
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Per-frame timing statistics of the video being played.
+//!
+//! The times of the most recent frames are kept in a ring for the 95th percentile,
+//! means and maxima cover the whole video.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+// Microseconds spent reading videos
+static READ_TIME: AtomicU32 = AtomicU32::new(0);
+
+/// Count `micros` spent reading a video
+pub fn add_read_time(micros: u32) {
+    READ_TIME.fetch_add(micros, Ordering::Relaxed);
+}
+
+/// Microseconds spent reading videos since the last call
+pub fn take_read_time() -> u32 {
+    READ_TIME.swap(0, Ordering::Relaxed)
+}
+
+/// Microseconds spent on a frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTimes {
+    /// Reading the SD card
+    pub read: u32,
+    /// Decoding, the rest of the time taken to produce the frame
+    pub decode: u32,
+    /// Sending pixels to the display
+    pub display: u32,
+    /// Waiting until the frame is due
+    pub idle: u32,
+}
+
+const PHASES: usize = 4;
+
+impl FrameTimes {
+    fn phases(&self) -> [u32; PHASES] {
+        [self.read, self.decode, self.display, self.idle]
+    }
+}
+
+/// Mean, 95th percentile and maximum in microseconds
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub mean: u32,
+    pub p95: u32,
+    pub max: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub frames: u32,
+    /// Frames drawn after they were due
+    pub dropped: u32,
+    pub read: Timing,
+    pub decode: Timing,
+    pub display: Timing,
+    pub idle: Timing,
+}
+
+impl Summary {
+    pub const CSV_HEADER: &str = "video,frames,dropped,\
+        read_mean,read_p95,read_max,decode_mean,decode_p95,decode_max,\
+        display_mean,display_p95,display_max,idle_mean,idle_p95,idle_max";
+
+    fn timings(&self) -> [(&'static str, &Timing); PHASES] {
+        [
+            ("read", &self.read),
+            ("decode", &self.decode),
+            ("display", &self.display),
+            ("idle", &self.idle),
+        ]
+    }
+
+    /// Values for a line of [`Summary::CSV_HEADER`], without the video
+    pub fn csv(&self) -> Csv<'_> {
+        Csv(self)
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} frames, {} dropped", self.frames, self.dropped)?;
+        for (name, timing) in self.timings() {
+            write!(
+                f,
+                ", {name} {}/{}/{} us",
+                timing.mean, timing.p95, timing.max
+            )?;
+        }
+        f.write_str(" (mean/p95/max)")
+    }
+}
+
+/// CSV values of a [`Summary`]
+pub struct Csv<'a>(&'a Summary);
+
+impl fmt::Display for Csv<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.0.frames, self.0.dropped)?;
+        for (_, timing) in self.0.timings() {
+            write!(f, ",{},{},{}", timing.mean, timing.p95, timing.max)?;
+        }
+        Ok(())
+    }
+}
+
+/// Timing statistics with a ring of the last `N` frames
+pub struct Stats<const N: usize> {
+    recent: [FrameTimes; N],
+    frames: u32,
+    dropped: u32,
+    total: [u64; PHASES],
+    max: [u32; PHASES],
+}
+
+impl<const N: usize> Default for Stats<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Stats<N> {
+    pub const fn new() -> Self {
+        Self {
+            recent: [FrameTimes {
+                read: 0,
+                decode: 0,
+                display: 0,
+                idle: 0,
+            }; N],
+            frames: 0,
+            dropped: 0,
+            total: [0; PHASES],
+            max: [0; PHASES],
+        }
+    }
+
+    /// Add the times of a frame, `late` if it was drawn after it was due
+    pub fn record(&mut self, times: FrameTimes, late: bool) {
+        if N > 0 {
+            self.recent[self.frames as usize % N] = times;
+        }
+        self.frames += 1;
+        self.dropped += late as u32;
+        for (phase, time) in times.phases().into_iter().enumerate() {
+            self.total[phase] += time as u64;
+            self.max[phase] = self.max[phase].max(time);
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let recent = &self.recent[..(self.frames as usize).min(N)];
+        let timing = |phase: usize| {
+            if self.frames == 0 {
+                return Timing::default();
+            }
+            Timing {
+                mean: (self.total[phase] / self.frames as u64) as u32,
+                p95: p95::<N>(recent, phase),
+                max: self.max[phase],
+            }
+        };
+        Summary {
+            frames: self.frames,
+            dropped: self.dropped,
+            read: timing(0),
+            decode: timing(1),
+            display: timing(2),
+            idle: timing(3),
+        }
+    }
+}
+
+/// 95th percentile of `phase` in `frames` by the nearest rank
+fn p95<const N: usize>(frames: &[FrameTimes], phase: usize) -> u32 {
+    if frames.is_empty() {
+        return 0;
+    }
+    let mut times = [0u32; N];
+    let times = &mut times[..frames.len()];
+    for (time, frame) in times.iter_mut().zip(frames) {
+        *time = frame.phases()[phase];
+    }
+    let rank = (frames.len() * 95).div_ceil(100);
+    *times.select_nth_unstable(rank - 1).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(read: u32, decode: u32, display: u32, idle: u32) -> FrameTimes {
+        FrameTimes {
+            read,
+            decode,
+            display,
+            idle,
+        }
+    }
+
+    #[test]
+    fn nearest_rank() {
+        let frames: Vec<_> = (1..=100).rev().map(|time| times(time, 0, 0, 0)).collect();
+        assert_eq!(p95::<100>(&frames, 0), 95);
+        assert_eq!(p95::<100>(&frames[..20], 0), 99);
+        assert_eq!(p95::<100>(&frames[..1], 0), 100);
+        assert_eq!(p95::<100>(&[], 0), 0);
+    }
+
+    #[test]
+    fn summary() {
+        let mut stats = Stats::<8>::new();
+        assert_eq!(stats.summary(), Summary::default());
+        stats.record(times(10, 100, 1000, 5), false);
+        stats.record(times(30, 300, 3000, 0), true);
+        stats.record(times(20, 200, 2000, 1), false);
+        let summary = stats.summary();
+        assert_eq!((summary.frames, summary.dropped), (3, 1));
+        let timing = |mean, p95, max| Timing { mean, p95, max };
+        assert_eq!(summary.read, timing(20, 30, 30));
+        assert_eq!(summary.decode, timing(200, 300, 300));
+        assert_eq!(summary.display, timing(2000, 3000, 3000));
+        assert_eq!(summary.idle, timing(2, 5, 5));
+    }
+
+    #[test]
+    fn percentile_of_recent_frames() {
+        let mut stats = Stats::<4>::new();
+        stats.record(times(1000, 0, 0, 0), false);
+        for _ in 0..4 {
+            stats.record(times(10, 0, 0, 0), false);
+        }
+        // The slow first frame left the ring but still counts for the mean and maximum
+        assert_eq!(
+            stats.summary().read,
+            Timing {
+                mean: 208,
+                p95: 10,
+                max: 1000
+            }
+        );
+        let mut empty = Stats::<0>::new();
+        empty.record(times(10, 0, 0, 0), false);
+        assert_eq!(
+            empty.summary().read,
+            Timing {
+                mean: 10,
+                p95: 0,
+                max: 10
+            }
+        );
+    }
+
+    #[test]
+    fn formats() {
+        let mut stats = Stats::<2>::new();
+        stats.record(times(1, 2, 3, 4), true);
+        let summary = stats.summary();
+        assert_eq!(
+            summary.to_string(),
+            "1 frames, 1 dropped, read 1/1/1 us, decode 2/2/2 us, display 3/3/3 us, \
+             idle 4/4/4 us (mean/p95/max)"
+        );
+        assert_eq!(summary.csv().to_string(), "1,1,1,1,1,2,2,2,3,3,3,4,4,4");
+        assert_eq!(Summary::CSV_HEADER.split(',').count(), 15);
+    }
+}
@@ -0,0 +1,220 @@
+//! Fixed-point YUV 4:2:0 to big-endian RGB565 conversion.
+//!
+//! The chroma contribution to each color channel is looked up in tables computed at
+//! compile time, so a 2x2 block of pixels sharing a chroma sample takes four table
+//! lookups for the chroma plus one per pixel for the luma.
+
+use cyd_encoder::format::yuv::{ColorRange, Matrix};
+
+// Coefficients in 16.16 fixed point
+const ONE: i32 = 1 << 16;
+
+struct Coefficients {
+    r_v: i32,
+    g_u: i32,
+    g_v: i32,
+    b_u: i32,
+}
+
+const BT601: Coefficients = Coefficients {
+    r_v: 91881,  // 1.402
+    g_u: 22554,  // 0.344136
+    g_v: 46802,  // 0.714136
+    b_u: 116130, // 1.772
+};
+
+const BT709: Coefficients = Coefficients {
+    r_v: 103206, // 1.5748
+    g_u: 12275,  // 0.1873
+    g_v: 30677,  // 0.4681
+    b_u: 121609, // 1.8556
+};
+
+/// Converts from one matrix and range
+pub struct Converter {
+    y: [i16; 256],
+    r_v: [i16; 256],
+    g_u: [i16; 256],
+    g_v: [i16; 256],
+    b_u: [i16; 256],
+}
+
+static BT601_FULL: Converter = Converter::new(Matrix::Bt601, ColorRange::Full);
+static BT601_LIMITED: Converter = Converter::new(Matrix::Bt601, ColorRange::Limited);
+static BT709_FULL: Converter = Converter::new(Matrix::Bt709, ColorRange::Full);
+static BT709_LIMITED: Converter = Converter::new(Matrix::Bt709, ColorRange::Limited);
+
+impl Converter {
+    const fn new(matrix: Matrix, range: ColorRange) -> Self {
+        let coefficients = match matrix {
+            Matrix::Bt601 => BT601,
+            Matrix::Bt709 => BT709,
+        };
+        // Limited range is scaled up to full range
+        let (y_offset, y_scale, c_scale) = match range {
+            ColorRange::Full => (0, ONE, ONE),
+            ColorRange::Limited => (16, 255 * ONE / 219, 255 * ONE / 224),
+        };
+        let mut converter = Self {
+            y: [0; 256],
+            r_v: [0; 256],
+            g_u: [0; 256],
+            g_v: [0; 256],
+            b_u: [0; 256],
+        };
+        let mut i = 0;
+        while i < 256 {
+            let value = i as i32;
+            converter.y[i] = round(value - y_offset, y_scale);
+            let chroma = value - 128;
+            converter.r_v[i] = round(chroma, scale(coefficients.r_v, c_scale));
+            converter.g_u[i] = round(chroma, scale(coefficients.g_u, c_scale));
+            converter.g_v[i] = round(chroma, scale(coefficients.g_v, c_scale));
+            converter.b_u[i] = round(chroma, scale(coefficients.b_u, c_scale));
+            i += 1;
+        }
+        converter
+    }
+
+    pub fn get(matrix: Matrix, range: ColorRange) -> &'static Self {
+        match (matrix, range) {
+            (Matrix::Bt601, ColorRange::Full) => &BT601_FULL,
+            (Matrix::Bt601, ColorRange::Limited) => &BT601_LIMITED,
+            (Matrix::Bt709, ColorRange::Full) => &BT709_FULL,
+            (Matrix::Bt709, ColorRange::Limited) => &BT709_LIMITED,
+        }
+    }
+
+    /// Convert two rows of luma sharing a row of chroma into two bytes per pixel.
+    /// `luma[1]` and `output[1]` are empty for the last row of an odd height.
+    pub fn convert_rows(&self, luma: [&[u8]; 2], u: &[u8], v: &[u8], output: [&mut [u8]; 2]) {
+        let [top, bottom] = output;
+        let width = luma[0].len();
+        for ((x, &u), &v) in (0..width).step_by(2).zip(u).zip(v) {
+            let (u, v) = (u as usize, v as usize);
+            let r = self.r_v[v];
+            let g = -self.g_u[u] - self.g_v[v];
+            let b = self.b_u[u];
+            for x in x..width.min(x + 2) {
+                top[x * 2..x * 2 + 2].copy_from_slice(&self.pixel(luma[0][x], r, g, b));
+                if let Some(pixel) = bottom.get_mut(x * 2..x * 2 + 2) {
+                    pixel.copy_from_slice(&self.pixel(luma[1][x], r, g, b));
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn pixel(&self, y: u8, r: i16, g: i16, b: i16) -> [u8; 2] {
+        let y = self.y[y as usize];
+        let channel = |offset: i16, bits: u32| ((y + offset).clamp(0, 255) as u16) >> (8 - bits);
+        (channel(r, 5) << 11 | channel(g, 6) << 5 | channel(b, 5)).to_be_bytes()
+    }
+}
+
+/// `coefficient * scale` in 16.16 fixed point
+const fn scale(coefficient: i32, scale: i32) -> i32 {
+    ((coefficient as i64 * scale as i64) >> 16) as i32
+}
+
+/// `value * coefficient` rounded to the nearest integer
+const fn round(value: i32, coefficient: i32) -> i16 {
+    (value * coefficient + ONE / 2).div_euclid(ONE) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Red and blue weights of the luma of each matrix
+    fn weights(matrix: Matrix) -> (f64, f64) {
+        match matrix {
+            Matrix::Bt601 => (0.299, 0.114),
+            Matrix::Bt709 => (0.2126, 0.0722),
+        }
+    }
+
+    /// RGB565 channels of the pixel in floating point
+    fn reference(matrix: Matrix, range: ColorRange, y: u8, u: u8, v: u8) -> [i32; 3] {
+        let (kr, kb) = weights(matrix);
+        let kg = 1.0 - kr - kb;
+        let (y, u, v) = (y as f64, u as f64 - 128.0, v as f64 - 128.0);
+        let (y, u, v) = match range {
+            ColorRange::Full => (y, u, v),
+            ColorRange::Limited => (
+                (y - 16.0) * 255.0 / 219.0,
+                u * 255.0 / 224.0,
+                v * 255.0 / 224.0,
+            ),
+        };
+        let r = y + 2.0 * (1.0 - kr) * v;
+        let g = y - 2.0 * kb * (1.0 - kb) / kg * u - 2.0 * kr * (1.0 - kr) / kg * v;
+        let b = y + 2.0 * (1.0 - kb) * u;
+        let channel =
+            |value: f64, bits: u32| (value.round().clamp(0.0, 255.0) as i32) >> (8 - bits);
+        [channel(r, 5), channel(g, 6), channel(b, 5)]
+    }
+
+    fn convert(converter: &Converter, y: u8, u: u8, v: u8) -> [i32; 3] {
+        let mut pixels = [0; 8];
+        let (top, bottom) = pixels.split_at_mut(4);
+        converter.convert_rows([&[y, y], &[y, y]], &[u], &[v], [top, bottom]);
+        assert!(
+            pixels.chunks(2).all(|pixel| pixel == &pixels[..2]),
+            "pixels sharing chroma differ"
+        );
+        let pixel = u16::from_be_bytes([pixels[0], pixels[1]]) as i32;
+        [pixel >> 11, pixel >> 5 & 0x3F, pixel & 0x1F]
+    }
+
+    #[test]
+    fn within_one_step_of_floating_point() {
+        for matrix in [Matrix::Bt601, Matrix::Bt709] {
+            for range in [ColorRange::Full, ColorRange::Limited] {
+                let converter = Converter::get(matrix, range);
+                for y in (0..=255).step_by(3) {
+                    for u in (0..=255).step_by(5) {
+                        for v in (0..=255).step_by(5) {
+                            let expected = reference(matrix, range, y, u, v);
+                            let actual = convert(converter, y, u, v);
+                            for (actual, expected) in actual.into_iter().zip(expected) {
+                                assert!(
+                                    (actual - expected).abs() <= 1,
+                                    "{matrix:?} {range:?} YUV {y} {u} {v}: \
+                                     {actual:?} instead of {expected:?}"
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn grays_and_limits() {
+        let full = Converter::get(Matrix::Bt709, ColorRange::Full);
+        assert_eq!(convert(full, 0, 128, 128), [0, 0, 0]);
+        assert_eq!(convert(full, 255, 128, 128), [31, 63, 31]);
+        assert_eq!(convert(full, 128, 128, 128), [16, 32, 16]);
+        // Limited range black and white, and values beyond them clamp
+        let limited = Converter::get(Matrix::Bt601, ColorRange::Limited);
+        assert_eq!(convert(limited, 16, 128, 128), [0, 0, 0]);
+        assert_eq!(convert(limited, 235, 128, 128), [31, 63, 31]);
+        assert_eq!(convert(limited, 0, 128, 128), [0, 0, 0]);
+        assert_eq!(convert(limited, 255, 128, 128), [31, 63, 31]);
+    }
+
+    #[test]
+    fn odd_widths_and_heights() {
+        let converter = Converter::get(Matrix::Bt601, ColorRange::Full);
+        let mut top = [0; 6];
+        converter.convert_rows(
+            [&[0, 255, 255], &[]],
+            &[128, 128],
+            &[128, 128],
+            [&mut top, &mut []],
+        );
+        assert_eq!(top, [0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+}
@@ -1,8 +1,11 @@
-use cyd_encoder::format::{self, FormatHeader};
+use cyd_encoder::{
+    board::{self, Board},
+    format::{self, FormatHeader, metadata},
+};
 use regex::Regex;
 use std::{
     error::Error,
-    fs::{File, rename},
+    fs::{self, File, rename},
     io::{self, Write},
     path::Path,
     process::{Command, Output, exit},
@@ -18,9 +21,18 @@ struct Args {
     #[argh(option, default = "15u8")]
     /// frames per second
     fps: u8,
+    #[argh(option, default = "board::ESP32_2432S028R.name.to_string()")]
+    /// board to fit the video to (2432s028r, 2432s024r or 3248s035r)
+    board: String,
     #[argh(option)]
     /// path to subtitles srt/vtt file
     subtitles: Option<String>,
+    #[argh(option)]
+    /// title shown in the player menu, written to a sidecar file
+    title: Option<String>,
+    #[argh(switch)]
+    /// write a poster thumbnail sidecar file
+    poster: bool,
     #[argh(positional)]
     input: String,
     #[argh(positional)]
@@ -29,21 +41,21 @@ struct Args {
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Args = argh::from_env();
+    let board = board::find(&args.board).ok_or("unknown board")?;
+    write_metadata(&args)?;
     match args.format.as_str() {
-        "mjpeg" => encode_mjpeg(args),
-        "yuv" => encode_yuv(args),
-        "rgb" => encode_rgb(args),
+        "mjpeg" => encode_mjpeg(args, board),
+        "yuv" => encode_yuv(args, board),
+        "rgb" => encode_rgb(args, board),
         _ => Err("invalid format".into()),
     }
 }
 
-fn encode_mjpeg(args: Args) -> Result<(), Box<dyn Error>> {
+fn encode_mjpeg(args: Args, board: Board) -> Result<(), Box<dyn Error>> {
     let header = format::mjpeg::MjpegHeader::new(args.fps);
     let mut filter = format!(
         "framerate={},scale=size={}x{}:force_original_aspect_ratio=decrease:reset_sar=1:flags=lanczos",
-        args.fps,
-        format::mjpeg::MjpegHeader::MAX_WIDTH,
-        format::mjpeg::MjpegHeader::MAX_HEIGHT
+        args.fps, board.width, board.height
     );
     if let Some(subtitles) = args.subtitles {
         filter.insert_str(
@@ -74,6 +86,47 @@ fn encode_mjpeg(args: Args) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+fn write_metadata(args: &Args) -> Result<(), Box<dyn Error>> {
+    let output = Path::new(&args.output);
+    if let Some(title) = &args.title {
+        fs::write(
+            output.with_extension(metadata::TITLE_EXTENSION),
+            format!("{title}\n"),
+        )?;
+    }
+    if args.poster {
+        let filter = format!(
+            "thumbnail,scale=size={}x{}:force_original_aspect_ratio=decrease:reset_sar=1:flags=lanczos",
+            metadata::POSTER_WIDTH,
+            metadata::POSTER_HEIGHT
+        );
+        let status = Command::new("ffmpeg")
+            .args([
+                "-hide_banner",
+                "-i",
+                &args.input,
+                "-an",
+                "-vf",
+                &filter,
+                "-frames:v",
+                "1",
+                "-pix_fmt",
+                "yuv420p",
+                "-q:v",
+                "5",
+                "-f",
+                "mjpeg",
+                "-y",
+            ])
+            .arg(output.with_extension(metadata::POSTER_EXTENSION))
+            .status()?;
+        if !status.success() {
+            return Err("failed to create poster".into());
+        }
+    }
+    Ok(())
+}
+
 const DUMP_SEPARATOR: &str = " @@!!!!@@ ";
 
 fn parse_output(output: Output) -> Result<(u16, u16), Box<dyn Error>> {
@@ -101,12 +154,22 @@ fn parse_output(output: Output) -> Result<(u16, u16), Box<dyn Error>> {
     Ok((width, height))
 }
 
-fn encode_yuv(args: Args) -> Result<(), Box<dyn Error>> {
+/// Raw frames must fit the panel of the board they are played on
+fn check_size(board: &Board, width: u16, height: u16) -> Result<(), Box<dyn Error>> {
+    if !board.fits(width, height) {
+        return Err(format!(
+            "{width}x{height} frames don't fit the {}x{} panel of {}",
+            board.width, board.height, board.name
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn encode_yuv(args: Args, board: Board) -> Result<(), Box<dyn Error>> {
     let mut filter = format!(
         "framerate={},scale=size={}x{}:force_original_aspect_ratio=decrease:reset_sar=1:out_color_matrix=bt709:out_range=full:out_primaries=bt709:out_transfer=bt709",
-        args.fps,
-        format::yuv::YuvHeader::MAX_WIDTH,
-        format::yuv::YuvHeader::MAX_HEIGHT
+        args.fps, board.width, board.height
     );
     if let Some(subtitles) = args.subtitles {
         filter.insert_str(
@@ -133,17 +196,16 @@ fn encode_yuv(args: Args) -> Result<(), Box<dyn Error>> {
         ])
         .output()?;
     let (width, height) = parse_output(result)?;
+    check_size(&board, width, height)?;
     let header = format::yuv::YuvHeader::new(width, height, args.fps);
     prepend_header(args.output, header)?;
     Ok(())
 }
 
-fn encode_rgb(args: Args) -> Result<(), Box<dyn Error>> {
+fn encode_rgb(args: Args, board: Board) -> Result<(), Box<dyn Error>> {
     let mut filter = format!(
         "framerate={},scale=size={}x{}:force_original_aspect_ratio=decrease:reset_sar=1:out_color_matrix=bt709:out_range=full:out_primaries=bt709:out_transfer=bt709",
-        args.fps,
-        format::yuv::YuvHeader::MAX_WIDTH,
-        format::yuv::YuvHeader::MAX_HEIGHT
+        args.fps, board.width, board.height
     );
     if let Some(subtitles) = args.subtitles {
         filter.insert_str(
@@ -170,6 +232,7 @@ fn encode_rgb(args: Args) -> Result<(), Box<dyn Error>> {
         ])
         .output()?;
     let (width, height) = parse_output(result)?;
+    check_size(&board, width, height)?;
     let header = format::yuv::YuvHeader::new(width, height, args.fps);
     prepend_header(args.output, header)?;
     Ok(())
@@ -0,0 +1,55 @@
+use cyd_encoder::playlist::{self, Entry, EntryOptions};
+use std::{
+    error::Error,
+    fs::OpenOptions,
+    io::{Seek, SeekFrom, Write},
+};
+
+#[derive(argh::FromArgs)]
+/// Append videos to a playlist
+struct Args {
+    #[argh(option, default = "1u16")]
+    /// number of times to play each video
+    repeat: u16,
+    #[argh(option, default = "0u32")]
+    /// offset in seconds to start playing each video at
+    start: u32,
+    #[argh(option)]
+    /// maximum number of seconds to play each video
+    duration: Option<u32>,
+    #[argh(positional)]
+    /// playlist file, e.g. PLAYLIST.M3U
+    playlist: String,
+    #[argh(positional)]
+    /// video paths relative to the video directory
+    videos: Vec<String>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Args = argh::from_env();
+    if args.repeat == 0 {
+        return Err("repeat must be at least 1".into());
+    }
+    let options = EntryOptions {
+        repeat: args.repeat,
+        start: args.start,
+        duration: args.duration,
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.playlist)?;
+    if file.seek(SeekFrom::End(0))? == 0 {
+        writeln!(file, "{}", playlist::HEADER)?;
+    }
+    for video in &args.videos {
+        let path = video.replace('\\', "/");
+        let entry = Entry {
+            path: &path,
+            options,
+        };
+        write!(file, "{entry}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! CYD boards the player runs on and the size of their panels.
+//!
+//! Videos are scaled to fit the panel of the board they are encoded for.
+
+/// Board with its panel size in landscape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Board {
+    /// Model number without the `ESP32-` prefix, in lower case
+    pub name: &'static str,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Board {
+    /// Whether `width`x`height` frames fit the panel
+    pub fn fits(&self, width: u16, height: u16) -> bool {
+        width > 0 && height > 0 && width <= self.width && height <= self.height
+    }
+}
+
+/// 2.8" ESP32-2432S028R with ILI9341 or ST7789
+pub const ESP32_2432S028R: Board = Board {
+    name: "2432s028r",
+    width: 320,
+    height: 240,
+};
+
+/// 2.4" ESP32-2432S024R
+pub const ESP32_2432S024R: Board = Board {
+    name: "2432s024r",
+    width: 320,
+    height: 240,
+};
+
+/// 3.5" ESP32-3248S035R with ST7796
+pub const ESP32_3248S035R: Board = Board {
+    name: "3248s035r",
+    width: 480,
+    height: 320,
+};
+
+pub const BOARDS: [Board; 3] = [ESP32_2432S028R, ESP32_2432S024R, ESP32_3248S035R];
+
+/// Board named `name`, with or without the `ESP32-` prefix
+pub fn find(name: &str) -> Option<Board> {
+    let name = name
+        .get(..6)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("esp32-"))
+        .map_or(name, |_| &name[6..]);
+    BOARDS
+        .into_iter()
+        .find(|board| board.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(find("2432s024r"), Some(ESP32_2432S024R));
+        assert_eq!(find("ESP32-3248S035R"), Some(ESP32_3248S035R));
+        assert_eq!(find("esp32-"), None);
+        assert_eq!(find("3248"), None);
+    }
+
+    #[test]
+    fn frames_fit_the_panel() {
+        assert!(ESP32_2432S028R.fits(320, 240));
+        assert!(ESP32_2432S028R.fits(320, 180));
+        assert!(!ESP32_2432S028R.fits(480, 320));
+        assert!(!ESP32_2432S028R.fits(320, 0));
+        assert!(ESP32_3248S035R.fits(480, 320));
+    }
+}
@@ -1,7 +1,4 @@
 pub trait FormatHeader<const HEADER_SIZE: usize> {
-    const MAX_WIDTH: usize;
-    const MAX_HEIGHT: usize;
-
     fn parse(header: &[u8; HEADER_SIZE]) -> Self;
     fn encode(&self, header: &mut [u8; HEADER_SIZE]);
     fn header_size() -> usize {
@@ -10,6 +7,7 @@ pub trait FormatHeader<const HEADER_SIZE: usize> {
     fn fps(&self) -> u8;
 }
 
+pub mod metadata;
 pub mod mjpeg;
 pub mod rgb;
 pub mod size_fps;
@@ -0,0 +1,27 @@
+// Optional sidecar files stored next to a video with the same base name.
+// `NAME.TXT` holds the title on its first line, `NAME.JPG` a poster thumbnail.
+
+pub const TITLE_EXTENSION: &str = "TXT";
+pub const POSTER_EXTENSION: &str = "JPG";
+
+pub const MAX_TITLE_LEN: usize = 32;
+
+pub const POSTER_WIDTH: usize = 80;
+pub const POSTER_HEIGHT: usize = 60;
+// Largest poster JPEG the player will load
+pub const MAX_POSTER_SIZE: usize = 8 * 1024;
+
+/// Extract the title from the contents of a title file
+pub fn parse_title(contents: &[u8]) -> &str {
+    let line = contents
+        .split(|&b| b == b'\n' || b == b'\r')
+        .next()
+        .unwrap_or_default();
+    let line = &line[..line.len().min(MAX_TITLE_LEN)];
+    // Truncation may split a UTF-8 sequence, keep the valid prefix
+    let line = match str::from_utf8(line) {
+        Ok(line) => line,
+        Err(e) => str::from_utf8(&line[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    line.trim()
+}
@@ -21,9 +21,6 @@ impl SizeFpsHeader {
 }
 
 impl FormatHeader<5> for SizeFpsHeader {
-    const MAX_WIDTH: usize = 320;
-    const MAX_HEIGHT: usize = 240;
-
     fn parse(header: &[u8; 5]) -> Self {
         let width = u16::from_le_bytes([header[0], header[1]]);
         let height = u16::from_le_bytes([header[2], header[3]]);
@@ -1,3 +1,20 @@
 use super::size_fps::SizeFpsHeader;
 
 pub type YuvHeader = SizeFpsHeader;
+
+/// Matrix the YUV values were encoded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matrix {
+    Bt601,
+    /// Used by the encoder
+    Bt709,
+}
+
+/// Range of the YUV values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorRange {
+    /// 0-255, used by the encoder
+    Full,
+    /// 16-235 luma and 16-240 chroma
+    Limited,
+}
@@ -1,3 +1,6 @@
 #![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
 
+pub mod board;
 pub mod format;
+pub mod playlist;
+pub mod settings;
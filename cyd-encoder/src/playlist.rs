@@ -0,0 +1,297 @@
+//! M3U style playlists.
+//!
+//! Each non-comment line is the path of a video relative to the video directory.
+//! A `#CYD:` line sets options for the following entry, e.g.
+//!
+//! ```text
+//! #EXTM3U
+//! #CYD:repeat=2,start=0:10,duration=30
+//! INTRO.MJP
+//! CLIPS/CLIP1.MJP
+//! ```
+//!
+//! Times are seconds, `MM:SS` or `HH:MM:SS`.
+
+use core::fmt;
+
+pub const DEFAULT_NAME: &str = "PLAYLIST.M3U";
+pub const EXTENSION: &str = "CYP";
+pub const HEADER: &str = "#EXTM3U";
+const OPTIONS_PREFIX: &str = "#CYD:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryOptions {
+    /// Number of times to play the entry
+    pub repeat: u16,
+    /// Offset in seconds to start playing at
+    pub start: u32,
+    /// Maximum number of seconds to play
+    pub duration: Option<u32>,
+}
+
+impl Default for EntryOptions {
+    fn default() -> Self {
+        Self {
+            repeat: 1,
+            start: 0,
+            duration: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub path: &'a str,
+    pub options: EntryOptions,
+}
+
+impl<'a> Entry<'a> {
+    pub fn new(path: &'a str) -> Self {
+        Self {
+            path,
+            options: EntryOptions::default(),
+        }
+    }
+
+    /// Iterate the folder names and file name of the path
+    pub fn components(&self) -> impl Iterator<Item = &'a str> {
+        self.path.split(['/', '\\'])
+    }
+}
+
+impl fmt::Display for Entry<'_> {
+    /// Format as playlist lines, including a trailing newline
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let defaults = EntryOptions::default();
+        if self.options != defaults {
+            f.write_str(OPTIONS_PREFIX)?;
+            let mut separator = "";
+            if self.options.repeat != defaults.repeat {
+                write!(f, "repeat={}", self.options.repeat)?;
+                separator = ",";
+            }
+            if self.options.start != defaults.start {
+                write!(f, "{separator}start={}", self.options.start)?;
+                separator = ",";
+            }
+            if let Some(duration) = self.options.duration {
+                write!(f, "{separator}duration={duration}")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "{}", self.path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownOption,
+    InvalidValue,
+    InvalidPath,
+}
+
+/// Parses a playlist one line at a time
+#[derive(Debug, Default)]
+pub struct Parser {
+    options: EntryOptions,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a line, returning an entry if the line is a path.
+    /// An invalid options line is ignored entirely.
+    pub fn parse_line<'a>(&mut self, line: &'a str) -> Result<Option<Entry<'a>>, ParseError> {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if let Some(options) = line.strip_prefix(OPTIONS_PREFIX) {
+            self.options = parse_options(options, self.options)?;
+            return Ok(None);
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let path = line.strip_prefix("./").unwrap_or(line);
+        let entry = Entry {
+            path,
+            options: core::mem::take(&mut self.options),
+        };
+        if entry
+            .components()
+            .any(|component| component.is_empty() || component == "." || component == "..")
+        {
+            return Err(ParseError::InvalidPath);
+        }
+        Ok(Some(entry))
+    }
+}
+
+fn parse_options(options: &str, mut parsed: EntryOptions) -> Result<EntryOptions, ParseError> {
+    for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let (key, value) = option.split_once('=').ok_or(ParseError::InvalidValue)?;
+        let value = value.trim();
+        match key.trim() {
+            "repeat" => {
+                parsed.repeat = value
+                    .parse()
+                    .ok()
+                    .filter(|&repeat| repeat > 0)
+                    .ok_or(ParseError::InvalidValue)?;
+            }
+            "start" => parsed.start = parse_time(value)?,
+            "duration" => parsed.duration = Some(parse_time(value)?),
+            _ => return Err(ParseError::UnknownOption),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Parse seconds, `MM:SS` or `HH:MM:SS`
+fn parse_time(value: &str) -> Result<u32, ParseError> {
+    let mut seconds: u32 = 0;
+    let mut fields = 0;
+    for field in value.split(':') {
+        fields += 1;
+        let field: u32 = field.parse().map_err(|_| ParseError::InvalidValue)?;
+        if fields > 1 && field >= 60 {
+            return Err(ParseError::InvalidValue);
+        }
+        seconds = seconds
+            .checked_mul(60)
+            .and_then(|s| s.checked_add(field))
+            .ok_or(ParseError::InvalidValue)?;
+    }
+    if fields > 3 {
+        return Err(ParseError::InvalidValue);
+    }
+    Ok(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &str) -> Vec<Result<Option<Entry<'_>>, ParseError>> {
+        let mut parser = Parser::new();
+        lines.lines().map(|line| parser.parse_line(line)).collect()
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        assert_eq!(
+            parse("\u{feff}#EXTM3U\n\n   \n#EXTINF:10,Intro\n# comment"),
+            [Ok(None), Ok(None), Ok(None), Ok(None), Ok(None)]
+        );
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            parse("INTRO.MJP\n  ./CLIPS/CLIP1.MJP  \nCLIPS\\CLIP2.MJP"),
+            [
+                Ok(Some(Entry::new("INTRO.MJP"))),
+                Ok(Some(Entry::new("CLIPS/CLIP1.MJP"))),
+                Ok(Some(Entry::new("CLIPS\\CLIP2.MJP"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_paths() {
+        for path in [
+            "../SECRET.MJP",
+            "CLIPS//CLIP.MJP",
+            "CLIPS/./CLIP.MJP",
+            "/CLIP.MJP",
+        ] {
+            assert_eq!(parse(path), [Err(ParseError::InvalidPath)], "{path}");
+        }
+    }
+
+    #[test]
+    fn options_apply_to_the_next_entry() {
+        let options = EntryOptions {
+            repeat: 2,
+            start: 10,
+            duration: Some(3723),
+        };
+        assert_eq!(
+            parse("#CYD: repeat = 2, start=0:10 ,duration=1:02:03,\nA.MJP\nB.MJP"),
+            [
+                Ok(None),
+                Ok(Some(Entry {
+                    path: "A.MJP",
+                    options
+                })),
+                Ok(Some(Entry::new("B.MJP"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_options() {
+        for (line, error) in [
+            ("#CYD:loop=2", ParseError::UnknownOption),
+            ("#CYD:repeat", ParseError::InvalidValue),
+            ("#CYD:repeat=0", ParseError::InvalidValue),
+            ("#CYD:repeat=-1", ParseError::InvalidValue),
+            ("#CYD:repeat=65536", ParseError::InvalidValue),
+            ("#CYD:start=ten", ParseError::InvalidValue),
+        ] {
+            assert_eq!(parse(line), [Err(error)], "{line}");
+        }
+    }
+
+    #[test]
+    fn bad_options_line_is_ignored() {
+        assert_eq!(
+            parse("#CYD:repeat=3\n#CYD:start=5,loop=2\nA.MJP"),
+            [
+                Ok(None),
+                Err(ParseError::UnknownOption),
+                Ok(Some(Entry {
+                    path: "A.MJP",
+                    options: EntryOptions {
+                        repeat: 3,
+                        ..EntryOptions::default()
+                    }
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("0"), Ok(0));
+        assert_eq!(parse_time("90"), Ok(90));
+        assert_eq!(parse_time("1:30"), Ok(90));
+        assert_eq!(parse_time("1:00:00"), Ok(3600));
+        assert_eq!(parse_time("100:00"), Ok(6000));
+    }
+
+    #[test]
+    fn bad_numbers() {
+        for time in [
+            "",
+            ":",
+            "1:",
+            "1:60",
+            "1:2:60",
+            "1:2:3:4",
+            "-1",
+            "1.5",
+            "99999999999",
+        ] {
+            assert_eq!(parse_time(time), Err(ParseError::InvalidValue), "{time}");
+        }
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let text = "#CYD:repeat=2,duration=30\nCLIPS/A.MJP\nB.MJP\n";
+        let entries: Vec<_> = parse(text).into_iter().flatten().flatten().collect();
+        let written: String = entries.iter().map(ToString::to_string).collect();
+        assert_eq!(written, text);
+    }
+}
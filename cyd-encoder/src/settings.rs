@@ -0,0 +1,543 @@
+//! Player settings read from `CYD.CFG` in the SD card root.
+//!
+//! The file contains `key = value` lines, optionally grouped in `[section]`s.
+//! Lines starting with `#` or `;` are comments. For example
+//!
+//! ```text
+//! directory = CLIPS
+//! shuffle = true
+//! repeat = all
+//! brightness = 80
+//!
+//! [touch]
+//! tap_center = stop
+//! ```
+
+use crate::format::yuv::{ColorRange, Matrix};
+
+pub const FILENAME: &str = "CYD.CFG";
+
+// Longest directory name in 8.3 format
+const MAX_DIRECTORY_LEN: usize = 8;
+const MAX_CURVE_POINTS: usize = 8;
+
+/// Player actions that touch gestures can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Previous,
+    Next,
+    TogglePause,
+    Stop,
+    BrightnessUp,
+    BrightnessDown,
+}
+
+impl Action {
+    fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "none" => Action::None,
+            "previous" => Action::Previous,
+            "next" => Action::Next,
+            "pause" => Action::TogglePause,
+            "stop" => Action::Stop,
+            "brightness_up" => Action::BrightnessUp,
+            "brightness_down" => Action::BrightnessDown,
+            _ => return None,
+        })
+    }
+}
+
+/// What to play when a video started from the menu ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// Return to the menu
+    None,
+    /// Play the same video again
+    One,
+    /// Play the next video
+    All,
+}
+
+/// What to do when a video can't be played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Show the error briefly and play the next video
+    Skip,
+    /// Show the error until the player is restarted
+    Halt,
+}
+
+/// When to show the on-screen display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlay {
+    Off,
+    /// For a few seconds after the video starts or the screen is touched
+    Touch,
+    /// All the time, to watch the frame rate while debugging
+    Always,
+}
+
+/// Backlight brightness by ambient light, as points of light and brightness in percent.
+///
+/// Brightness is interpolated between the points and constant beyond the first and last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrightnessCurve {
+    points: [(u8, u8); MAX_CURVE_POINTS],
+    len: usize,
+}
+
+impl Default for BrightnessCurve {
+    fn default() -> Self {
+        let mut points = [(0, 0); MAX_CURVE_POINTS];
+        points[..3].copy_from_slice(&[(0, 10), (30, 50), (70, 100)]);
+        Self { points, len: 3 }
+    }
+}
+
+impl BrightnessCurve {
+    /// Points in order of increasing light
+    pub fn points(&self) -> &[(u8, u8)] {
+        &self.points[..self.len]
+    }
+
+    /// Parse `light:brightness` pairs separated by commas, in order of increasing light
+    fn parse(value: &str) -> Result<Self, SettingsError> {
+        let mut curve = Self {
+            points: [(0, 0); MAX_CURVE_POINTS],
+            len: 0,
+        };
+        for point in value.split(',') {
+            let (light, brightness) = point.split_once(':').ok_or(SettingsError::InvalidValue)?;
+            let point = (
+                parse_percent(light.trim())?,
+                parse_percent(brightness.trim())?,
+            );
+            let increasing = curve
+                .points()
+                .last()
+                .is_none_or(|&(last, _)| last < point.0);
+            if curve.len == MAX_CURVE_POINTS || !increasing {
+                return Err(SettingsError::InvalidValue);
+            }
+            curve.points[curve.len] = point;
+            curve.len += 1;
+        }
+        Ok(curve)
+    }
+}
+
+/// Actions bound to touch gestures, `None` keeps the player default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TouchSettings {
+    pub tap_left: Option<Action>,
+    pub tap_center: Option<Action>,
+    pub tap_right: Option<Action>,
+    pub double_tap: Option<Action>,
+    pub long_press: Option<Action>,
+    pub swipe_left: Option<Action>,
+    pub swipe_right: Option<Action>,
+    pub swipe_up: Option<Action>,
+    pub swipe_down: Option<Action>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    directory: [u8; MAX_DIRECTORY_LEN],
+    directory_len: usize,
+    pub shuffle: bool,
+    pub repeat: Repeat,
+    /// Backlight brightness in percent
+    pub brightness: u8,
+    /// Follow the ambient light with the backlight brightness
+    pub auto_brightness: bool,
+    pub brightness_curve: BrightnessCurve,
+    /// Display rotation in degrees, 90 or 270
+    pub rotation: u16,
+    pub touch_enabled: bool,
+    pub touch: TouchSettings,
+    pub overlay: Overlay,
+    /// Volume in percent, for players with audio output. The CYD player plays no audio and ignores it.
+    pub volume: u8,
+    /// Sleep lightly while waiting with the backlight off
+    pub light_sleep: bool,
+    /// Seconds without playing or touching before deep sleep, 0 never sleeps
+    pub sleep_after: u32,
+    pub on_error: OnError,
+    /// Append errors to the log file in the video directory
+    pub error_log: bool,
+    /// Append timing statistics of each video to a CSV file in the video directory
+    pub stats: bool,
+    /// How `yuv` videos were encoded
+    pub yuv_matrix: Matrix,
+    pub yuv_range: ColorRange,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            directory: [0; MAX_DIRECTORY_LEN],
+            directory_len: 0,
+            shuffle: false,
+            repeat: Repeat::None,
+            brightness: 100,
+            auto_brightness: false,
+            brightness_curve: BrightnessCurve::default(),
+            rotation: 270,
+            touch_enabled: true,
+            touch: TouchSettings::default(),
+            overlay: Overlay::Off,
+            volume: 50,
+            light_sleep: false,
+            sleep_after: 0,
+            on_error: OnError::Skip,
+            error_log: false,
+            stats: false,
+            yuv_matrix: Matrix::Bt709,
+            yuv_range: ColorRange::Full,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsError {
+    InvalidLine,
+    UnknownSection,
+    UnknownKey,
+    InvalidValue,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Player,
+    Touch,
+}
+
+impl Settings {
+    /// Parse settings, starting from the defaults.
+    /// Invalid lines are reported to `on_error` with their line number and otherwise ignored.
+    pub fn parse(contents: &str, mut on_error: impl FnMut(usize, SettingsError)) -> Self {
+        let mut settings = Self::default();
+        let mut section = Some(Section::Player);
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim_start_matches('\u{feff}').trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name.trim() {
+                    "player" => Some(Section::Player),
+                    "touch" => Some(Section::Touch),
+                    _ => {
+                        on_error(index + 1, SettingsError::UnknownSection);
+                        None
+                    }
+                };
+                continue;
+            }
+            let Some(section) = section else {
+                // Already reported the unknown section
+                continue;
+            };
+            let result = match line.split_once('=') {
+                Some((key, value)) => settings.set(section, key.trim(), value.trim()),
+                None => Err(SettingsError::InvalidLine),
+            };
+            if let Err(e) = result {
+                on_error(index + 1, e);
+            }
+        }
+        settings
+    }
+
+    /// Video directory name, or `None` to use the default for the video format
+    pub fn directory(&self) -> Option<&str> {
+        str::from_utf8(&self.directory[..self.directory_len])
+            .ok()
+            .filter(|directory| !directory.is_empty())
+    }
+
+    fn set(&mut self, section: Section, key: &str, value: &str) -> Result<(), SettingsError> {
+        match section {
+            Section::Player => match key {
+                "directory" => self.set_directory(value)?,
+                "shuffle" => self.shuffle = parse_bool(value)?,
+                "repeat" => {
+                    self.repeat = match value {
+                        "none" => Repeat::None,
+                        "one" => Repeat::One,
+                        "all" => Repeat::All,
+                        _ => return Err(SettingsError::InvalidValue),
+                    }
+                }
+                "brightness" => self.brightness = parse_percent(value)?,
+                "auto_brightness" => self.auto_brightness = parse_bool(value)?,
+                "brightness_curve" => self.brightness_curve = BrightnessCurve::parse(value)?,
+                "rotation" => {
+                    self.rotation = match value {
+                        "90" => 90,
+                        "270" => 270,
+                        _ => return Err(SettingsError::InvalidValue),
+                    }
+                }
+                "overlay" => {
+                    self.overlay = match value {
+                        "touch" => Overlay::Touch,
+                        "always" => Overlay::Always,
+                        _ if parse_bool(value)? => Overlay::Touch,
+                        _ => Overlay::Off,
+                    }
+                }
+                "volume" => self.volume = parse_percent(value)?,
+                "light_sleep" => self.light_sleep = parse_bool(value)?,
+                "sleep_after" => {
+                    self.sleep_after = value.parse().map_err(|_| SettingsError::InvalidValue)?
+                }
+                "on_error" => {
+                    self.on_error = match value {
+                        "skip" => OnError::Skip,
+                        "halt" => OnError::Halt,
+                        _ => return Err(SettingsError::InvalidValue),
+                    }
+                }
+                "error_log" => self.error_log = parse_bool(value)?,
+                "stats" => self.stats = parse_bool(value)?,
+                "yuv_matrix" => {
+                    self.yuv_matrix = match value {
+                        "bt601" => Matrix::Bt601,
+                        "bt709" => Matrix::Bt709,
+                        _ => return Err(SettingsError::InvalidValue),
+                    }
+                }
+                "yuv_range" => {
+                    self.yuv_range = match value {
+                        "full" => ColorRange::Full,
+                        "limited" => ColorRange::Limited,
+                        _ => return Err(SettingsError::InvalidValue),
+                    }
+                }
+                _ => return Err(SettingsError::UnknownKey),
+            },
+            Section::Touch => {
+                if key == "enabled" {
+                    self.touch_enabled = parse_bool(value)?;
+                    return Ok(());
+                }
+                let binding = match key {
+                    "tap_left" => &mut self.touch.tap_left,
+                    "tap_center" => &mut self.touch.tap_center,
+                    "tap_right" => &mut self.touch.tap_right,
+                    "double_tap" => &mut self.touch.double_tap,
+                    "long_press" => &mut self.touch.long_press,
+                    "swipe_left" => &mut self.touch.swipe_left,
+                    "swipe_right" => &mut self.touch.swipe_right,
+                    "swipe_up" => &mut self.touch.swipe_up,
+                    "swipe_down" => &mut self.touch.swipe_down,
+                    _ => return Err(SettingsError::UnknownKey),
+                };
+                *binding = Some(Action::parse(value).ok_or(SettingsError::InvalidValue)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_directory(&mut self, value: &str) -> Result<(), SettingsError> {
+        let value = value.trim_matches('/');
+        if value.len() > MAX_DIRECTORY_LEN
+            || !value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        {
+            return Err(SettingsError::InvalidValue);
+        }
+        self.directory[..value.len()].copy_from_slice(value.as_bytes());
+        self.directory[..value.len()].make_ascii_uppercase();
+        self.directory_len = value.len();
+        Ok(())
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, SettingsError> {
+    match value {
+        "true" | "on" | "yes" | "1" => Ok(true),
+        "false" | "off" | "no" | "0" => Ok(false),
+        _ => Err(SettingsError::InvalidValue),
+    }
+}
+
+fn parse_percent(value: &str) -> Result<u8, SettingsError> {
+    value
+        .parse()
+        .ok()
+        .filter(|&percent| percent <= 100)
+        .ok_or(SettingsError::InvalidValue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> (Settings, Vec<(usize, SettingsError)>) {
+        let mut errors = Vec::new();
+        let settings = Settings::parse(contents, |line, error| errors.push((line, error)));
+        (settings, errors)
+    }
+
+    #[test]
+    fn defaults() {
+        let (settings, errors) = parse("");
+        assert_eq!(settings, Settings::default());
+        assert_eq!(errors, []);
+        assert_eq!(settings.directory(), None);
+        assert_eq!(settings.repeat, Repeat::None);
+        assert_eq!(settings.brightness, 100);
+        assert!(settings.touch_enabled);
+        assert_eq!(
+            settings.brightness_curve.points(),
+            [(0, 10), (30, 50), (70, 100)]
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let (settings, errors) = parse("\u{feff}# comment\n\n  ; comment\n\t\n");
+        assert_eq!(settings, Settings::default());
+        assert_eq!(errors, []);
+    }
+
+    #[test]
+    fn values() {
+        let (settings, errors) = parse(
+            "directory = /clips/\n\
+             shuffle=yes\n\
+             repeat = all\n\
+             brightness = 80\n\
+             brightness_curve = 0:5, 50:60 ,100:100\n\
+             rotation = 90\n\
+             overlay = always\n\
+             light_sleep = on\n\
+             sleep_after = 300\n\
+             on_error = halt\n\
+             yuv_matrix = bt601\n\
+             yuv_range = limited\n\
+             [touch]\n\
+             enabled = off\n\
+             tap_center = stop\n\
+             swipe_up = none\n\
+             [player]\n\
+             stats = 1\n",
+        );
+        assert_eq!(errors, []);
+        assert_eq!(settings.directory(), Some("CLIPS"));
+        assert!(settings.shuffle);
+        assert_eq!(settings.repeat, Repeat::All);
+        assert_eq!(settings.brightness, 80);
+        assert_eq!(
+            settings.brightness_curve.points(),
+            [(0, 5), (50, 60), (100, 100)]
+        );
+        assert_eq!(settings.rotation, 90);
+        assert_eq!(settings.overlay, Overlay::Always);
+        assert!(settings.light_sleep);
+        assert_eq!(settings.sleep_after, 300);
+        assert_eq!(settings.on_error, OnError::Halt);
+        assert_eq!(settings.yuv_matrix, Matrix::Bt601);
+        assert_eq!(settings.yuv_range, ColorRange::Limited);
+        assert!(!settings.touch_enabled);
+        assert_eq!(settings.touch.tap_center, Some(Action::Stop));
+        assert_eq!(settings.touch.swipe_up, Some(Action::None));
+        assert_eq!(settings.touch.tap_left, None);
+        assert!(settings.stats);
+    }
+
+    #[test]
+    fn overlay_as_bool() {
+        assert_eq!(parse("overlay = true").0.overlay, Overlay::Touch);
+        assert_eq!(parse("overlay = off").0.overlay, Overlay::Off);
+    }
+
+    #[test]
+    fn unknown_keys_and_sections() {
+        let (settings, errors) = parse(
+            "colour = blue\n\
+             [audio]\n\
+             brightness = 10\n\
+             [touch]\n\
+             brightness = 20\n\
+             tap_left = stop\n\
+             [ player ]\n\
+             brightness = 30\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                (1, SettingsError::UnknownKey),
+                (2, SettingsError::UnknownSection),
+                (5, SettingsError::UnknownKey),
+            ]
+        );
+        assert_eq!(settings.touch.tap_left, Some(Action::Stop));
+        assert_eq!(settings.brightness, 30);
+    }
+
+    #[test]
+    fn invalid_values_keep_defaults() {
+        let (settings, errors) = parse(
+            "brightness = 101\n\
+             shuffle = maybe\n\
+             repeat = twice\n\
+             rotation = 180\n\
+             directory = TOO_LONG_NAME\n\
+             directory = A/B\n\
+             sleep_after = -1\n\
+             brightness_curve = 50:50, 40:60\n\
+             brightness_curve = 0:10\n\
+             brightness = \n\
+             just some text\n\
+             [touch]\n\
+             tap_left = jump\n",
+        );
+        let invalid = |line| (line, SettingsError::InvalidValue);
+        assert_eq!(
+            errors,
+            [
+                invalid(1),
+                invalid(2),
+                invalid(3),
+                invalid(4),
+                invalid(5),
+                invalid(6),
+                invalid(7),
+                invalid(8),
+                invalid(10),
+                (11, SettingsError::InvalidLine),
+                invalid(13),
+            ]
+        );
+        assert_eq!(
+            settings,
+            Settings {
+                brightness_curve: BrightnessCurve::parse("0:10").unwrap(),
+                ..Settings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn brightness_curve_limits() {
+        assert!(BrightnessCurve::parse("0:0,10:10,20:20,30:30,40:40,50:50,60:60,70:70").is_ok());
+        assert_eq!(
+            BrightnessCurve::parse("0:0,10:10,20:20,30:30,40:40,50:50,60:60,70:70,80:80"),
+            Err(SettingsError::InvalidValue)
+        );
+        assert_eq!(
+            BrightnessCurve::parse("10:10,10:20"),
+            Err(SettingsError::InvalidValue)
+        );
+        assert_eq!(
+            BrightnessCurve::parse("10:101"),
+            Err(SettingsError::InvalidValue)
+        );
+        assert_eq!(BrightnessCurve::parse(""), Err(SettingsError::InvalidValue));
+    }
+}
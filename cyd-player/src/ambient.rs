@@ -0,0 +1,66 @@
+//! Ambient light from the LDR next to the display, sampled by ADC1
+
+use cyd_core::ambient::{AutoBrightness, FilterConfig};
+use cyd_encoder::settings::BrightnessCurve;
+use esp_hal::{
+    Blocking,
+    analog::adc::{Adc, AdcConfig, AdcPin, Attenuation},
+    peripherals::{ADC1, GPIO34},
+    time::{Duration, Instant},
+};
+
+// The smoothing spans about a second of readings
+const SAMPLE_PERIOD: Duration = Duration::from_millis(100);
+
+pub struct LightSensor {
+    adc: Adc<'static, ADC1<'static>, Blocking>,
+    pin: AdcPin<GPIO34<'static>, ADC1<'static>>,
+}
+
+impl LightSensor {
+    pub fn new(adc: ADC1<'static>, pin: GPIO34<'static>) -> Self {
+        let mut config = AdcConfig::new();
+        let pin = config.enable_pin(pin, Attenuation::_11dB);
+        Self {
+            adc: Adc::new(adc, config),
+            pin,
+        }
+    }
+
+    /// 12-bit reading, lower when brighter
+    pub fn read(&mut self) -> u16 {
+        loop {
+            if let Ok(reading) = self.adc.read_oneshot(&mut self.pin) {
+                return reading;
+            }
+        }
+    }
+}
+
+/// Backlight brightness following the ambient light
+pub struct AmbientLight {
+    sensor: LightSensor,
+    auto: AutoBrightness,
+    next_sample: Instant,
+}
+
+impl AmbientLight {
+    pub fn new(sensor: LightSensor, curve: BrightnessCurve) -> Self {
+        Self {
+            sensor,
+            auto: AutoBrightness::new(FilterConfig::default(), curve),
+            next_sample: Instant::now(),
+        }
+    }
+
+    /// Sample the light when due, returns the new brightness when it changes
+    pub fn update(&mut self) -> Option<u8> {
+        let now = Instant::now();
+        if now < self.next_sample {
+            return None;
+        }
+        self.next_sample = now + SAMPLE_PERIOD;
+        let reading = self.sensor.read();
+        self.auto.update(reading)
+    }
+}
@@ -0,0 +1,153 @@
+//! Backlight dimmed by PWM from the LEDC peripheral
+
+use esp_hal::{
+    delay::Delay,
+    gpio::DriveMode,
+    ledc::{
+        LSGlobalClkSource, Ledc, LowSpeed,
+        channel::{self, Channel, ChannelHW, ChannelIFace},
+        timer::{self, Timer, TimerIFace},
+    },
+    peripherals::LEDC,
+    time::{Instant, Rate},
+};
+
+use crate::{ambient::AmbientLight, board::BacklightPin};
+use cyd_core::fade::{self, Fade};
+
+const DUTY_BITS: timer::config::Duty = timer::config::Duty::Duty10Bit;
+const MAX_DUTY: u32 = 1 << DUTY_BITS as u32;
+// Fast enough not to flicker, slow enough for 10 bits from the APB clock
+const FREQUENCY: Rate = Rate::from_khz(5);
+
+// From off to fully on and back
+const FADE_IN_MS: u32 = 500;
+const FADE_OUT_MS: u32 = 300;
+const FADE_STEP_MS: u32 = 5;
+
+/// PWM timer for the backlight, which borrows it
+pub struct Pwm {
+    _ledc: Ledc<'static>,
+    timer: Timer<'static, LowSpeed>,
+}
+
+impl Pwm {
+    pub fn new(ledc: LEDC<'static>) -> Self {
+        let mut ledc = Ledc::new(ledc);
+        ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+        let mut timer = ledc.timer::<LowSpeed>(timer::Number::Timer0);
+        timer
+            .configure(timer::config::Config {
+                duty: DUTY_BITS,
+                clock_source: timer::LSClockSource::APBClk,
+                frequency: FREQUENCY,
+            })
+            .expect("backlight timer");
+        Self { _ledc: ledc, timer }
+    }
+}
+
+pub struct Backlight<'a> {
+    channel: Channel<'a, LowSpeed>,
+    /// Brightness in percent when on
+    brightness: u8,
+    /// Current brightness
+    level: u8,
+    fade: Option<Fade>,
+    ambient: Option<AmbientLight>,
+}
+
+impl<'a> Backlight<'a> {
+    /// Backlight on `pin`, which starts off
+    pub fn new(pwm: &'a Pwm, pin: BacklightPin) -> Self {
+        let mut channel = Channel::new(channel::Number::Channel0, pin);
+        channel
+            .configure(channel::config::Config {
+                timer: &pwm.timer,
+                duty_pct: 0,
+                drive_mode: DriveMode::PushPull,
+            })
+            .expect("backlight channel");
+        Self {
+            channel,
+            brightness: 100,
+            level: 0,
+            fade: None,
+            ambient: None,
+        }
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Set the brightness when on, right away unless the backlight is off or fading
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        match self.fade {
+            Some(fade) if fade.target() > 0 => self.fade_in(),
+            Some(_) => {}
+            None if self.level > 0 => self.on(),
+            None => {}
+        }
+    }
+
+    /// Follow the ambient light, until the brightness is changed by hand
+    /// and the light changes again
+    pub fn set_ambient(&mut self, ambient: Option<AmbientLight>) {
+        self.ambient = ambient;
+    }
+
+    /// Whether the backlight is lit, also while fading
+    pub fn is_on(&self) -> bool {
+        self.level > 0
+    }
+
+    pub fn on(&mut self) {
+        self.fade = None;
+        self.set(self.brightness);
+    }
+
+    pub fn off(&mut self) {
+        self.fade = None;
+        self.set(0);
+    }
+
+    /// Start fading in, continued by [`Backlight::update`]
+    pub fn fade_in(&mut self) {
+        self.fade = Some(Fade::new(self.level, self.brightness, now_ms(), FADE_IN_MS));
+    }
+
+    /// Fade out and return once the backlight is off
+    pub fn fade_out(&mut self) {
+        self.fade = Some(Fade::new(self.level, 0, now_ms(), FADE_OUT_MS));
+        let delay = Delay::new();
+        while self.fade.is_some() {
+            self.update();
+            delay.delay_millis(FADE_STEP_MS);
+        }
+    }
+
+    /// Continue fading and follow the ambient light, call regularly
+    pub fn update(&mut self) {
+        if let Some(brightness) = self.ambient.as_mut().and_then(AmbientLight::update) {
+            self.set_brightness(brightness);
+        }
+        if let Some(fade) = self.fade {
+            let now_ms = now_ms();
+            self.set(fade.brightness(now_ms));
+            if fade.is_done(now_ms) {
+                self.fade = None;
+            }
+        }
+    }
+
+    fn set(&mut self, level: u8) {
+        self.level = level;
+        self.channel.set_duty_hw(fade::duty(level, MAX_DUTY));
+    }
+}
+
+fn now_ms() -> u32 {
+    Instant::now().duration_since_epoch().as_millis() as u32
+}
@@ -7,22 +7,32 @@
 )]
 #![deny(clippy::large_stack_frames)]
 
-use core::ops::DerefMut;
-
-cfg_if::cfg_if! {
-    if #[cfg(feature = "yuv")] {
-        use cyd_player::video::yuv;
-    } else if #[cfg(feature = "rgb")] {
-        use cyd_player::video::rgb;
-    } else if #[cfg(feature = "mjpeg")] {
-        use cyd_player::video::mjpeg;
-    }
-}
+use core::{convert::Infallible, fmt, ops::DerefMut};
 
-use cyd_player::touch::TouchDetector;
-use embedded_sdmmc::ShortFileName;
+use cyd_core::{failures::Failures, stats::Stats};
+use cyd_encoder::settings::{OnError, Repeat, Settings};
+use cyd_player::{
+    ambient::{AmbientLight, LightSensor},
+    display::Display,
+    error::Error,
+    error_log,
+    library::{self, Folder, Library},
+    menu::{Menu, Selection},
+    osd::Osd,
+    playlist::Playlist,
+    power::Power,
+    resume::{Resume, ResumePoint},
+    sdcard::{self, DirectoryType, SdCard},
+    stats_log,
+    touch::Touch,
+    video::{
+        Playback, Played,
+        any::{self, AnyDecoder, DEFAULT_DIRECTORY, Format},
+    },
+};
+use embedded_sdmmc::{SdCardError, ShortFileName};
 use esp_backtrace as _;
-use esp_hal::clock::CpuClock;
+use esp_hal::{clock::CpuClock, delay::Delay, rng::Rng};
 
 #[cfg(feature = "alloc")]
 extern crate alloc;
@@ -31,6 +41,14 @@ extern crate alloc;
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
+const MOUNT_RETRY_MS: u32 = 1000;
+// How long to show why a video is skipped
+const SKIP_MESSAGE_MS: u32 = 2000;
+// Frames kept for timing percentiles
+const STATS_FRAMES: usize = 256;
+// Failed videos remembered to tell when none can be played
+const MAX_FAILURES: usize = 32;
+
 #[allow(
     clippy::large_stack_frames,
     reason = "it's not unusual to allocate larger buffers etc. in main"
@@ -49,8 +67,10 @@ fn main() -> ! {
     esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 98768);
 
     let mut display_buffer = [0u8; 5 * 1024];
+    let backlight_pwm = cyd_player::backlight::Pwm::new(peripherals.LEDC);
     let mut display = cyd_player::display::Display::new(
         &mut display_buffer,
+        &backlight_pwm,
         cyd_player::display::Peripherals {
             spi2: peripherals.SPI2,
             dc: peripherals.GPIO2,
@@ -59,9 +79,15 @@ fn main() -> ! {
             mosi: peripherals.GPIO13,
             sclk: peripherals.GPIO14,
             cs: peripherals.GPIO15,
+            #[cfg(not(any(feature = "board-2432s024r", feature = "board-3248s035r")))]
             bl: peripherals.GPIO21,
+            #[cfg(any(feature = "board-2432s024r", feature = "board-3248s035r"))]
+            bl: peripherals.GPIO27,
+            dma: peripherals.DMA_SPI2,
         },
     );
+    #[cfg(feature = "dual-core")]
+    let _decoder_core = cyd_player::dual_core::start(peripherals.CPU_CTRL);
     let mut sdcard = match cyd_player::sdcard::SdCard::new(cyd_player::sdcard::Peripherals {
         spi3: peripherals.SPI3,
         cs: peripherals.GPIO5,
@@ -73,76 +99,428 @@ fn main() -> ! {
         Err(e) => display.message(format_args!("SD card error: {e:?}")),
     };
 
-    let touch_detector = TouchDetector::new(peripherals.IO_MUX, peripherals.GPIO36);
+    let mut touch = Touch::new(
+        cyd_player::touch::Peripherals {
+            io_mux: peripherals.IO_MUX,
+            irq: peripherals.GPIO36,
+            #[cfg(not(feature = "touch-on-display-bus"))]
+            sclk: peripherals.GPIO25,
+            #[cfg(not(feature = "touch-on-display-bus"))]
+            mosi: peripherals.GPIO32,
+            #[cfg(not(feature = "touch-on-display-bus"))]
+            miso: peripherals.GPIO39,
+            cs: peripherals.GPIO33,
+        },
+        cyd_player::display::SIZE,
+    );
 
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "yuv")] {
-            const SUFFIX: &str = "YUV";
-        } else if #[cfg(feature = "rgb")] {
-            const SUFFIX: &str = "RGB";
-        } else if #[cfg(feature = "mjpeg")] {
-            const SUFFIX: &str= "MJP";
+    mount_card(&mut sdcard, &mut display, "Insert SD card");
+    let settings = loop {
+        match sdcard.open_root_directory(|root| Ok(cyd_player::settings::load(root)?)) {
+            Ok(settings) => break settings,
+            Err(e) => match e.into_card_fault() {
+                Ok(_) => mount_card(&mut sdcard, &mut display, "Reinsert SD card"),
+                Err(e) => display.message(format_args!("settings error: {e:?}")),
+            },
         }
+    };
+    display.apply(&settings);
+    let mut power = Power::new(peripherals.LPWR);
+    power.configure(&settings);
+    if settings.auto_brightness {
+        let sensor = LightSensor::new(peripherals.ADC1, peripherals.GPIO34);
+        display
+            .backlight()
+            .set_ambient(Some(AmbientLight::new(sensor, settings.brightness_curve)));
     }
+    touch.configure(&settings);
+    let rng = Rng::new();
 
-    log::info!("Loading dir {SUFFIX}");
-    if let Err(e) = sdcard.open_directory(SUFFIX, |directory| {
-        const MAX_FILES: usize = 5;
-        let mut filenames: [Option<ShortFileName>; MAX_FILES] = [None; _];
-        let mut index: usize = 0;
-        if let Err(e) = directory.iterate_dir(|entry| {
-            if index < MAX_FILES
-                && !entry.attributes.is_directory()
-                && entry.name.extension() == SUFFIX.as_bytes()
-            {
-                log::info!("Found {}", entry.name);
-                filenames[index] = Some(entry.name);
-                index += 1;
+    // Shared by all videos, sized for the largest frame of any format
+    let mut decode_buffer = [0u8; any::DECODE_SIZE];
+
+    let directory_name = settings.directory().unwrap_or(DEFAULT_DIRECTORY);
+    // Where playback was when the card failed, it may not have been saved yet
+    let mut position: Option<ResumePoint> = None;
+    loop {
+        log::info!("Loading dir {directory_name}");
+        let result = sdcard.open_directory(directory_name, |video_directory| {
+            let (resume, resume_point) = Resume::load(video_directory)?;
+            let resume_point = position
+                .take()
+                .or(resume_point)
+                .filter(|point| point.exists(video_directory));
+            let mut player = Player {
+                sdcard: &sdcard,
+                video_directory,
+                display: &mut display,
+                touch: &mut touch,
+                power: &mut power,
+                decode_buffer: &mut decode_buffer,
+                settings: &settings,
+                rng,
+                resume,
+                position: &mut position,
+                failures: Failures::new(),
             };
-        }) {
-            display.message(format_args!("directory {SUFFIX} error: {e:?}"))
-        };
-        filenames.sort();
-
-        #[allow(clippy::infinite_iter)]
-        filenames.into_iter().flatten().cycle().for_each(|filename| {
-            log::info!("Playing {filename}");
-            match directory.open_file_in_dir(filename, embedded_sdmmc::Mode::ReadOnly) {
-                Ok(file) => {
-                    cfg_if::cfg_if! {
-                        if #[cfg(feature = "yuv")] {
-                            let result = cyd_player::video::play::<_, _, _, _, { yuv::DECODE_SIZE }, yuv::YuvDecoder<_>>(
-                                file,
-                                display.deref_mut(),
-                                &touch_detector
-                            );
-                        } else if #[cfg(feature = "rgb")] {
-                            let result = cyd_player::video::play::<_, _, _, _, { rgb::DECODE_SIZE }, rgb::RgbDecoder<_>>(
-                                file,
-                                display.deref_mut(),
-                                &touch_detector
-                            );
-                        } else if #[cfg(feature = "mjpeg")] {
-                            let result = cyd_player::video::play::<_, _, _, _, { mjpeg::DECODE_SIZE }, mjpeg::MjpegDecoder<_>>(
-                                file,
-                                display.deref_mut(),
-                                &touch_detector
-                            );
+
+            if let Some(playlist) = Playlist::find(video_directory)? {
+                log::info!("Playing playlist {}", playlist.name());
+                player.play_playlist(
+                    &playlist,
+                    resume_point.filter(|point| point.entry.is_some()),
+                )?;
+            }
+
+            let mut menu = Menu::new(directory_name);
+            let mut last = None;
+            if let Some(point) = resume_point.filter(|point| point.entry.is_none()) {
+                log::info!("Resuming {} at frame {}", point.name, point.frame);
+                menu.open(point.folder);
+                let filename = player.play_folder(&point.folder, point.name, point.frame)?;
+                menu.show(filename);
+                last = Some(filename);
+            }
+            if !settings.touch_enabled {
+                // Nothing can be selected from the menu
+                return player.play_unattended(menu.folder(), last);
+            }
+            loop {
+                player.display.backlight().on();
+                let selection = menu.select(
+                    video_directory,
+                    player.display.deref_mut(),
+                    player.touch,
+                    player.power,
+                );
+                let filename = match selection {
+                    Ok(Selection::Video(filename)) => filename,
+                    Ok(Selection::Empty) => return Ok(()),
+                    Ok(Selection::Idle) => {
+                        player.display.backlight().fade_out();
+                        player.power.deep_sleep()
+                    }
+                    Err(e) => match e.into_card_fault() {
+                        Ok(fault) => return Err(fault.into()),
+                        Err(e) => player
+                            .display
+                            .message(format_args!("directory {directory_name} error: {e:?}")),
+                    },
+                };
+                let filename = player.play_folder(menu.folder(), filename, 0)?;
+                menu.show(filename);
+            }
+        });
+        match result {
+            Ok(()) => display.message(format_args!("no videos found in {directory_name}")),
+            Err(e) => match e.into_card_fault() {
+                Ok(fault) => {
+                    log::warn!("SD card fault: {fault:?}");
+                    mount_card(&mut sdcard, &mut display, "Reinsert SD card");
+                }
+                Err(e) => display.message(format_args!("{e:?}")),
+            },
+        }
+    }
+    // for inspiration have a look at the examples at https://github.com/esp-rs/esp-hal/tree/esp-hal-v~1.0/examples
+}
+
+/// Mount the SD card, showing `message` and retrying until a card is inserted
+fn mount_card(sdcard: &mut SdCard, display: &mut Display, message: &str) {
+    let delay = Delay::new();
+    let mut shown = false;
+    while let Err(e) = sdcard.mount() {
+        log::warn!("SD card error: {e:?}");
+        if !shown {
+            display.show(format_args!("{message}"));
+            shown = true;
+        }
+        delay.delay_millis(MOUNT_RETRY_MS);
+    }
+}
+
+/// Plays videos below the video directory and saves where to resume
+struct Player<'a, 'd> {
+    sdcard: &'a SdCard,
+    video_directory: &'a DirectoryType<'a>,
+    display: &'a mut Display<'d>,
+    touch: &'a mut Touch,
+    power: &'a mut Power,
+    decode_buffer: &'a mut [u8],
+    settings: &'a Settings,
+    rng: Rng,
+    resume: Resume,
+    position: &'a mut Option<ResumePoint>,
+    // Playlist entry, folder and name of each video that failed since one played
+    failures: Failures<(Option<u32>, Folder, ShortFileName), MAX_FAILURES>,
+}
+
+impl Player<'_, '_> {
+    /// Play the playlist in a loop until stopped, starting at `resume_point` if given
+    fn play_playlist(
+        &mut self,
+        playlist: &Playlist,
+        resume_point: Option<ResumePoint>,
+    ) -> library::Result<()> {
+        let mut index = resume_point.and_then(|point| point.entry).unwrap_or(0);
+        let mut resume_point = resume_point;
+        loop {
+            let Some(entry) = playlist.entry(self.video_directory, index as usize)? else {
+                if index == 0 {
+                    log::warn!("playlist {} is empty", playlist.name());
+                    return Ok(());
+                }
+                self.check_failures(index as usize);
+                index = 0;
+                continue;
+            };
+            // Resume only if the playlist still has the same video at this index
+            let frame = resume_point
+                .take()
+                .filter(|point| point.folder == entry.folder && point.name == entry.name)
+                .map_or(0, |point| point.frame);
+            let playback = Playback {
+                start: entry.options.start,
+                duration: entry.options.duration,
+                frame,
+            };
+            let point = ResumePoint {
+                entry: Some(index),
+                folder: entry.folder,
+                name: entry.name,
+                frame,
+            };
+            let result = entry.folder.open(
+                self.video_directory,
+                |directory| -> library::Result<Played> {
+                    let mut played = Played::Finished;
+                    let mut playback = playback;
+                    for _ in 0..entry.options.repeat {
+                        played = self.play_video(directory, point, playback)?;
+                        if played != Played::Finished {
+                            break;
                         }
-                    };
-                   match result {
-                        Ok(_) => {},
-                        Err(e) => display.message(format_args!("{e:?}"))
+                        playback.frame = 0;
                     }
+                    Ok(played)
+                },
+            );
+            let played = match result {
+                Ok(played) => played,
+                // The entry's folder is missing
+                Err(e) if !sdcard::is_fault(&e) => self.skip(&point, format_args!("{e:?}")),
+                Err(e) => return Err(e),
+            };
+            match played {
+                Played::Finished | Played::Next => index += 1,
+                Played::Previous => index = index.saturating_sub(1),
+                Played::Stopped => return Ok(()),
+            }
+        }
+    }
+
+    /// Play videos in `folder` starting at `filename` and `frame` until one ends without
+    /// a next video or playback is stopped, returns the last video played
+    fn play_folder(
+        &mut self,
+        folder: &Folder,
+        filename: ShortFileName,
+        frame: u32,
+    ) -> library::Result<ShortFileName> {
+        folder.open(self.video_directory, |directory| {
+            let library = Library::new(directory)?;
+            let videos = library.video_count(directory)?;
+            let mut filename = filename;
+            let mut frame = frame;
+            loop {
+                let point = ResumePoint {
+                    entry: None,
+                    folder: *folder,
+                    name: filename,
+                    frame,
+                };
+                let playback = Playback {
+                    frame,
+                    ..Playback::default()
+                };
+                let next = match self.play_video(directory, point, playback)? {
+                    Played::Finished => match self.settings.repeat {
+                        Repeat::None => None,
+                        Repeat::One => Some(filename),
+                        Repeat::All => self.next_video(&library, directory, filename)?,
+                    },
+                    Played::Next => self.next_video(&library, directory, filename)?,
+                    Played::Previous => Some(library.previous_video(directory, filename)?),
+                    Played::Stopped => None,
+                };
+                self.check_failures(videos);
+                match next {
+                    Some(next) => filename = next,
+                    None => return Ok(filename),
                 }
-                Err(e) => display.message(format_args!("{filename} error: {e:?}"))
+                frame = 0;
+            }
+        })
+    }
+
+    /// Play the videos in `folder` one after another without end, starting after `last`
+    /// if given. Returns only if the folder has no videos.
+    fn play_unattended(
+        &mut self,
+        folder: &Folder,
+        mut last: Option<ShortFileName>,
+    ) -> library::Result<()> {
+        loop {
+            let next = folder.open(self.video_directory, |directory| {
+                let library = Library::new(directory)?;
+                match last {
+                    Some(last) => self.next_video(&library, directory, last),
+                    None => library.first_video(directory),
+                }
+            })?;
+            let Some(next) = next else {
+                return Ok(());
             };
-        });
-        Ok(())
-    }) {
-        display.message(format_args!("{e:?}"))
-    };
+            last = Some(self.play_folder(folder, next, 0)?);
+        }
+    }
 
-    unreachable!();
-    // for inspiration have a look at the examples at https://github.com/esp-rs/esp-hal/tree/esp-hal-v~1.0/examples
+    /// The next video in order, or a random one when shuffling
+    fn next_video(
+        &self,
+        library: &Library,
+        directory: &DirectoryType,
+        filename: ShortFileName,
+    ) -> library::Result<Option<ShortFileName>> {
+        if self.settings.shuffle {
+            library.random_video(directory, self.rng.random())
+        } else {
+            library.next_video(directory, filename)
+        }
+    }
+
+    /// Play video `point.name` in `directory`, saving the position as it plays.
+    /// Videos that can't be played are skipped, only card faults are returned.
+    fn play_video(
+        &mut self,
+        directory: &DirectoryType,
+        point: ResumePoint,
+        playback: Playback,
+    ) -> library::Result<Played> {
+        let filename = point.name;
+        log::info!("Playing {filename}");
+        let Some(format) = Format::from_name(&filename) else {
+            return Ok(self.skip(&point, format_args!("format not supported")));
+        };
+        let decoder = match self
+            .sdcard
+            .open_video(directory, filename)
+            .map_err(Error::SdCardError)
+            .and_then(|file| AnyDecoder::new(file, format).map_err(Error::ReadExactError))
+            .map(|decoder| {
+                decoder.with_yuv_colors(self.settings.yuv_matrix, self.settings.yuv_range)
+            }) {
+            Ok(decoder) => decoder,
+            Err(e) => return self.failed::<Infallible, Infallible>(&point, e),
+        };
+        let video_directory = self.video_directory;
+        let resume = &mut self.resume;
+        let position = &mut *self.position;
+        let mut progress = |frame: u32| {
+            let point = ResumePoint { frame, ..point };
+            *position = Some(point);
+            if let Err(e) = resume.update(video_directory, point) {
+                log::warn!("saving resume point failed: {e:?}");
+            }
+        };
+        let mut title = [0u8; 12];
+        let title = format_no_std::show(&mut title, format_args!("{filename}")).unwrap_or_default();
+        let mut osd = Osd::new(self.settings.overlay, title);
+        let mut stats = Stats::<STATS_FRAMES>::new();
+        let (display, backlight) = self.display.split();
+        let result = cyd_player::video::play(
+            decoder,
+            self.decode_buffer,
+            display,
+            backlight,
+            self.touch,
+            self.power,
+            playback,
+            &mut osd,
+            &mut stats,
+            &mut progress,
+        );
+        self.display.backlight().fade_out();
+        self.power.active();
+        match result {
+            Ok(played) => {
+                self.save_stats(&point, &stats);
+                *self.position = None;
+                self.failures.clear();
+                Ok(played)
+            }
+            Err(e) => match e.into_card_fault() {
+                // Stats aren't saved, writing the stats file would only fail again
+                Ok(fault) => Err(fault),
+                Err(e) => {
+                    self.save_stats(&point, &stats);
+                    Ok(self.skip(&point, format_args!("{e:?}")))
+                }
+            },
+        }
+    }
+
+    /// Log the timing statistics of video `point.name` and append them to the stats file
+    fn save_stats(&self, point: &ResumePoint, stats: &Stats<STATS_FRAMES>) {
+        let summary = stats.summary();
+        if summary.frames == 0 {
+            return;
+        }
+        log::info!("{}: {summary}", point.name);
+        if self.settings.stats
+            && let Err(e) =
+                stats_log::append(self.video_directory, &point.folder, &point.name, &summary)
+        {
+            log::warn!("writing stats failed: {e:?}");
+        }
+    }
+
+    /// Return a card fault, otherwise skip the video
+    fn failed<D: fmt::Debug, DI: fmt::Debug>(
+        &mut self,
+        point: &ResumePoint,
+        error: Error<embedded_sdmmc::Error<SdCardError>, D, DI>,
+    ) -> library::Result<Played> {
+        match error.into_card_fault() {
+            Ok(fault) => Err(fault),
+            Err(e) => Ok(self.skip(point, format_args!("{e:?}"))),
+        }
+    }
+
+    /// Show why video `point.name` can't be played and, unless the error policy halts,
+    /// skip to the next one
+    fn skip(&mut self, point: &ResumePoint, reason: fmt::Arguments) -> Played {
+        if self.settings.error_log
+            && let Err(e) =
+                error_log::append(self.video_directory, &point.folder, &point.name, reason)
+        {
+            log::warn!("writing error log failed: {e:?}");
+        }
+        let name = point.name;
+        if self.settings.on_error == OnError::Halt {
+            self.display.message(format_args!("{name} error: {reason}"));
+        }
+        self.display.show(format_args!("{name} error: {reason}"));
+        Delay::new().delay_millis(SKIP_MESSAGE_MS);
+        self.failures.add((point.entry, point.folder, point.name));
+        Played::Next
+    }
+
+    /// Halt if each of the `videos` videos failed
+    fn check_failures(&mut self, videos: usize) {
+        if self.failures.all_failed(videos) {
+            self.display
+                .message(format_args!("none of {videos} videos can be played"));
+        }
+    }
 }
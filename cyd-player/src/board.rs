@@ -0,0 +1,75 @@
+//! Board profile selected by a `board-*` feature, without one the 2.8" ESP32-2432S028R
+//! with an ILI9341 panel.
+//!
+//! Boards differ in their panel, the backlight pin and how the touch controller is wired.
+//! The 2.8" board bit-bangs touch on its own pins, the 2.4" and 3.5" boards share the
+//! display SPI bus with it. The SD card is wired the same on all boards.
+
+#[cfg(any(
+    all(feature = "board-2432s028r-st7789", feature = "board-2432s024r"),
+    all(feature = "board-2432s028r-st7789", feature = "board-3248s035r"),
+    all(feature = "board-2432s024r", feature = "board-3248s035r"),
+))]
+compile_error!("select at most one board-* feature");
+
+use cyd_encoder::board::{self, Board};
+use mipidsi::options::{ColorInversion, ColorOrder};
+
+#[cfg(not(any(
+    feature = "board-2432s028r-st7789",
+    feature = "board-2432s024r",
+    feature = "board-3248s035r"
+)))]
+mod profile {
+    use super::*;
+
+    pub const BOARD: Board = board::ESP32_2432S028R;
+    pub type Model = mipidsi::models::ILI9341Rgb565;
+    pub const MODEL: Model = mipidsi::models::ILI9341Rgb565;
+    pub const COLOR_ORDER: ColorOrder = ColorOrder::Bgr;
+    pub const INVERSION: ColorInversion = ColorInversion::Normal;
+    // The panel is mounted mirrored
+    pub const FLIP_HORIZONTAL: bool = true;
+    pub type BacklightPin = esp_hal::peripherals::GPIO21<'static>;
+}
+
+#[cfg(feature = "board-2432s028r-st7789")]
+mod profile {
+    use super::*;
+
+    pub const BOARD: Board = board::ESP32_2432S028R;
+    pub type Model = mipidsi::models::ST7789;
+    pub const MODEL: Model = mipidsi::models::ST7789;
+    pub const COLOR_ORDER: ColorOrder = ColorOrder::Rgb;
+    pub const INVERSION: ColorInversion = ColorInversion::Inverted;
+    pub const FLIP_HORIZONTAL: bool = false;
+    pub type BacklightPin = esp_hal::peripherals::GPIO21<'static>;
+}
+
+#[cfg(feature = "board-2432s024r")]
+mod profile {
+    use super::*;
+
+    pub const BOARD: Board = board::ESP32_2432S024R;
+    pub type Model = mipidsi::models::ILI9341Rgb565;
+    pub const MODEL: Model = mipidsi::models::ILI9341Rgb565;
+    pub const COLOR_ORDER: ColorOrder = ColorOrder::Bgr;
+    pub const INVERSION: ColorInversion = ColorInversion::Normal;
+    pub const FLIP_HORIZONTAL: bool = false;
+    pub type BacklightPin = esp_hal::peripherals::GPIO27<'static>;
+}
+
+#[cfg(feature = "board-3248s035r")]
+mod profile {
+    use super::*;
+
+    pub const BOARD: Board = board::ESP32_3248S035R;
+    pub type Model = mipidsi::models::ST7796;
+    pub const MODEL: Model = mipidsi::models::ST7796;
+    pub const COLOR_ORDER: ColorOrder = ColorOrder::Bgr;
+    pub const INVERSION: ColorInversion = ColorInversion::Normal;
+    pub const FLIP_HORIZONTAL: bool = false;
+    pub type BacklightPin = esp_hal::peripherals::GPIO27<'static>;
+}
+
+pub use profile::*;
@@ -1,46 +1,248 @@
 #![deny(clippy::large_stack_frames)]
 
 use core::{
+    cell::RefCell,
+    convert::Infallible,
     fmt,
     ops::{Deref, DerefMut},
 };
 
+use crate::{
+    backlight::{Backlight, Pwm},
+    board,
+};
+use critical_section::Mutex;
+use cyd_core::{
+    blit::{self, BlitTarget, fill_bytes},
+    pipeline::{BackgroundBus, Pipeline, SLOTS},
+};
+use cyd_encoder::settings::Settings;
 use embedded_graphics::{
     draw_target::DrawTarget,
     mono_font::{MonoTextStyle, ascii::FONT_6X10},
     pixelcolor::Rgb565,
     prelude::*,
+    primitives::Rectangle,
     text::{Baseline, Text},
 };
-use embedded_hal_bus::spi::{ExclusiveDevice, NoDelay};
+use embedded_hal::{
+    digital::{ErrorType as DigitalErrorType, OutputPin},
+    spi::{ErrorType as SpiErrorType, Operation, SpiDevice},
+};
 use esp_hal::{
     Blocking,
     delay::Delay,
+    dma::DmaTxBuf,
+    dma_tx_buffer,
     gpio::{Level, Output, OutputConfig},
-    peripherals::{GPIO2, GPIO4, GPIO12, GPIO13, GPIO14, GPIO15, GPIO21, SPI2},
+    peripherals::{DMA_SPI2, GPIO2, GPIO4, GPIO12, GPIO13, GPIO14, GPIO15, SPI2},
     spi::{
-        Mode as SpiMode,
-        master::{Config as SpiConfig, Spi},
+        Error as SpiError, Mode as SpiMode,
+        master::{Config as SpiConfig, Spi, SpiDma, SpiDmaTransfer},
     },
     time::Rate,
 };
+#[cfg(feature = "touch-on-display-bus")]
+use esp_hal::{dma::DmaRxBuf, dma_buffers};
 use mipidsi::{
     Builder,
-    interface::SpiInterface,
-    models::{ILI9341Rgb565, Model},
+    interface::{SpiError as SpiInterfaceError, SpiInterface},
+    models::Model,
     options::{ColorOrder, Orientation, Rotation},
 };
 
-type InternalDisplay<'a> = mipidsi::Display<
-    SpiInterface<'a, ExclusiveDevice<Spi<'a, Blocking>, Output<'a>, NoDelay>, Output<'a>>,
-    ILI9341Rgb565,
-    Output<'a>,
->;
+type InternalDisplay<'a> =
+    mipidsi::Display<SpiInterface<'a, PipelinedSpi, PipelinedDc>, board::Model, Output<'a>>;
 
-pub const CENTER: Point = Point::new(
-    (ILI9341Rgb565::FRAMEBUFFER_SIZE.1 / 2) as i32,
-    (ILI9341Rgb565::FRAMEBUFFER_SIZE.0 / 2) as i32,
+// Rotated to landscape
+pub const SIZE: Size = Size::new(
+    board::Model::FRAMEBUFFER_SIZE.1 as u32,
+    board::Model::FRAMEBUFFER_SIZE.0 as u32,
 );
+// Videos are encoded for the panel of the board
+const _: () =
+    assert!(SIZE.width == board::BOARD.width as u32 && SIZE.height == board::BOARD.height as u32);
+
+pub const CENTER: Point = Point::new((SIZE.width / 2) as i32, (SIZE.height / 2) as i32);
+
+const FREQUENCY: Rate = Rate::from_mhz(40);
+
+// Lines of pixels per DMA band
+const BAND_LINES: usize = 8;
+const BAND_SIZE: usize = SIZE.width as usize * BAND_LINES * 2;
+
+static PIPELINE: Mutex<RefCell<Option<Pipeline<DmaBus>>>> = Mutex::new(RefCell::new(None));
+
+/// Call `f` with the pipeline taken out of [`PIPELINE`], so interrupts aren't masked
+/// while it waits for DMA transfers
+fn with_pipeline<R>(f: impl FnOnce(&mut Pipeline<DmaBus>) -> R) -> R {
+    let mut pipeline =
+        critical_section::with(|cs| PIPELINE.borrow_ref_mut(cs).take()).expect("display pipeline");
+    let result = f(&mut pipeline);
+    critical_section::with(|cs| PIPELINE.borrow_ref_mut(cs).replace(pipeline));
+    result
+}
+
+/// Sends bands with SPI DMA from two buffers
+struct DmaBus {
+    spi: Option<SpiDma<'static, Blocking>>,
+    buffers: [Option<DmaTxBuf>; SLOTS],
+    transfer: Option<(usize, SpiDmaTransfer<'static, Blocking, DmaTxBuf>)>,
+    #[cfg(feature = "touch-on-display-bus")]
+    touch: TouchBuffers,
+}
+
+/// Bytes exchanged with the touch controller at a time
+#[cfg(feature = "touch-on-display-bus")]
+pub const TOUCH_BYTES: usize = 16;
+// The XPT2046 is clocked at up to 2.5 MHz
+#[cfg(feature = "touch-on-display-bus")]
+const TOUCH_FREQUENCY: Rate = Rate::from_mhz(2);
+
+/// The display chip select and buffers for exchanges with the touch controller
+#[cfg(feature = "touch-on-display-bus")]
+struct TouchBuffers {
+    display_cs: Output<'static>,
+    buffers: Option<(DmaRxBuf, DmaTxBuf)>,
+}
+
+#[cfg(feature = "touch-on-display-bus")]
+impl DmaBus {
+    /// Exchange `bytes` with the touch controller selected by `cs`,
+    /// no transfer may be in flight
+    fn touch_transfer(
+        &mut self,
+        cs: &mut Output<'_>,
+        bytes: &mut [u8; TOUCH_BYTES],
+    ) -> Result<(), SpiError> {
+        let mut spi = self.spi.take().expect("transfer in flight");
+        let (mut rx, mut tx) = self.touch.buffers.take().expect("touch transfer in flight");
+        spi.apply_config(&spi_config(TOUCH_FREQUENCY))
+            .expect("touch SPI config");
+        tx.as_mut_slice()[..TOUCH_BYTES].copy_from_slice(bytes);
+        tx.set_length(TOUCH_BYTES);
+        rx.set_length(TOUCH_BYTES);
+        self.touch.display_cs.set_high();
+        cs.set_low();
+        let (mut spi, rx, tx, result) = match spi.transfer(TOUCH_BYTES, rx, TOUCH_BYTES, tx) {
+            Ok(transfer) => {
+                let (spi, (rx, tx)) = transfer.wait();
+                bytes.copy_from_slice(&rx.as_slice()[..TOUCH_BYTES]);
+                (spi, rx, tx, Ok(()))
+            }
+            Err((e, spi, rx, tx)) => (spi, rx, tx, Err(e)),
+        };
+        cs.set_high();
+        self.touch.display_cs.set_low();
+        spi.apply_config(&spi_config(FREQUENCY))
+            .expect("display SPI config");
+        self.spi = Some(spi);
+        self.touch.buffers = Some((rx, tx));
+        result
+    }
+}
+
+/// Exchange `bytes` with the touch controller selected by `cs`, which shares the display
+/// SPI bus. Waits for the display to finish and deselects it meanwhile.
+#[cfg(feature = "touch-on-display-bus")]
+pub fn touch_transfer(cs: &mut Output<'_>, bytes: &mut [u8; TOUCH_BYTES]) -> Result<(), SpiError> {
+    with_pipeline(|pipeline| {
+        pipeline.flush()?;
+        pipeline.bus_mut().touch_transfer(cs, bytes)
+    })
+}
+
+impl BackgroundBus for DmaBus {
+    type Error = SpiError;
+
+    fn buffer(&mut self, slot: usize) -> &mut [u8] {
+        self.buffers[slot]
+            .as_mut()
+            .expect("band in flight")
+            .as_mut_slice()
+    }
+
+    fn start(&mut self, slot: usize, len: usize) -> Result<(), SpiError> {
+        let spi = self.spi.take().expect("transfer in flight");
+        let mut buffer = self.buffers[slot].take().expect("band in flight");
+        buffer.set_length(len);
+        match spi.write(len, buffer) {
+            Ok(transfer) => {
+                self.transfer = Some((slot, transfer));
+                Ok(())
+            }
+            Err((e, spi, buffer)) => {
+                self.spi = Some(spi);
+                self.buffers[slot] = Some(buffer);
+                Err(e)
+            }
+        }
+    }
+
+    fn wait(&mut self) -> Result<(), SpiError> {
+        if let Some((slot, transfer)) = self.transfer.take() {
+            let (spi, buffer) = transfer.wait();
+            self.spi = Some(spi);
+            self.buffers[slot] = Some(buffer);
+        }
+        Ok(())
+    }
+}
+
+/// Write-only display SPI device, each write is sent in the background
+/// while the next band is prepared.
+/// CS stays asserted, unless the touch controller shares the bus and is being read.
+pub struct PipelinedSpi;
+
+impl SpiErrorType for PipelinedSpi {
+    type Error = SpiError;
+}
+
+impl SpiDevice for PipelinedSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), SpiError> {
+        with_pipeline(|pipeline| {
+            for operation in operations {
+                match operation {
+                    Operation::Write(data) => pipeline.write(data)?,
+                    Operation::DelayNs(ns) => {
+                        pipeline.flush()?;
+                        Delay::new().delay_nanos(*ns);
+                    }
+                    _ => return Err(SpiError::Unsupported),
+                }
+            }
+            pipeline.submit()
+        })
+    }
+}
+
+/// Data/command pin that waits for queued bytes to be sent before switching
+pub struct PipelinedDc(Output<'static>);
+
+impl DigitalErrorType for PipelinedDc {
+    type Error = Infallible;
+}
+
+impl OutputPin for PipelinedDc {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        flush();
+        self.0.set_low();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        flush();
+        self.0.set_high();
+        Ok(())
+    }
+}
+
+/// Wait until everything queued for the display has been sent
+pub fn flush() {
+    if let Err(e) = with_pipeline(|pipeline| pipeline.flush()) {
+        log::error!("display flush error: {e:?}");
+    }
+}
 
 pub struct Peripherals {
     pub spi2: SPI2<'static>,
@@ -50,56 +252,111 @@ pub struct Peripherals {
     pub mosi: GPIO13<'static>,
     pub sclk: GPIO14<'static>,
     pub cs: GPIO15<'static>,
-    pub bl: GPIO21<'static>,
+    pub bl: board::BacklightPin,
+    pub dma: DMA_SPI2<'static>,
 }
 
 pub struct Display<'a> {
     display: InternalDisplay<'a>,
+    backlight: Backlight<'a>,
+    #[cfg(not(feature = "touch-on-display-bus"))]
+    _cs: Output<'a>,
 }
 
 impl<'a> Display<'a> {
-    pub fn new(display_buffer: &'a mut [u8], peripherals: Peripherals) -> Self {
-        let spi = Spi::new(
-            peripherals.spi2,
-            SpiConfig::default()
-                .with_frequency(Rate::from_mhz(40))
-                .with_mode(SpiMode::_0),
-        )
-        .expect("display SPI")
-        .with_sck(peripherals.sclk)
-        .with_mosi(peripherals.mosi)
-        .with_miso(peripherals.miso);
-
-        let dc = Output::new(peripherals.dc, Level::Low, OutputConfig::default());
+    pub fn new(display_buffer: &'a mut [u8], pwm: &'a Pwm, peripherals: Peripherals) -> Self {
+        let spi = Spi::new(peripherals.spi2, spi_config(FREQUENCY))
+            .expect("display SPI")
+            .with_sck(peripherals.sclk)
+            .with_mosi(peripherals.mosi)
+            .with_miso(peripherals.miso)
+            .with_dma(peripherals.dma);
+        let bus = DmaBus {
+            spi: Some(spi),
+            buffers: [
+                Some(dma_tx_buffer!(BAND_SIZE).expect("display DMA buffer")),
+                Some(dma_tx_buffer!(BAND_SIZE).expect("display DMA buffer")),
+            ],
+            transfer: None,
+            #[cfg(feature = "touch-on-display-bus")]
+            touch: TouchBuffers {
+                display_cs: Output::new(peripherals.cs, Level::Low, OutputConfig::default()),
+                buffers: Some(touch_buffers()),
+            },
+        };
+        critical_section::with(|cs| {
+            PIPELINE.borrow_ref_mut(cs).replace(Pipeline::new(bus));
+        });
+
+        let dc = PipelinedDc(Output::new(
+            peripherals.dc,
+            Level::Low,
+            OutputConfig::default(),
+        ));
+        #[cfg(not(feature = "touch-on-display-bus"))]
         let cs = Output::new(peripherals.cs, Level::Low, OutputConfig::default());
         let mut rst = Output::new(peripherals.rst, Level::Low, OutputConfig::default());
         rst.set_high();
 
-        let spi_dev = ExclusiveDevice::new_no_delay(spi, cs).expect("infallible");
-        let interface = SpiInterface::new(spi_dev, dc, display_buffer);
+        let interface = SpiInterface::new(PipelinedSpi, dc, display_buffer);
 
-        let mut display = Builder::new(ILI9341Rgb565, interface)
+        let mut display = Builder::new(board::MODEL, interface)
             .reset_pin(rst)
             .display_size(
-                ILI9341Rgb565::FRAMEBUFFER_SIZE.0,
-                ILI9341Rgb565::FRAMEBUFFER_SIZE.1,
-            )
-            .color_order(ColorOrder::Bgr)
-            .orientation(
-                Orientation::new()
-                    .rotate(Rotation::Deg270)
-                    .flip_horizontal(),
+                board::Model::FRAMEBUFFER_SIZE.0,
+                board::Model::FRAMEBUFFER_SIZE.1,
             )
+            .color_order(board::COLOR_ORDER)
+            .invert_colors(board::INVERSION)
+            .orientation(orientation(Rotation::Deg270))
             .init(&mut Delay::new())
             .expect("display builder init");
 
-        let _backlight = Output::new(peripherals.bl, Level::High, OutputConfig::default());
+        let mut backlight = Backlight::new(pwm, peripherals.bl);
         display.clear(Rgb565::BLACK).expect("display clear");
+        backlight.on();
 
-        Self { display }
+        Self {
+            display,
+            backlight,
+            #[cfg(not(feature = "touch-on-display-bus"))]
+            _cs: cs,
+        }
     }
 
+    /// Apply the display rotation and brightness settings
+    pub fn apply(&mut self, settings: &Settings) {
+        let rotation = match settings.rotation {
+            90 => Rotation::Deg90,
+            _ => Rotation::Deg270,
+        };
+        self.display
+            .set_orientation(orientation(rotation))
+            .expect("display orientation");
+        self.backlight.set_brightness(settings.brightness);
+    }
+
+    pub fn backlight(&mut self) -> &mut Backlight<'a> {
+        &mut self.backlight
+    }
+
+    /// The display to draw on and its backlight
+    pub fn split(&mut self) -> (&mut InternalDisplay<'a>, &mut Backlight<'a>) {
+        (&mut self.display, &mut self.backlight)
+    }
+
+    /// Show a message and halt
     pub fn message(&mut self, args: fmt::Arguments) -> ! {
+        self.show(args);
+
+        let delay = Delay::new();
+        loop {
+            delay.delay_millis(5000);
+        }
+    }
+
+    /// Show a message on a blank screen, turning the backlight on
+    pub fn show(&mut self, args: fmt::Arguments) {
         let mut buf = [0u8; 256];
         let message = format_no_std::show(&mut buf, args).unwrap();
         log::error!("{message}");
@@ -108,14 +365,48 @@ impl<'a> Display<'a> {
         Text::with_baseline(message, Point::default(), style, Baseline::Top)
             .draw(&mut self.display)
             .unwrap();
+        flush();
+        self.backlight.on();
+    }
+}
 
-        let delay = Delay::new();
-        loop {
-            delay.delay_millis(5000);
+impl BlitTarget for InternalDisplay<'_> {
+    fn blit(&mut self, area: &Rectangle, bytes: &[u8]) -> Result<(), Self::Error> {
+        if self.bounding_box().intersection(area) != *area {
+            return fill_bytes(self, area, bytes);
         }
+        // The panels have no address offset
+        blit::write_memory_start(unsafe { self.dcs() }, area)?;
+        // The command leaves DC high for data, so the packed pixels are queued as they are
+        // rather than copied pixel by pixel through the interface buffer
+        PipelinedSpi.write(bytes).map_err(SpiInterfaceError::Spi)
+    }
+}
+
+fn orientation(rotation: Rotation) -> Orientation {
+    let orientation = Orientation::new().rotate(rotation);
+    if board::FLIP_HORIZONTAL {
+        orientation.flip_horizontal()
+    } else {
+        orientation
     }
 }
 
+fn spi_config(frequency: Rate) -> SpiConfig {
+    SpiConfig::default()
+        .with_frequency(frequency)
+        .with_mode(SpiMode::_0)
+}
+
+#[cfg(feature = "touch-on-display-bus")]
+fn touch_buffers() -> (DmaRxBuf, DmaTxBuf) {
+    let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(TOUCH_BYTES);
+    (
+        DmaRxBuf::new(rx_descriptors, rx_buffer).expect("touch DMA buffer"),
+        DmaTxBuf::new(tx_descriptors, tx_buffer).expect("touch DMA buffer"),
+    )
+}
+
 impl<'a> Deref for Display<'a> {
     type Target = InternalDisplay<'a>;
 
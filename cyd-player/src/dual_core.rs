@@ -0,0 +1,174 @@
+//! Decode JPEG frames on the second core.
+//!
+//! The PRO core copies each JPEG frame into the job buffer and hands it to the APP core,
+//! which decodes it into a [`BandRing`]. Meanwhile the PRO core reads the next frame from
+//! the SD card and sends the bands to the display as they are ready.
+//!
+//! Only the compressed frame crosses cores, so nothing the PRO core uses is touched by
+//! the APP core. Frames that read the file while they are drawn are drawn on the PRO core.
+
+use core::{
+    cell::{RefCell, UnsafeCell},
+    hint,
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    display::SIZE,
+    video::{mjpeg, top_left},
+};
+use critical_section::Mutex;
+use cyd_core::{
+    bands::{BandRing, BandWriter, Consumer},
+    blit::{Blit, BlitTarget},
+    jpeg::{ARENA_SIZE, JpegDrawable},
+};
+use esp_hal::{
+    peripherals::CPU_CTRL,
+    system::{AppCoreGuard, CpuControl, Stack},
+};
+
+const BANDS: usize = 8;
+// Four display lines
+const BAND_PIXELS: usize = SIZE.width as usize * 4;
+// Decoders work in the job buffer and need little stack
+const STACK_SIZE: usize = 16 * 1024;
+
+type Writer = BandWriter<'static, BANDS, BAND_PIXELS>;
+
+static RING: BandRing<BANDS, BAND_PIXELS> = BandRing::new();
+static CONSUMER: Mutex<RefCell<Option<Consumer<'static, BANDS, BAND_PIXELS>>>> =
+    Mutex::new(RefCell::new(None));
+static JOB: JobCell = JobCell(UnsafeCell::new(Job {
+    len: 0,
+    error: None,
+    buffer: [0; mjpeg::DECODE_SIZE],
+}));
+// Set by the PRO core once the job is ready, cleared by the APP core as it starts on it
+static READY: AtomicBool = AtomicBool::new(false);
+static mut STACK: Stack<STACK_SIZE> = Stack::new();
+
+/// JPEG frame to decode on the APP core
+struct Job {
+    len: usize,
+    error: Option<tjpgdec_rs::Error>,
+    // Arena followed by the JPEG data
+    buffer: [u8; mjpeg::DECODE_SIZE],
+}
+
+/// The job belongs to the PRO core until it sets [`READY`], then to the APP core until
+/// the end of the frame is committed to the ring
+struct JobCell(UnsafeCell<Job>);
+
+unsafe impl Sync for JobCell {}
+
+/// Start decoding on the APP core, which runs until the guard is dropped
+pub fn start(cpu_control: CPU_CTRL<'static>) -> AppCoreGuard<'static> {
+    let (producer, consumer) = RING.split().expect("decoder core already started");
+    critical_section::with(|cs| CONSUMER.borrow_ref_mut(cs).replace(consumer));
+    // The ring can only be split once, so the stack is not borrowed elsewhere
+    let stack = unsafe { &mut *addr_of_mut!(STACK) };
+    CpuControl::new(cpu_control)
+        .start_app_core(stack, move || {
+            let mut writer = BandWriter::new(producer, SIZE);
+            loop {
+                if !READY.swap(false, Ordering::Acquire) {
+                    hint::spin_loop();
+                    continue;
+                }
+                // The PRO core leaves the job alone until the end of the frame
+                let job = unsafe { &mut *JOB.0.get() };
+                job.error = decode(job, &mut writer).err();
+                writer.finish();
+            }
+        })
+        .expect("start decoder core")
+}
+
+fn decode(job: &mut Job, writer: &mut Writer) -> Result<(), tjpgdec_rs::Error> {
+    let (arena, data) = job
+        .buffer
+        .split_first_chunk_mut::<ARENA_SIZE>()
+        .expect("job buffer size");
+    let frame = JpegDrawable::with_scale(arena, &data[..job.len], 0)?;
+    let Ok(()) = frame.blit(writer, top_left(&frame));
+    frame.take_error().map_or(Ok(()), Err)
+}
+
+/// Frames decoded on the APP core, drawn by the PRO core
+pub struct Offload {
+    // Returned when dropped
+    consumer: Option<Consumer<'static, BANDS, BAND_PIXELS>>,
+    in_flight: bool,
+}
+
+impl Offload {
+    /// `None` if the APP core has not been started
+    pub fn take() -> Option<Self> {
+        let consumer = critical_section::with(|cs| CONSUMER.borrow_ref_mut(cs).take())?;
+        Some(Self {
+            consumer: Some(consumer),
+            in_flight: false,
+        })
+    }
+
+    /// Whether a frame is being decoded or drawn
+    pub fn in_flight(&self) -> bool {
+        self.in_flight
+    }
+
+    /// Decode `jpeg` on the APP core, false if it doesn't fit the job buffer.
+    /// The previous frame must be finished.
+    pub fn submit(&mut self, jpeg: &[u8]) -> bool {
+        assert!(!self.in_flight, "previous frame not finished");
+        // The APP core is done with the job once it finished the previous frame
+        let job = unsafe { &mut *JOB.0.get() };
+        let Some(data) = job.buffer[ARENA_SIZE..].get_mut(..jpeg.len()) else {
+            return false;
+        };
+        data.copy_from_slice(jpeg);
+        job.len = jpeg.len();
+        job.error = None;
+        READY.store(true, Ordering::Release);
+        self.in_flight = true;
+        true
+    }
+
+    /// Draw the bands decoded so far to `target`, the first error is kept in `result`
+    pub fn draw_ready<T: BlitTarget>(&mut self, target: &mut T, result: &mut Result<(), T::Error>) {
+        if self.in_flight && self.consumer().draw_ready(target, result) {
+            self.in_flight = false;
+        }
+    }
+
+    /// Draw the rest of the frame to `target`, the first error is kept in `result`.
+    /// Returns the error decoding the frame.
+    pub fn finish<T: BlitTarget>(
+        &mut self,
+        target: &mut T,
+        result: &mut Result<(), T::Error>,
+    ) -> Option<tjpgdec_rs::Error> {
+        while self.in_flight {
+            self.draw_ready(target, result);
+            hint::spin_loop();
+        }
+        // The end of the frame was consumed, so the APP core is done with the job
+        unsafe { &mut *JOB.0.get() }.error.take()
+    }
+
+    fn consumer(&mut self) -> &mut Consumer<'static, BANDS, BAND_PIXELS> {
+        self.consumer.as_mut().expect("consumer")
+    }
+}
+
+impl Drop for Offload {
+    fn drop(&mut self) {
+        // Don't leave bands of a frame that was cut short for the next video
+        if self.in_flight {
+            self.consumer().discard_frame();
+        }
+        let consumer = self.consumer.take();
+        critical_section::with(|cs| *CONSUMER.borrow_ref_mut(cs) = consumer);
+    }
+}
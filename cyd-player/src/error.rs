@@ -1,4 +1,6 @@
+use crate::video::decoder::DecodeError;
 use core::fmt;
+use embedded_graphics::prelude::Size;
 use embedded_io::ReadExactError;
 use embedded_sdmmc::SdCardError;
 use esp_hal::spi::master::ConfigError;
@@ -17,6 +19,8 @@ where
     ReadError(IO),
     ReadExactError(ReadExactError<IO>),
     DecodeErrors(D),
+    BufferTooSmall { required: usize, available: usize },
+    UnsupportedSize(Size),
 }
 
 impl<IO, D, DI> From<ConfigError> for Error<IO, D, DI>
@@ -62,3 +66,45 @@ where
         Error::SpiError(value)
     }
 }
+
+impl<IO, D, DI> From<DecodeError<IO, D>> for Error<IO, D, DI>
+where
+    IO: fmt::Debug,
+    D: fmt::Debug,
+    DI: fmt::Debug,
+{
+    fn from(value: DecodeError<IO, D>) -> Self {
+        match value {
+            DecodeError::Read(e) => Error::ReadError(e),
+            DecodeError::Codec(e) => Error::DecodeErrors(e),
+            DecodeError::BufferTooSmall {
+                required,
+                available,
+            } => Error::BufferTooSmall {
+                required,
+                available,
+            },
+            DecodeError::UnsupportedSize(size) => Error::UnsupportedSize(size),
+        }
+    }
+}
+
+impl<D, DI> Error<embedded_sdmmc::Error<SdCardError>, D, DI>
+where
+    D: fmt::Debug,
+    DI: fmt::Debug,
+{
+    /// The SD card error if the card failed or was removed, rather than a single file
+    pub fn into_card_fault(self) -> Result<embedded_sdmmc::Error<SdCardError>, Self> {
+        match self {
+            Error::SdCardError(e)
+            | Error::ReadError(e)
+            | Error::ReadExactError(ReadExactError::Other(e))
+                if crate::sdcard::is_fault(&e) =>
+            {
+                Ok(e)
+            }
+            e => Err(e),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Log of videos that couldn't be played, kept in the video directory
+
+use crate::{
+    library::{self, Folder},
+    sdcard::DirectoryType,
+};
+use core::fmt;
+use cyd_core::log_file::append_rotating;
+use embedded_sdmmc::ShortFileName;
+
+const FILENAME: &str = "ERRORS.LOG";
+const OLD_FILENAME: &str = "ERRORS.1";
+// Start over when the log grows beyond this, keeping the previous log
+const MAX_SIZE: u32 = 64 * 1024;
+
+/// Append why `folder/name` couldn't be played to the log in `directory`
+pub fn append(
+    directory: &DirectoryType,
+    folder: &Folder,
+    name: &ShortFileName,
+    reason: fmt::Arguments,
+) -> library::Result<()> {
+    let mut buffer = [0u8; 256];
+    let line = format_no_std::show(&mut buffer, format_args!("{folder}{name}: {reason}"))
+        .unwrap_or("error message too long");
+    append_rotating(
+        directory,
+        FILENAME,
+        OLD_FILENAME,
+        MAX_SIZE,
+        None,
+        line.as_bytes(),
+    )
+}
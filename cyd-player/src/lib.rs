@@ -1,6 +1,20 @@
 #![cfg_attr(not(test), no_std)]
+pub mod ambient;
+pub mod backlight;
+pub mod board;
 pub mod display;
+#[cfg(feature = "dual-core")]
+pub mod dual_core;
 pub mod error;
+pub mod error_log;
+pub mod library;
+pub mod menu;
+pub mod osd;
+pub mod playlist;
+pub mod power;
+pub mod resume;
 pub mod sdcard;
+pub mod settings;
+pub mod stats_log;
 pub mod touch;
 pub mod video;
@@ -0,0 +1,304 @@
+use crate::{sdcard::DirectoryType, video::any::Format};
+use core::fmt;
+use embedded_sdmmc::{DirEntry, SdCardError, ShortFileName};
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub type Result<T> = core::result::Result<T, embedded_sdmmc::Error<SdCardError>>;
+
+// The video directory and root are open too, the volume manager allows 4 open directories
+pub const MAX_DEPTH: usize = 2;
+
+/// Folder or video in a folder, folders sort before videos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Item {
+    Folder(ShortFileName),
+    Video(ShortFileName),
+}
+
+impl Item {
+    fn from_entry(entry: &DirEntry) -> Option<Self> {
+        if entry.attributes.is_volume() {
+            None
+        } else if entry.attributes.is_directory() {
+            // Skip `.` and `..`
+            (!entry.name.base_name().starts_with(b".")).then_some(Item::Folder(entry.name))
+        } else if Format::from_name(&entry.name).is_some() {
+            Some(Item::Video(entry.name))
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &ShortFileName {
+        match self {
+            Item::Folder(name) | Item::Video(name) => name,
+        }
+    }
+}
+
+/// Path of a folder below the video directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Folder {
+    names: [Option<ShortFileName>; MAX_DEPTH],
+}
+
+impl Folder {
+    pub fn depth(&self) -> usize {
+        self.names.iter().flatten().count()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &ShortFileName> {
+        self.names.iter().flatten()
+    }
+
+    /// Enter subfolder `name`, returns false if nested too deep
+    pub fn push(&mut self, name: ShortFileName) -> bool {
+        match self.names.iter_mut().find(|name| name.is_none()) {
+            Some(slot) => {
+                *slot = Some(name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return to the parent folder, returns the folder left
+    pub fn pop(&mut self) -> Option<ShortFileName> {
+        self.names.iter_mut().rev().find_map(Option::take)
+    }
+
+    /// Open this folder below `directory` and call `f` with it
+    pub fn open<'a, R, E, F>(
+        &self,
+        directory: &DirectoryType<'a>,
+        f: F,
+    ) -> core::result::Result<R, E>
+    where
+        E: From<embedded_sdmmc::Error<SdCardError>>,
+        F: FnOnce(&DirectoryType<'a>) -> core::result::Result<R, E>,
+    {
+        open_path(&self.names, directory, f)
+    }
+}
+
+fn open_path<'a, R, E, F>(
+    names: &[Option<ShortFileName>],
+    directory: &DirectoryType<'a>,
+    f: F,
+) -> core::result::Result<R, E>
+where
+    E: From<embedded_sdmmc::Error<SdCardError>>,
+    F: FnOnce(&DirectoryType<'a>) -> core::result::Result<R, E>,
+{
+    match names.split_first() {
+        Some((Some(name), rest)) => {
+            let subdirectory = directory.open_dir(*name)?;
+            let result = open_path(rest, &subdirectory, f);
+            subdirectory.close()?;
+            result
+        }
+        _ => f(directory),
+    }
+}
+
+/// Path prefix, each folder name followed by `/`
+impl fmt::Display for Folder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in self.names() {
+            write!(f, "{name}/")?;
+        }
+        Ok(())
+    }
+}
+
+/// Sorted listing of the folders and videos of any enabled format in a folder.
+///
+/// With `alloc` the listing is read once into a list of names.
+/// Otherwise the folder is rescanned for every lookup, finding the next entry
+/// in sort order by a minimum scan, so any number of entries can be handled.
+pub struct Library {
+    #[cfg(feature = "alloc")]
+    items: Vec<Item>,
+}
+
+#[cfg(feature = "alloc")]
+impl Library {
+    pub fn new(directory: &DirectoryType) -> Result<Self> {
+        let mut items = Vec::new();
+        directory.iterate_dir(|entry| {
+            if let Some(item) = Item::from_entry(entry) {
+                items.push(item);
+            }
+        })?;
+        items.sort_unstable();
+        Ok(Self { items })
+    }
+
+    pub fn count(&self, _directory: &DirectoryType) -> Result<usize> {
+        Ok(self.items.len())
+    }
+
+    /// First item after `after`, or the first item if `None`
+    pub fn next(&self, _directory: &DirectoryType, after: Option<&Item>) -> Result<Option<Item>> {
+        let index = after.map_or(0, |after| self.items.partition_point(|item| item <= after));
+        Ok(self.items.get(index).copied())
+    }
+
+    /// Last item before `before`
+    pub fn previous(&self, _directory: &DirectoryType, before: &Item) -> Result<Option<Item>> {
+        let index = self.items.partition_point(|item| item < before);
+        Ok(index.checked_sub(1).map(|index| self.items[index]))
+    }
+
+    /// Item at `index` in sort order
+    pub fn nth(&self, _directory: &DirectoryType, index: usize) -> Result<Option<Item>> {
+        Ok(self.items.get(index).copied())
+    }
+
+    /// Index of `item` in sort order
+    pub fn position(&self, _directory: &DirectoryType, item: &Item) -> Result<usize> {
+        Ok(self.items.partition_point(|i| i < item))
+    }
+
+    fn folder_count(&self, _directory: &DirectoryType) -> Result<usize> {
+        Ok(self
+            .items
+            .partition_point(|item| matches!(item, Item::Folder(_))))
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl Library {
+    pub fn new(_directory: &DirectoryType) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    fn scan(&self, directory: &DirectoryType, mut f: impl FnMut(Item)) -> Result<()> {
+        directory.iterate_dir(|entry| {
+            if let Some(item) = Item::from_entry(entry) {
+                f(item);
+            }
+        })
+    }
+
+    pub fn count(&self, directory: &DirectoryType) -> Result<usize> {
+        let mut count = 0;
+        self.scan(directory, |_| count += 1)?;
+        Ok(count)
+    }
+
+    /// First item after `after`, or the first item if `None`
+    pub fn next(&self, directory: &DirectoryType, after: Option<&Item>) -> Result<Option<Item>> {
+        let mut next: Option<Item> = None;
+        self.scan(directory, |item| {
+            if after.is_none_or(|after| item > *after) && next.is_none_or(|next| item < next) {
+                next = Some(item);
+            }
+        })?;
+        Ok(next)
+    }
+
+    /// Last item before `before`
+    pub fn previous(&self, directory: &DirectoryType, before: &Item) -> Result<Option<Item>> {
+        let mut previous: Option<Item> = None;
+        self.scan(directory, |item| {
+            if item < *before && previous.is_none_or(|previous| item > previous) {
+                previous = Some(item);
+            }
+        })?;
+        Ok(previous)
+    }
+
+    /// Item at `index` in sort order
+    pub fn nth(&self, directory: &DirectoryType, index: usize) -> Result<Option<Item>> {
+        let mut item = self.next(directory, None)?;
+        for _ in 0..index {
+            match item {
+                Some(current) => item = self.next(directory, Some(&current))?,
+                None => break,
+            }
+        }
+        Ok(item)
+    }
+
+    /// Index of `item` in sort order
+    pub fn position(&self, directory: &DirectoryType, item: &Item) -> Result<usize> {
+        let mut position = 0;
+        self.scan(directory, |i| {
+            if i < *item {
+                position += 1;
+            }
+        })?;
+        Ok(position)
+    }
+
+    fn folder_count(&self, directory: &DirectoryType) -> Result<usize> {
+        let mut count = 0;
+        self.scan(directory, |item| {
+            if matches!(item, Item::Folder(_)) {
+                count += 1;
+            }
+        })?;
+        Ok(count)
+    }
+}
+
+impl Library {
+    /// The video after `name`, wrapping around to the first video
+    pub fn next_video(
+        &self,
+        directory: &DirectoryType,
+        name: ShortFileName,
+    ) -> Result<Option<ShortFileName>> {
+        match self.next(directory, Some(&Item::Video(name)))? {
+            Some(item) => Ok(Some(*item.name())),
+            None => self.first_video(directory),
+        }
+    }
+
+    /// The video before `name`, or `name` if it is the first video
+    pub fn previous_video(
+        &self,
+        directory: &DirectoryType,
+        name: ShortFileName,
+    ) -> Result<ShortFileName> {
+        match self.previous(directory, &Item::Video(name))? {
+            Some(Item::Video(previous)) => Ok(previous),
+            _ => Ok(name),
+        }
+    }
+
+    /// The video at `random` modulo the number of videos
+    pub fn random_video(
+        &self,
+        directory: &DirectoryType,
+        random: u32,
+    ) -> Result<Option<ShortFileName>> {
+        let folders = self.folder_count(directory)?;
+        let videos = self.video_count(directory)?;
+        if videos == 0 {
+            return Ok(None);
+        }
+        let index = folders + random as usize % videos;
+        Ok(self.nth(directory, index)?.map(|item| *item.name()))
+    }
+
+    /// Number of videos, not counting folders
+    pub fn video_count(&self, directory: &DirectoryType) -> Result<usize> {
+        Ok(self.count(directory)? - self.folder_count(directory)?)
+    }
+
+    /// The first video in sort order
+    pub fn first_video(&self, directory: &DirectoryType) -> Result<Option<ShortFileName>> {
+        // Folders sort before videos
+        let mut item = self.next(directory, None)?;
+        while let Some(Item::Folder(_)) = item {
+            item = self.next(directory, item.as_ref())?;
+        }
+        Ok(item.map(|item| *item.name()))
+    }
+}
@@ -0,0 +1,473 @@
+use core::{convert::Infallible, fmt};
+
+use crate::{
+    display::SIZE,
+    error::Error,
+    library::{Folder, Item, Library},
+    power::Power,
+    sdcard::DirectoryType,
+    touch::Touch,
+};
+use cyd_core::gesture::{Direction, Gesture};
+use cyd_encoder::format::metadata;
+use embedded_graphics::{
+    mono_font::{
+        MonoTextStyle,
+        ascii::{FONT_6X10, FONT_8X13},
+    },
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use embedded_io::Read;
+use embedded_sdmmc::{Mode, SdCardError, ShortFileName};
+use esp_hal::delay::Delay;
+
+const HEADER_HEIGHT: u32 = 20;
+const FOOTER_HEIGHT: u32 = 28;
+const ROW_HEIGHT: u32 = metadata::POSTER_HEIGHT as u32 + 4;
+const PAGE_SIZE: usize = ((SIZE.height - HEADER_HEIGHT - FOOTER_HEIGHT) / ROW_HEIGHT) as usize;
+const FOOTER_TOP: i32 = (SIZE.height - FOOTER_HEIGHT) as i32;
+
+const HIGHLIGHT: Rgb565 = Rgb565::CSS_GOLD;
+
+type MenuError<DI> = Error<embedded_sdmmc::Error<SdCardError>, Infallible, DI>;
+
+struct Entry {
+    name: ShortFileName,
+    title: [u8; metadata::MAX_TITLE_LEN],
+    title_len: usize,
+}
+
+impl Entry {
+    fn title(&self) -> &str {
+        metadata::parse_title(&self.title[..self.title_len])
+    }
+}
+
+enum Browse {
+    Play(ShortFileName),
+    Enter(ShortFileName),
+    Back,
+    Empty,
+    Idle,
+}
+
+/// How [`Menu::select`] ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// A video was tapped
+    Video(ShortFileName),
+    /// There are no videos or folders
+    Empty,
+    /// Nothing was touched for the idle period of `power`
+    Idle,
+}
+
+/// Paged list of the folders and videos in the video directory
+pub struct Menu<'a> {
+    title: &'a str,
+    folder: Folder,
+    page: usize,
+    // Item to show the page of next time
+    focus: Option<Item>,
+}
+
+impl<'a> Menu<'a> {
+    pub fn new(title: &'a str) -> Self {
+        Self {
+            title,
+            folder: Folder::default(),
+            page: 0,
+            focus: None,
+        }
+    }
+
+    /// Folder of the last selected video
+    pub fn folder(&self) -> &Folder {
+        &self.folder
+    }
+
+    /// Browse `folder` next time
+    pub fn open(&mut self, folder: Folder) {
+        self.folder = folder;
+        self.page = 0;
+    }
+
+    /// Show the page containing video `name` next time
+    pub fn show(&mut self, name: ShortFileName) {
+        self.focus = Some(Item::Video(name));
+    }
+
+    /// Display the menu until a video is tapped or the player is idle.
+    /// The video is in [`Menu::folder`] below `directory`.
+    pub fn select<DT>(
+        &mut self,
+        directory: &DirectoryType,
+        display: &mut DT,
+        touch: &mut Touch,
+        power: &mut Power,
+    ) -> Result<Selection, MenuError<DT::Error>>
+    where
+        DT: DrawTarget<Color = Rgb565>,
+        DT::Error: fmt::Debug,
+    {
+        loop {
+            let folder = self.folder;
+            match folder.open(directory, |directory| {
+                self.browse(directory, display, touch, power)
+            })? {
+                Browse::Play(name) => return Ok(Selection::Video(name)),
+                Browse::Enter(name) => {
+                    if self.folder.push(name) {
+                        self.page = 0;
+                    } else {
+                        log::warn!("{name} nested too deep");
+                    }
+                }
+                Browse::Back => {
+                    self.focus = self.folder.pop().map(Item::Folder);
+                }
+                Browse::Empty if self.folder.depth() > 0 => {
+                    self.focus = self.folder.pop().map(Item::Folder);
+                }
+                Browse::Empty => return Ok(Selection::Empty),
+                Browse::Idle => return Ok(Selection::Idle),
+            }
+        }
+    }
+
+    fn browse<DT>(
+        &mut self,
+        directory: &DirectoryType,
+        display: &mut DT,
+        touch: &mut Touch,
+        power: &mut Power,
+    ) -> Result<Browse, MenuError<DT::Error>>
+    where
+        DT: DrawTarget<Color = Rgb565>,
+        DT::Error: fmt::Debug,
+    {
+        let library = Library::new(directory)?;
+        let count = library.count(directory)?;
+        if count == 0 {
+            return Ok(Browse::Empty);
+        }
+        let pages = count.div_ceil(PAGE_SIZE);
+        if let Some(focus) = self.focus.take() {
+            self.page = library.position(directory, &focus)? / PAGE_SIZE;
+        }
+        self.page = self.page.min(pages - 1);
+        let delay = Delay::new();
+        loop {
+            let items = self.draw_page(&library, directory, display, pages)?;
+            let page = loop {
+                delay.delay_millis(20);
+                let gesture = touch.poll_gesture();
+                if power.is_idle(touch.is_active()) {
+                    return Ok(Browse::Idle);
+                }
+                match gesture {
+                    Some(Gesture::Tap(point)) if point.y < HEADER_HEIGHT as i32 => {
+                        if self.folder.depth() > 0 {
+                            return Ok(Browse::Back);
+                        }
+                    }
+                    Some(Gesture::Tap(point)) if point.y >= FOOTER_TOP => {
+                        if point.x < (SIZE.width / 2) as i32 {
+                            break self.page.checked_sub(1);
+                        } else {
+                            break Some(self.page + 1).filter(|&page| page < pages);
+                        }
+                    }
+                    Some(Gesture::Tap(point)) => {
+                        let row = ((point.y as u32 - HEADER_HEIGHT) / ROW_HEIGHT) as usize;
+                        match items.get(row) {
+                            Some(Some(Item::Video(name))) => return Ok(Browse::Play(*name)),
+                            Some(Some(Item::Folder(name))) => return Ok(Browse::Enter(*name)),
+                            _ => {}
+                        }
+                    }
+                    Some(Gesture::Swipe(Direction::Left)) => {
+                        break Some(self.page + 1).filter(|&page| page < pages);
+                    }
+                    Some(Gesture::Swipe(Direction::Right)) => break self.page.checked_sub(1),
+                    _ => {}
+                }
+            };
+            if let Some(page) = page {
+                self.page = page;
+            }
+        }
+    }
+
+    fn draw_page<DT>(
+        &self,
+        library: &Library,
+        directory: &DirectoryType,
+        display: &mut DT,
+        pages: usize,
+    ) -> Result<[Option<Item>; PAGE_SIZE], MenuError<DT::Error>>
+    where
+        DT: DrawTarget<Color = Rgb565>,
+        DT::Error: fmt::Debug,
+    {
+        let mut items = [None; PAGE_SIZE];
+        let mut item = library.nth(directory, self.page * PAGE_SIZE)?;
+        for slot in items.iter_mut() {
+            let Some(current) = item else {
+                break;
+            };
+            *slot = Some(current);
+            item = library.next(directory, Some(&current))?;
+        }
+
+        display.clear(Rgb565::BLACK).map_err(Error::DisplayError)?;
+        let small = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+        let large = MonoTextStyle::new(&FONT_8X13, Rgb565::WHITE);
+        let highlight = MonoTextStyle::new(&FONT_8X13, HIGHLIGHT);
+
+        let mut header = Point::new(4, 4);
+        if self.folder.depth() > 0 {
+            header = Text::with_baseline("< Back  ", header, small, Baseline::Top)
+                .draw(display)
+                .map_err(Error::DisplayError)?;
+        }
+        header = Text::with_baseline(self.title, header, small, Baseline::Top)
+            .draw(display)
+            .map_err(Error::DisplayError)?;
+        for name in self.folder.names() {
+            let mut buf = [0u8; 14];
+            let name = format_no_std::show(&mut buf, format_args!("/{name}")).unwrap_or_default();
+            header = Text::with_baseline(name, header, small, Baseline::Top)
+                .draw(display)
+                .map_err(Error::DisplayError)?;
+        }
+        let mut buf = [0u8; 32];
+        let page = format_no_std::show(&mut buf, format_args!("  {}/{}", self.page + 1, pages))
+            .unwrap_or_default();
+        Text::with_baseline(page, header, small, Baseline::Top)
+            .draw(display)
+            .map_err(Error::DisplayError)?;
+
+        for (row, item) in items.iter().enumerate() {
+            let Some(item) = item else {
+                break;
+            };
+            let top = (HEADER_HEIGHT + row as u32 * ROW_HEIGHT) as i32;
+            let poster = Rectangle::new(
+                Point::new(4, top + 2),
+                Size::new(
+                    metadata::POSTER_WIDTH as u32,
+                    metadata::POSTER_HEIGHT as u32,
+                ),
+            );
+            let text_left = poster.top_left.x + poster.size.width as i32 + 8;
+            let mut buf = [0u8; 13];
+
+            let name = match item {
+                Item::Folder(name) => {
+                    draw_folder(&poster, display).map_err(Error::DisplayError)?;
+                    let name =
+                        format_no_std::show(&mut buf, format_args!("{name}/")).unwrap_or_default();
+                    Text::with_baseline(
+                        name,
+                        Point::new(text_left, top + 16),
+                        large,
+                        Baseline::Top,
+                    )
+                    .draw(display)
+                    .map_err(Error::DisplayError)?;
+                    continue;
+                }
+                Item::Video(name) => *name,
+            };
+
+            let entry = read_entry(directory, name);
+            if !draw_poster(directory, name, &poster, display)? {
+                poster
+                    .into_styled(PrimitiveStyle::with_stroke(Rgb565::CSS_DIM_GRAY, 1))
+                    .draw(display)
+                    .map_err(Error::DisplayError)?;
+            }
+
+            let filename =
+                format_no_std::show(&mut buf, format_args!("{name}")).unwrap_or_default();
+            let title = entry
+                .as_ref()
+                .map(Entry::title)
+                .filter(|title| !title.is_empty());
+            Text::with_baseline(
+                title.unwrap_or(filename),
+                Point::new(text_left, top + 16),
+                large,
+                Baseline::Top,
+            )
+            .draw(display)
+            .map_err(Error::DisplayError)?;
+            if title.is_some() {
+                Text::with_baseline(
+                    filename,
+                    Point::new(text_left, top + 34),
+                    small,
+                    Baseline::Top,
+                )
+                .draw(display)
+                .map_err(Error::DisplayError)?;
+            }
+        }
+
+        if self.page > 0 {
+            Text::with_baseline(
+                "< Prev",
+                Point::new(8, FOOTER_TOP + 8),
+                highlight,
+                Baseline::Top,
+            )
+            .draw(display)
+            .map_err(Error::DisplayError)?;
+        }
+        if self.page + 1 < pages {
+            Text::with_baseline(
+                "Next >",
+                Point::new(SIZE.width as i32 - 8 - 6 * 8, FOOTER_TOP + 8),
+                highlight,
+                Baseline::Top,
+            )
+            .draw(display)
+            .map_err(Error::DisplayError)?;
+        }
+
+        Ok(items)
+    }
+}
+
+fn draw_folder<DT>(area: &Rectangle, display: &mut DT) -> Result<(), DT::Error>
+where
+    DT: DrawTarget<Color = Rgb565>,
+{
+    let style = PrimitiveStyle::with_fill(HIGHLIGHT);
+    let width = area.size.width * 3 / 4;
+    let height = area.size.height / 2;
+    let body = Rectangle::with_center(area.center(), Size::new(width, height));
+    // Tab above the folder body
+    Rectangle::new(body.top_left - Point::new(0, 6), Size::new(width / 3, 6))
+        .into_styled(style)
+        .draw(display)?;
+    body.into_styled(style).draw(display)
+}
+
+/// Name of the sidecar file for `name` with `extension`
+fn sidecar<'a>(name: &ShortFileName, extension: &str, buf: &'a mut [u8; 12]) -> Option<&'a str> {
+    let base = name.base_name();
+    let len = base.len() + 1 + extension.len();
+    if len > buf.len() {
+        return None;
+    }
+    buf[..base.len()].copy_from_slice(base);
+    buf[base.len()] = b'.';
+    buf[base.len() + 1..len].copy_from_slice(extension.as_bytes());
+    str::from_utf8(&buf[..len]).ok()
+}
+
+fn read_entry(directory: &DirectoryType, name: ShortFileName) -> Option<Entry> {
+    let mut buf = [0u8; 12];
+    let title_name = sidecar(&name, metadata::TITLE_EXTENSION, &mut buf)?;
+    let mut file = directory
+        .open_file_in_dir(title_name, Mode::ReadOnly)
+        .ok()?;
+    let mut entry = Entry {
+        name,
+        title: [0u8; metadata::MAX_TITLE_LEN],
+        title_len: 0,
+    };
+    entry.title_len = file.read(&mut entry.title).ok()?;
+    log::debug!("{} title {}", entry.name, entry.title());
+    Some(entry)
+}
+
+#[cfg(feature = "mjpeg")]
+fn draw_poster<DT>(
+    directory: &DirectoryType,
+    name: ShortFileName,
+    area: &Rectangle,
+    display: &mut DT,
+) -> Result<bool, MenuError<DT::Error>>
+where
+    DT: DrawTarget<Color = Rgb565>,
+    DT::Error: fmt::Debug,
+{
+    use cyd_core::jpeg::{ARENA_SIZE, JpegDrawable, MAX_SCALE};
+    use embedded_graphics::image::Image;
+
+    let mut buf = [0u8; 12];
+    let Some(poster_name) = sidecar(&name, metadata::POSTER_EXTENSION, &mut buf) else {
+        return Ok(false);
+    };
+    let Ok(mut file) = directory.open_file_in_dir(poster_name, Mode::ReadOnly) else {
+        return Ok(false);
+    };
+    let mut buffer = [0u8; ARENA_SIZE + metadata::MAX_POSTER_SIZE];
+    let (arena, jpeg_buffer) = buffer.split_first_chunk_mut::<ARENA_SIZE>().unwrap();
+    let mut len = 0;
+    loop {
+        match file.read(&mut jpeg_buffer[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) => {
+                log::warn!("{poster_name} read error: {e:?}");
+                return Ok(false);
+            }
+        }
+        if len == jpeg_buffer.len() {
+            log::warn!("{poster_name} too large");
+            return Ok(false);
+        }
+    }
+
+    let jpeg_data = &jpeg_buffer[..len];
+    let size = match JpegDrawable::with_scale(arena, jpeg_data, 0) {
+        Ok(poster) => poster.size(),
+        Err(e) => {
+            log::warn!("{poster_name} decode error: {e:?}");
+            return Ok(false);
+        }
+    };
+    // Find the smallest scale down that fits
+    let scale = (0..MAX_SCALE)
+        .find(|scale| {
+            size.width.div_ceil(1 << scale) <= area.size.width
+                && size.height.div_ceil(1 << scale) <= area.size.height
+        })
+        .unwrap_or(MAX_SCALE);
+    let poster = match JpegDrawable::with_scale(arena, jpeg_data, scale) {
+        Ok(poster) => poster,
+        Err(e) => {
+            log::warn!("{poster_name} decode error: {e:?}");
+            return Ok(false);
+        }
+    };
+    let top_left = area.center() - poster.size() / 2;
+    Image::new(&poster, top_left)
+        .draw(&mut display.clipped(area))
+        .map_err(Error::DisplayError)?;
+    if let Some(e) = poster.take_error() {
+        log::warn!("{poster_name} decode error: {e:?}");
+    }
+    Ok(true)
+}
+
+#[cfg(not(feature = "mjpeg"))]
+fn draw_poster<DT>(
+    _directory: &DirectoryType,
+    _name: ShortFileName,
+    _area: &Rectangle,
+    _display: &mut DT,
+) -> Result<bool, MenuError<DT::Error>>
+where
+    DT: DrawTarget<Color = Rgb565>,
+    DT::Error: fmt::Debug,
+{
+    Ok(false)
+}
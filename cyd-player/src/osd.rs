@@ -0,0 +1,257 @@
+//! On-screen display of the title, progress and frame rate of the playing video.
+//!
+//! The overlay is a strip along the bottom of the display. Frames are clipped above it
+//! while it is shown, so they never draw over it and it doesn't flicker. Videos with a
+//! letterbox below them leave room for it and aren't clipped at all.
+
+use crate::display::SIZE;
+use cyd_encoder::settings::Overlay;
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, MonoTextStyleBuilder, ascii::FONT_6X10},
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use esp_hal::time::{Duration, Instant};
+
+/// Height of the strip
+pub const HEIGHT: u32 = 18;
+const TOP: i32 = (SIZE.height - HEIGHT) as i32;
+const MARGIN: i32 = 4;
+const BAR_WIDTH: u32 = SIZE.width - 2 * MARGIN as u32;
+const BAR_HEIGHT: u32 = 3;
+// Text starts below the progress bar
+const TEXT_TOP: i32 = TOP + 6;
+// Room for an 8.3 file name
+const TITLE_WIDTH: i32 = 13 * 6;
+
+const BACKGROUND: Rgb565 = Rgb565::BLACK;
+const BAR: Rgb565 = Rgb565::new(0, 40, 31);
+const BAR_BACKGROUND: Rgb565 = Rgb565::new(6, 12, 6);
+
+// How long the overlay is shown after the video starts or the screen is touched
+const SHOW_DURATION: Duration = Duration::from_secs(3);
+
+/// What the overlay shows, it is only redrawn when this changes
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Status {
+    elapsed: u32,
+    total: Option<u32>,
+    bar: u32,
+    fps: u32,
+    lag_ms: u64,
+}
+
+/// Frame rate and worst lag measured over about a second
+struct Rate {
+    start: Instant,
+    frames: u32,
+    max_lag: Duration,
+    fps: u32,
+    lag: Duration,
+}
+
+impl Rate {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            frames: 0,
+            max_lag: Duration::ZERO,
+            fps: 0,
+            lag: Duration::ZERO,
+        }
+    }
+
+    fn frame(&mut self, lag: Duration) {
+        self.frames += 1;
+        if lag > self.max_lag {
+            self.max_lag = lag;
+        }
+        let elapsed = self.start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let fps = (self.frames as u64 * 1000 / elapsed.as_millis()) as u32;
+            *self = Self {
+                fps,
+                lag: self.max_lag,
+                ..Self::new()
+            };
+        }
+    }
+}
+
+/// Overlay of the video being played
+pub struct Osd<'a> {
+    mode: Overlay,
+    title: &'a str,
+    visible_until: Instant,
+    // The strip is on the display
+    shown: bool,
+    drawn: Option<Status>,
+    rate: Rate,
+}
+
+impl<'a> Osd<'a> {
+    /// Overlay for the video `title`, shown as it starts unless `mode` is off
+    pub fn new(mode: Overlay, title: &'a str) -> Self {
+        Self {
+            mode,
+            title,
+            visible_until: Instant::now() + SHOW_DURATION,
+            shown: false,
+            drawn: None,
+            rate: Rate::new(),
+        }
+    }
+
+    /// Show the overlay for a while after the screen was touched
+    pub fn touched(&mut self) {
+        self.visible_until = Instant::now() + SHOW_DURATION;
+    }
+
+    fn is_visible(&self) -> bool {
+        match self.mode {
+            Overlay::Off => false,
+            Overlay::Touch => Instant::now() < self.visible_until,
+            Overlay::Always => true,
+        }
+    }
+
+    /// Call before drawing a frame, returns the row the frame is clipped at.
+    /// Clears the overlay once it is hidden, the frame then draws over its area again.
+    pub fn begin_frame<D>(&mut self, display: &mut D) -> Result<i32, D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let visible = self.is_visible();
+        if self.shown && !visible {
+            strip()
+                .into_styled(PrimitiveStyle::with_fill(BACKGROUND))
+                .draw(display)?;
+            self.shown = false;
+        }
+        Ok(if visible { TOP } else { i32::MAX })
+    }
+
+    /// Call after drawing frame number `frame` of `frames`, measures the frame rate and
+    /// draws the overlay if it is shown
+    pub fn end_frame<D>(
+        &mut self,
+        display: &mut D,
+        frame: u32,
+        frames: Option<u32>,
+        fps: u8,
+        lag: Duration,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        self.rate.frame(lag);
+        if !self.is_visible() {
+            return Ok(());
+        }
+        let text = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(Rgb565::WHITE)
+            .background_color(BACKGROUND)
+            .build();
+        if !self.shown {
+            strip()
+                .into_styled(PrimitiveStyle::with_fill(BACKGROUND))
+                .draw(display)?;
+            Text::with_baseline(
+                self.title,
+                Point::new(MARGIN, TEXT_TOP),
+                text,
+                Baseline::Top,
+            )
+            .draw(display)?;
+            self.shown = true;
+            self.drawn = None;
+        }
+
+        let fps = fps.max(1) as u32;
+        let status = Status {
+            elapsed: frame / fps,
+            total: frames.map(|frames| frames / fps),
+            bar: frames.filter(|&frames| frames > 0).map_or(0, |frames| {
+                (BAR_WIDTH as u64 * frame.min(frames) as u64 / frames as u64) as u32
+            }),
+            fps: self.rate.fps,
+            lag_ms: self.rate.lag.as_millis(),
+        };
+        if self.drawn == Some(status) {
+            return Ok(());
+        }
+        self.draw(display, &status, text)?;
+        self.drawn = Some(status);
+        Ok(())
+    }
+
+    fn draw<D>(
+        &self,
+        display: &mut D,
+        status: &Status,
+        text: MonoTextStyle<'_, Rgb565>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let played = status.bar.min(BAR_WIDTH);
+        Rectangle::new(Point::new(MARGIN, TOP + 1), Size::new(played, BAR_HEIGHT))
+            .into_styled(PrimitiveStyle::with_fill(BAR))
+            .draw(display)?;
+        Rectangle::new(
+            Point::new(MARGIN + played as i32, TOP + 1),
+            Size::new(BAR_WIDTH - played, BAR_HEIGHT),
+        )
+        .into_styled(PrimitiveStyle::with_fill(BAR_BACKGROUND))
+        .draw(display)?;
+
+        let mut buf = [0u8; 32];
+        let (minutes, seconds) = (status.elapsed / 60, status.elapsed % 60);
+        let time = match status.total {
+            Some(total) => format_no_std::show(
+                &mut buf,
+                format_args!(
+                    "{minutes:2}:{seconds:02} / {:2}:{:02}",
+                    total / 60,
+                    total % 60
+                ),
+            ),
+            None => format_no_std::show(&mut buf, format_args!("{minutes:2}:{seconds:02}")),
+        }
+        .unwrap_or_default();
+        Text::with_baseline(
+            time,
+            Point::new(MARGIN + TITLE_WIDTH, TEXT_TOP),
+            text,
+            Baseline::Top,
+        )
+        .draw(display)?;
+
+        let mut buf = [0u8; 32];
+        let rate = format_no_std::show(
+            &mut buf,
+            format_args!("{:3} fps  lag {:4} ms", status.fps, status.lag_ms),
+        )
+        .unwrap_or_default();
+        let right = TextStyleBuilder::new()
+            .alignment(Alignment::Right)
+            .baseline(Baseline::Top)
+            .build();
+        Text::with_text_style(
+            rate,
+            Point::new(SIZE.width as i32 - MARGIN, TEXT_TOP),
+            text,
+            right,
+        )
+        .draw(display)?;
+        Ok(())
+    }
+}
+
+/// Area of the overlay
+fn strip() -> Rectangle {
+    Rectangle::new(Point::new(0, TOP), Size::new(SIZE.width, HEIGHT))
+}
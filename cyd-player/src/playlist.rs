@@ -0,0 +1,139 @@
+use crate::{
+    library::{self, Folder},
+    sdcard::DirectoryType,
+};
+use cyd_encoder::playlist::{self as format, Entry, EntryOptions, Parser};
+use embedded_io::Read;
+use embedded_sdmmc::{Mode, ShortFileName};
+
+// Longest playlist line, longer lines are skipped
+const MAX_LINE: usize = 128;
+
+/// Playlist entry resolved to a video in a folder below the video directory
+pub struct PlaylistEntry {
+    pub folder: Folder,
+    pub name: ShortFileName,
+    pub options: EntryOptions,
+}
+
+impl PlaylistEntry {
+    fn resolve(entry: &Entry) -> Option<Self> {
+        let mut folder = Folder::default();
+        let mut components = entry.components().peekable();
+        while let Some(component) = components.next() {
+            let name = match ShortFileName::create_from_str(component) {
+                Ok(name) => name,
+                Err(e) => {
+                    log::warn!("playlist {}: invalid name {e:?}", entry.path);
+                    return None;
+                }
+            };
+            if components.peek().is_none() {
+                return Some(Self {
+                    folder,
+                    name,
+                    options: entry.options,
+                });
+            }
+            if !folder.push(name) {
+                log::warn!("playlist {}: nested too deep", entry.path);
+                return None;
+            }
+        }
+        None
+    }
+}
+
+/// Playlist file in the video directory.
+/// Entries are read by index by rereading the file, so playlists can be any length.
+pub struct Playlist {
+    name: ShortFileName,
+}
+
+impl Playlist {
+    /// Find `PLAYLIST.M3U`, or else the first `*.CYP` file by name
+    pub fn find(directory: &DirectoryType) -> library::Result<Option<Self>> {
+        let (default_base, default_extension) = format::DEFAULT_NAME.split_once('.').unwrap();
+        let mut found: Option<ShortFileName> = None;
+        let mut default = false;
+        directory.iterate_dir(|entry| {
+            if default || entry.attributes.is_directory() {
+                return;
+            }
+            if entry.name.base_name() == default_base.as_bytes()
+                && entry.name.extension() == default_extension.as_bytes()
+            {
+                found = Some(entry.name);
+                default = true;
+            } else if entry.name.extension() == format::EXTENSION.as_bytes()
+                && found.is_none_or(|found| entry.name < found)
+            {
+                found = Some(entry.name);
+            }
+        })?;
+        Ok(found.map(|name| Self { name }))
+    }
+
+    pub fn name(&self) -> &ShortFileName {
+        &self.name
+    }
+
+    /// Entry at `index`, invalid lines and entries are skipped
+    pub fn entry(
+        &self,
+        directory: &DirectoryType,
+        index: usize,
+    ) -> library::Result<Option<PlaylistEntry>> {
+        let mut file = directory.open_file_in_dir(self.name, Mode::ReadOnly)?;
+        let mut parser = Parser::new();
+        let mut buffer = [0u8; MAX_LINE];
+        let mut valid = 0;
+        let mut eof = false;
+        let mut skipping = false;
+        let mut line_number = 0;
+        let mut count = 0;
+        loop {
+            if !eof && valid < buffer.len() {
+                let read = file.read(&mut buffer[valid..])?;
+                eof = read == 0;
+                valid += read;
+            }
+            let (line_len, consumed) = match buffer[..valid].iter().position(|&b| b == b'\n') {
+                Some(position) => (position, position + 1),
+                None if eof && valid == 0 => return Ok(None),
+                None if eof => (valid, valid),
+                None if valid == buffer.len() => {
+                    if !skipping {
+                        log::warn!("{} line {}: too long", self.name, line_number + 1);
+                    }
+                    skipping = true;
+                    valid = 0;
+                    continue;
+                }
+                None => continue,
+            };
+            line_number += 1;
+            if skipping {
+                skipping = false;
+            } else {
+                match str::from_utf8(&buffer[..line_len]) {
+                    Ok(line) => match parser.parse_line(line) {
+                        Ok(Some(entry)) => {
+                            if let Some(entry) = PlaylistEntry::resolve(&entry) {
+                                if count == index {
+                                    return Ok(Some(entry));
+                                }
+                                count += 1;
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => log::warn!("{} line {line_number}: {e:?}", self.name),
+                    },
+                    Err(_) => log::warn!("{} line {line_number}: invalid UTF-8", self.name),
+                }
+            }
+            buffer.copy_within(consumed..valid, 0);
+            valid -= consumed;
+        }
+    }
+}
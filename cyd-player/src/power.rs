@@ -0,0 +1,88 @@
+//! Light sleep between frames and deep sleep when idle.
+//!
+//! The timer behind `Instant` may stop in light sleep. Frame pacing only measures the
+//! work on a frame, not the wait after it, so it isn't affected.
+//!
+//! The APB clock stops in light sleep, and with it the backlight PWM and the display
+//! SPI DMA. So light sleep only happens with the backlight off and the display idle.
+
+use crate::{backlight::Backlight, display};
+use cyd_encoder::settings::Settings;
+use esp_hal::{
+    delay::Delay,
+    peripherals::{GPIO36, LPWR},
+    rtc_cntl::{
+        Rtc,
+        sleep::{Ext0WakeupSource, TimerWakeupSource, WakeupLevel},
+    },
+    time::{Duration, Instant},
+};
+
+// Shorter waits aren't worth the time it takes to enter and leave light sleep
+const LIGHT_SLEEP_MIN: Duration = Duration::from_millis(5);
+// About how long waking from light sleep takes
+const WAKE_TIME: Duration = Duration::from_millis(1);
+
+pub struct Power {
+    rtc: Rtc<'static>,
+    light_sleep: bool,
+    sleep_after: Option<Duration>,
+    last_active: Instant,
+}
+
+impl Power {
+    pub fn new(lpwr: LPWR<'static>) -> Self {
+        Self {
+            rtc: Rtc::new(lpwr),
+            light_sleep: false,
+            sleep_after: None,
+            last_active: Instant::now(),
+        }
+    }
+
+    pub fn configure(&mut self, settings: &Settings) {
+        // The APP core keeps decoding while the PRO core waits
+        self.light_sleep = settings.light_sleep && !cfg!(feature = "dual-core");
+        self.sleep_after =
+            (settings.sleep_after > 0).then(|| Duration::from_secs(settings.sleep_after as u64));
+    }
+
+    /// Wait for `duration`, in light sleep if it is long enough and `backlight` is off
+    pub fn wait(&mut self, duration: Duration, backlight: &Backlight) {
+        // Sleeping would stop the PWM and flash the backlight fully on or off
+        if !self.light_sleep || backlight.is_on() || duration < LIGHT_SLEEP_MIN {
+            Delay::new().delay(duration);
+            return;
+        }
+        // Don't leave a band half sent
+        display::flush();
+        let sleep = core::time::Duration::from_micros((duration - WAKE_TIME).as_micros());
+        self.rtc.sleep_light(&[&TimerWakeupSource::new(sleep)]);
+    }
+
+    /// Restart the idle timer
+    pub fn active(&mut self) {
+        self.last_active = Instant::now();
+    }
+
+    /// Whether the player was idle long enough to sleep, call while waiting for a touch.
+    /// `touched` restarts the idle timer.
+    pub fn is_idle(&mut self, touched: bool) -> bool {
+        if touched {
+            self.active();
+        }
+        self.sleep_after
+            .is_some_and(|after| self.last_active.elapsed() >= after)
+    }
+
+    /// Enter deep sleep until the screen is touched, which restarts the player
+    pub fn deep_sleep(&mut self) -> ! {
+        log::info!("Sleeping until touched");
+        // SAFETY: the touch IRQ input is only read by its interrupt handler,
+        // and nothing runs once asleep
+        let irq = unsafe { GPIO36::steal() };
+        // The XPT2046 pulls its IRQ line low while touched
+        let touch = Ext0WakeupSource::new(irq, WakeupLevel::Low);
+        self.rtc.sleep_deep(&[&touch])
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{
+    library::{self, Folder, MAX_DEPTH},
+    sdcard::DirectoryType,
+};
+use embedded_io::{Read, Write};
+use embedded_sdmmc::{Mode, ShortFileName};
+use esp_hal::time::{Duration, Instant};
+
+// Written alternately, so a write cut short by power loss leaves the other slot intact
+const SLOTS: [&str; 2] = ["RESUME.0", "RESUME.1"];
+const MAGIC: &[u8; 4] = b"CYDR";
+// Save at most this often to limit SD card wear
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+// 8.3 name without the dot, space padded
+const NAME_SIZE: usize = 11;
+// Magic, sequence, playlist entry, folders, name, frame, checksum
+const RECORD_SIZE: usize = 4 + 4 + 4 + MAX_DEPTH * NAME_SIZE + NAME_SIZE + 4 + 4;
+const NO_ENTRY: u32 = u32::MAX;
+
+/// Position to resume playback at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumePoint {
+    /// Playlist entry index, or `None` if the video was selected in the menu
+    pub entry: Option<u32>,
+    pub folder: Folder,
+    pub name: ShortFileName,
+    pub frame: u32,
+}
+
+impl ResumePoint {
+    /// Whether the video still exists below `directory`
+    pub fn exists(&self, directory: &DirectoryType) -> bool {
+        self.folder
+            .open(directory, |directory| {
+                directory.find_directory_entry(self.name)
+            })
+            .is_ok_and(|entry| !entry.attributes.is_directory())
+    }
+
+    fn encode(&self, sequence: u32) -> [u8; RECORD_SIZE] {
+        let mut record = [0u8; RECORD_SIZE];
+        let (header, mut rest) = record.split_at_mut(12);
+        header[..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        header[8..].copy_from_slice(&self.entry.unwrap_or(NO_ENTRY).to_le_bytes());
+        let mut names = self.folder.names();
+        for _ in 0..MAX_DEPTH {
+            let (slot, remaining) = rest.split_at_mut(NAME_SIZE);
+            if let Some(name) = names.next() {
+                encode_name(name, slot);
+            }
+            rest = remaining;
+        }
+        let (slot, rest) = rest.split_at_mut(NAME_SIZE);
+        encode_name(&self.name, slot);
+        rest[..4].copy_from_slice(&self.frame.to_le_bytes());
+        let checksum = crc32(&record[..RECORD_SIZE - 4]);
+        record[RECORD_SIZE - 4..].copy_from_slice(&checksum.to_le_bytes());
+        record
+    }
+
+    /// Returns the sequence number and resume point of a valid record
+    fn decode(record: &[u8; RECORD_SIZE]) -> Option<(u32, Self)> {
+        let (data, checksum) = record.split_at(RECORD_SIZE - 4);
+        if &data[..4] != MAGIC || crc32(data).to_le_bytes() != checksum {
+            return None;
+        }
+        let sequence = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        let entry = u32::from_le_bytes(data[8..12].try_into().unwrap());
+        let mut folder = Folder::default();
+        let mut rest = &data[12..];
+        for _ in 0..MAX_DEPTH {
+            let (slot, remaining) = rest.split_at(NAME_SIZE);
+            if slot[0] != 0 {
+                folder.push(decode_name(slot)?);
+            }
+            rest = remaining;
+        }
+        let (slot, rest) = rest.split_at(NAME_SIZE);
+        let point = Self {
+            entry: (entry != NO_ENTRY).then_some(entry),
+            folder,
+            name: decode_name(slot)?,
+            frame: u32::from_le_bytes(rest.try_into().unwrap()),
+        };
+        Some((sequence, point))
+    }
+}
+
+fn encode_name(name: &ShortFileName, slot: &mut [u8]) {
+    slot.fill(b' ');
+    slot[..name.base_name().len()].copy_from_slice(name.base_name());
+    slot[8..8 + name.extension().len()].copy_from_slice(name.extension());
+}
+
+fn decode_name(slot: &[u8]) -> Option<ShortFileName> {
+    let (base, extension) = slot.split_at(8);
+    let base = str::from_utf8(base).ok()?.trim_end();
+    let extension = str::from_utf8(extension).ok()?.trim_end();
+    let mut buffer = [0u8; NAME_SIZE + 1];
+    let name = if extension.is_empty() {
+        base
+    } else {
+        format_no_std::show(&mut buffer, format_args!("{base}.{extension}")).ok()?
+    };
+    ShortFileName::create_from_str(name).ok()
+}
+
+/// CRC-32 (IEEE) to detect records torn by power loss
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Saves the playback position in the video directory, so playback resumes after a power cycle
+pub struct Resume {
+    sequence: u32,
+    // Last point saved or attempted to
+    saved: Option<ResumePoint>,
+    saved_at: Option<Instant>,
+}
+
+impl Resume {
+    /// Read the newest valid resume point from `directory`
+    pub fn load(directory: &DirectoryType) -> library::Result<(Self, Option<ResumePoint>)> {
+        let mut newest: Option<(u32, ResumePoint)> = None;
+        for slot in SLOTS {
+            let mut file = match directory.open_file_in_dir(slot, Mode::ReadOnly) {
+                Ok(file) => file,
+                Err(embedded_sdmmc::Error::NotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            let mut record = [0u8; RECORD_SIZE];
+            let mut len = 0;
+            while len < record.len() {
+                match file.read(&mut record[len..])? {
+                    0 => break,
+                    read => len += read,
+                }
+            }
+            file.close()?;
+            match ResumePoint::decode(&record) {
+                Some((sequence, point)) if newest.is_none_or(|(newest, _)| sequence > newest) => {
+                    newest = Some((sequence, point));
+                }
+                Some(_) => {}
+                None => log::warn!("{slot} is invalid"),
+            }
+        }
+        let resume = Self {
+            sequence: newest.map_or(0, |(sequence, _)| sequence),
+            saved: newest.map(|(_, point)| point),
+            saved_at: None,
+        };
+        Ok((resume, newest.map(|(_, point)| point)))
+    }
+
+    /// Save `point` when playing a different video or at most every [`SAVE_INTERVAL`]
+    pub fn update(&mut self, directory: &DirectoryType, point: ResumePoint) -> library::Result<()> {
+        let same_video = self.saved.is_some_and(|saved| {
+            saved.entry == point.entry && saved.folder == point.folder && saved.name == point.name
+        });
+        let due = self
+            .saved_at
+            .is_none_or(|saved_at| saved_at.elapsed() >= SAVE_INTERVAL);
+        if !same_video || (due && self.saved != Some(point)) {
+            // Also after a failed save, so a read-only or full card isn't retried every frame
+            self.saved = Some(point);
+            self.saved_at = Some(Instant::now());
+            self.save(directory, point)?;
+        }
+        Ok(())
+    }
+
+    /// Save `point` to the older slot
+    fn save(&mut self, directory: &DirectoryType, point: ResumePoint) -> library::Result<()> {
+        let sequence = self.sequence.wrapping_add(1);
+        let slot = SLOTS[sequence as usize % SLOTS.len()];
+        let mut file = directory.open_file_in_dir(slot, Mode::ReadWriteCreateOrTruncate)?;
+        file.write_all(&point.encode(sequence))?;
+        file.close()?;
+        self.sequence = sequence;
+        Ok(())
+    }
+}
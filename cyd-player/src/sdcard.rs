@@ -1,9 +1,15 @@
 use crate::error::Error;
 use core::convert::Infallible;
+use cyd_core::{
+    contiguous::{ContiguousFile, FatLayout},
+    stats,
+};
 use embedded_hal::spi::SpiBus;
 use embedded_hal_bus::spi::ExclusiveDevice;
+use embedded_io::{ErrorType, Read, Seek, SeekFrom};
 use embedded_sdmmc::{
-    SdCardError, TimeSource, Timestamp, VolumeIdx, VolumeManager, filesystem::ToShortFileName,
+    Block, BlockCount, BlockDevice, BlockIdx, Mode, SdCardError, ShortFileName, TimeSource,
+    Timestamp, VolumeIdx, VolumeManager, filesystem::ToShortFileName,
 };
 use esp_hal::{
     Blocking,
@@ -11,7 +17,7 @@ use esp_hal::{
     gpio::{Level, Output, OutputConfig},
     peripherals::{GPIO5, GPIO18, GPIO19, GPIO23, SPI3},
     spi::master::{Config as SpiConfig, Spi},
-    time::Rate,
+    time::{Instant, Rate},
 };
 
 pub struct Peripherals {
@@ -25,16 +31,23 @@ pub struct Peripherals {
 type SdCardType =
     embedded_sdmmc::SdCard<ExclusiveDevice<Spi<'static, Blocking>, Output<'static>, Delay>, Delay>;
 type VolumeManagerType = VolumeManager<SdCardType, DummyTimesource, 4, 4, 1>;
-type DirectoryType<'a> = embedded_sdmmc::Directory<'a, SdCardType, DummyTimesource, 4, 4, 1>;
+pub type DirectoryType<'a> = embedded_sdmmc::Directory<'a, SdCardType, DummyTimesource, 4, 4, 1>;
+pub type FileType<'a> = embedded_sdmmc::File<'a, SdCardType, DummyTimesource, 4, 4, 1>;
+pub type SdResult<T> = Result<T, embedded_sdmmc::Error<SdCardError>>;
+
 pub struct SdCard {
-    volume_manager: VolumeManagerType,
+    // Only taken while mounting
+    volume_manager: Option<VolumeManagerType>,
+    // None if the volume can't be read directly
+    layout: Option<FatLayout>,
 }
 
 impl SdCard {
+    /// Set up the SPI bus, the card is initialized by [`SdCard::mount`]
     pub fn new(
         peripherals: Peripherals,
     ) -> Result<Self, Error<Infallible, Infallible, Infallible>> {
-        let mut spi = Spi::new(
+        let spi = Spi::new(
             peripherals.spi3,
             SpiConfig::default().with_frequency(Rate::from_khz(400)), // <=400kHz required for initialization
         )?
@@ -42,17 +55,47 @@ impl SdCard {
         .with_mosi(peripherals.mosi)
         .with_miso(peripherals.miso);
 
-        // Send 74+ clock cycles (10 bytes = 80 cycles)
-        // CS doesn't need to exist yet - it just needs to NOT be asserted
-        let mut dummy = [0xFF; 10];
-        SpiBus::transfer_in_place(&mut spi, &mut dummy)?;
-
         let cs = Output::new(peripherals.cs, Level::High, OutputConfig::default());
         let spi_dev = ExclusiveDevice::new(spi, cs, Delay::new()).unwrap();
         let sdcard = embedded_sdmmc::SdCard::new(spi_dev, Delay::new());
 
+        Ok(Self {
+            volume_manager: Some(VolumeManager::new(sdcard, DummyTimesource)),
+            layout: None,
+        })
+    }
+
+    /// Initialize the card, also after it was removed or failed.
+    /// Starts over with a new volume manager so nothing read from a previous card is cached,
+    /// all directories and files must be closed.
+    pub fn mount(&mut self) -> Result<(), Error<Infallible, Infallible, Infallible>> {
+        let (sdcard, time_source) = self.volume_manager.take().expect("volume manager").free();
+        let result = Self::initialize(&sdcard);
+        self.volume_manager = Some(VolumeManager::new(sdcard, time_source));
+        self.layout = result?;
+        Ok(())
+    }
+
+    fn initialize(
+        sdcard: &SdCardType,
+    ) -> Result<Option<FatLayout>, Error<Infallible, Infallible, Infallible>> {
+        sdcard.mark_card_uninit();
+        sdcard.spi(
+            |spi| -> Result<(), Error<Infallible, Infallible, Infallible>> {
+                let bus = spi.bus_mut();
+                bus.apply_config(&SpiConfig::default().with_frequency(Rate::from_khz(400)))?;
+                // Send 74+ clock cycles (10 bytes = 80 cycles)
+                // CS must NOT be asserted, so bypass the device
+                let mut dummy = [0xFF; 10];
+                SpiBus::transfer_in_place(bus, &mut dummy)?;
+                Ok(())
+            },
+        )?;
+
         // Force initialization
-        let _ = sdcard.num_bytes();
+        sdcard
+            .num_bytes()
+            .map_err(embedded_sdmmc::Error::DeviceError)?;
 
         // Reconfigure frequency
         sdcard.spi(|spi| {
@@ -60,44 +103,141 @@ impl SdCard {
                 .apply_config(&SpiConfig::default().with_frequency(Rate::from_mhz(80)))
         })?;
 
-        let volume_manager = VolumeManager::new(sdcard, DummyTimesource);
+        Ok(FatLayout::read(sdcard)?)
+    }
 
-        Ok(Self { volume_manager })
+    fn volume_manager(&self) -> &VolumeManagerType {
+        self.volume_manager.as_ref().expect("volume manager")
     }
 
-    pub fn open_directory<DN, F, R>(
-        &mut self,
-        dirname: DN,
+    pub fn open_root_directory<F, R>(
+        &self,
         f: F,
     ) -> Result<R, Error<embedded_sdmmc::Error<SdCardError>, Infallible, Infallible>>
     where
-        DN: ToShortFileName,
         F: FnOnce(
             &DirectoryType,
         )
             -> Result<R, Error<embedded_sdmmc::Error<SdCardError>, Infallible, Infallible>>,
     {
-        let volume = self.volume_manager.open_volume(VolumeIdx(0))?;
+        let volume = self.volume_manager().open_volume(VolumeIdx(0))?;
         let root_directory = volume.open_root_dir()?;
-        let directory = root_directory.open_dir(dirname)?;
 
-        let result = f(&directory)?;
+        let result = f(&root_directory)?;
 
         // Close in reverse order
-        directory.close()?;
         root_directory.close()?;
         volume.close()?;
 
         Ok(result)
     }
+
+    pub fn open_directory<DN, F, R>(
+        &self,
+        dirname: DN,
+        f: F,
+    ) -> Result<R, Error<embedded_sdmmc::Error<SdCardError>, Infallible, Infallible>>
+    where
+        DN: ToShortFileName,
+        F: FnOnce(
+            &DirectoryType,
+        )
+            -> Result<R, Error<embedded_sdmmc::Error<SdCardError>, Infallible, Infallible>>,
+    {
+        self.open_root_directory(|root_directory| {
+            let directory = root_directory.open_dir(dirname)?;
+            let result = f(&directory)?;
+            directory.close()?;
+            Ok(result)
+        })
+    }
+
+    /// Open `name` in `directory` for reading, with multi-block reads if it is not fragmented
+    pub fn open_video<'a>(
+        &'a self,
+        directory: &DirectoryType<'a>,
+        name: ShortFileName,
+    ) -> SdResult<VideoFile<'a>> {
+        let entry = directory.find_directory_entry(name)?;
+        let blocks = CardBlocks(self.volume_manager());
+        if let Some(layout) = self.layout
+            && let Some(first_block) = layout.contiguous(&blocks, &entry)?
+        {
+            return Ok(VideoFile::Contiguous(ContiguousFile::new(
+                blocks,
+                first_block,
+                entry.size,
+            )));
+        }
+        log::debug!("{name} is fragmented");
+        Ok(VideoFile::Fragmented(
+            directory.open_file_in_dir(name, Mode::ReadOnly)?,
+        ))
+    }
+}
+
+/// Whether `error` means the card failed or was removed
+pub fn is_fault(error: &embedded_sdmmc::Error<SdCardError>) -> bool {
+    matches!(error, embedded_sdmmc::Error::DeviceError(_))
+}
+
+/// The card's block device, shared with the volume manager
+pub struct CardBlocks<'a>(&'a VolumeManagerType);
+
+impl BlockDevice for CardBlocks<'_> {
+    type Error = SdCardError;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), SdCardError> {
+        self.0.device(|card| card.read(blocks, start_block_idx))
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), SdCardError> {
+        self.0.device(|card| card.write(blocks, start_block_idx))
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, SdCardError> {
+        self.0.device(|card| card.num_blocks())
+    }
+}
+
+/// Video file, read directly from the card if it is stored contiguously
+pub enum VideoFile<'a> {
+    Contiguous(ContiguousFile<CardBlocks<'a>>),
+    Fragmented(FileType<'a>),
+}
+
+impl ErrorType for VideoFile<'_> {
+    type Error = embedded_sdmmc::Error<SdCardError>;
+}
+
+impl Read for VideoFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> SdResult<usize> {
+        let start = Instant::now();
+        let result = match self {
+            VideoFile::Contiguous(file) => file.read(buf),
+            VideoFile::Fragmented(file) => file.read(buf),
+        };
+        stats::add_read_time(start.elapsed().as_micros() as u32);
+        result
+    }
+}
+
+impl Seek for VideoFile<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> SdResult<u64> {
+        match self {
+            VideoFile::Contiguous(file) => file.seek(pos),
+            VideoFile::Fragmented(file) => file.seek(pos),
+        }
+    }
 }
 
 pub struct DummyTimesource;
 
 impl TimeSource for DummyTimesource {
     fn get_timestamp(&self) -> Timestamp {
+        // FAT timestamps start in 1980
         Timestamp {
-            year_since_1970: 0,
+            year_since_1970: 10,
             zero_indexed_month: 0,
             zero_indexed_day: 0,
             hours: 0,
@@ -0,0 +1,37 @@
+use crate::{library, sdcard::DirectoryType};
+use cyd_encoder::settings::{FILENAME, Settings};
+use embedded_io::Read;
+use embedded_sdmmc::Mode;
+
+// Longest settings file, the rest of a longer file is ignored
+const MAX_SIZE: usize = 1024;
+
+/// Read `CYD.CFG` from `root`, using the defaults if there is no settings file
+pub fn load(root: &DirectoryType) -> library::Result<Settings> {
+    let mut file = match root.open_file_in_dir(FILENAME, Mode::ReadOnly) {
+        Ok(file) => file,
+        Err(embedded_sdmmc::Error::NotFound) => return Ok(Settings::default()),
+        Err(e) => return Err(e),
+    };
+    let mut buffer = [0u8; MAX_SIZE];
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+    if !file.is_eof() {
+        log::warn!("{FILENAME} is longer than {MAX_SIZE} bytes, ignoring the rest");
+    }
+    file.close()?;
+
+    let contents = match str::from_utf8(&buffer[..len]) {
+        Ok(contents) => contents,
+        // Keep the valid part, e.g. when the limit splits a character
+        Err(e) => str::from_utf8(&buffer[..e.valid_up_to()]).unwrap(),
+    };
+    Ok(Settings::parse(contents, |line, e| {
+        log::warn!("{FILENAME} line {line}: {e:?}");
+    }))
+}
@@ -0,0 +1,36 @@
+//! Timing statistics of played videos, kept as CSV in the video directory
+
+use crate::{
+    library::{self, Folder},
+    sdcard::DirectoryType,
+};
+use cyd_core::{log_file::append_rotating, stats::Summary};
+use embedded_sdmmc::ShortFileName;
+
+const FILENAME: &str = "STATS.CSV";
+const OLD_FILENAME: &str = "STATS.1";
+// Start over when the log grows beyond this, keeping the previous log
+const MAX_SIZE: u32 = 64 * 1024;
+
+/// Append the statistics of `folder/name` to the log in `directory`
+pub fn append(
+    directory: &DirectoryType,
+    folder: &Folder,
+    name: &ShortFileName,
+    summary: &Summary,
+) -> library::Result<()> {
+    let mut buffer = [0u8; 256];
+    let line = format_no_std::show(
+        &mut buffer,
+        format_args!("{folder}{name},{}", summary.csv()),
+    )
+    .unwrap_or_default();
+    append_rotating(
+        directory,
+        FILENAME,
+        OLD_FILENAME,
+        MAX_SIZE,
+        Some(Summary::CSV_HEADER.as_bytes()),
+        line.as_bytes(),
+    )
+}
@@ -2,12 +2,24 @@ use core::{
     cell::RefCell,
     sync::atomic::{AtomicBool, Ordering},
 };
+
+#[cfg(feature = "touch-on-display-bus")]
+use crate::display::{self, TOUCH_BYTES};
 use critical_section::Mutex;
+use cyd_core::gesture::{Action, Bindings, Gesture, GestureConfig, GestureRecognizer};
+use cyd_encoder::settings::Settings;
+use embedded_graphics::prelude::{Point, Size};
+#[cfg(not(feature = "touch-on-display-bus"))]
+use esp_hal::{
+    delay::Delay,
+    peripherals::{GPIO25, GPIO32, GPIO39},
+};
 use esp_hal::{
-    gpio::{Event, Input, InputConfig, Io, Pull},
+    gpio::{Event, Input, InputConfig, Io, Level, Output, OutputConfig, Pull},
     handler,
-    peripherals::{GPIO36, IO_MUX},
+    peripherals::{GPIO33, GPIO36, IO_MUX},
     ram,
+    time::Instant,
 };
 
 static TOUCH: Mutex<RefCell<Option<Input>>> = Mutex::new(RefCell::new(None));
@@ -36,6 +48,283 @@ impl TouchDetector {
     }
 }
 
+pub struct Peripherals {
+    pub io_mux: IO_MUX<'static>,
+    pub irq: GPIO36<'static>,
+    #[cfg(not(feature = "touch-on-display-bus"))]
+    pub sclk: GPIO25<'static>,
+    #[cfg(not(feature = "touch-on-display-bus"))]
+    pub mosi: GPIO32<'static>,
+    #[cfg(not(feature = "touch-on-display-bus"))]
+    pub miso: GPIO39<'static>,
+    pub cs: GPIO33<'static>,
+}
+
+/// Maps raw 12-bit XPT2046 readings to screen coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct Calibration {
+    pub x_min: u16,
+    pub x_max: u16,
+    pub y_min: u16,
+    pub y_max: u16,
+    pub swap_xy: bool,
+    pub invert_x: bool,
+    pub invert_y: bool,
+    pub screen: Size,
+}
+
+impl Calibration {
+    pub fn new(screen: Size) -> Self {
+        Self {
+            x_min: 200,
+            x_max: 3700,
+            y_min: 240,
+            y_max: 3800,
+            swap_xy: false,
+            invert_x: false,
+            invert_y: false,
+            screen,
+        }
+    }
+
+    pub fn map(&self, raw_x: u16, raw_y: u16) -> Point {
+        let (raw_x, raw_y) = if self.swap_xy {
+            (raw_y, raw_x)
+        } else {
+            (raw_x, raw_y)
+        };
+        let x = scale(
+            raw_x,
+            self.x_min,
+            self.x_max,
+            self.screen.width,
+            self.invert_x,
+        );
+        let y = scale(
+            raw_y,
+            self.y_min,
+            self.y_max,
+            self.screen.height,
+            self.invert_y,
+        );
+        Point::new(x, y)
+    }
+}
+
+fn scale(raw: u16, min: u16, max: u16, size: u32, invert: bool) -> i32 {
+    let range = max.saturating_sub(min).max(1) as u32;
+    let offset = raw.clamp(min, max).saturating_sub(min) as u32;
+    let value = (offset * size.saturating_sub(1) / range) as i32;
+    if invert {
+        size.saturating_sub(1) as i32 - value
+    } else {
+        value
+    }
+}
+
+mod command {
+    pub const X: u8 = 0xD0;
+    pub const Y: u8 = 0x90;
+    pub const Z1: u8 = 0xB0;
+    pub const Z2: u8 = 0xC0;
+}
+
+// Minimum pressure for a valid touch
+const PRESSURE_THRESHOLD: u16 = 400;
+
+// Pressure, then position. The first conversion after switching channel is noisy,
+// the first X is discarded.
+const SAMPLE: [u8; 5] = [command::Z1, command::Z2, command::X, command::X, command::Y];
+
+/// Read XPT2046 touch coordinates
+pub struct TouchScreen {
+    bus: TouchBus,
+    calibration: Calibration,
+}
+
+impl TouchScreen {
+    pub fn new(bus: TouchBus, calibration: Calibration) -> Self {
+        Self { bus, calibration }
+    }
+
+    /// Current touch position in screen coordinates, or `None` if not touched
+    pub fn sample(&mut self) -> Option<Point> {
+        let [z1, z2, _, x, y] = self.bus.read(SAMPLE)?;
+        let pressure = (z1 + 4095).saturating_sub(z2);
+        (pressure >= PRESSURE_THRESHOLD).then(|| self.calibration.map(x, y))
+    }
+}
+
+/// The 2.8" CYD wires the touch controller to pins not on either SPI bus,
+/// so it is bit-banged
+#[cfg(not(feature = "touch-on-display-bus"))]
+pub struct TouchBus {
+    sclk: Output<'static>,
+    mosi: Output<'static>,
+    miso: Input<'static>,
+    cs: Output<'static>,
+    delay: Delay,
+}
+
+#[cfg(not(feature = "touch-on-display-bus"))]
+impl TouchBus {
+    pub fn new(
+        sclk: GPIO25<'static>,
+        mosi: GPIO32<'static>,
+        miso: GPIO39<'static>,
+        cs: GPIO33<'static>,
+    ) -> Self {
+        Self {
+            sclk: Output::new(sclk, Level::Low, OutputConfig::default()),
+            mosi: Output::new(mosi, Level::Low, OutputConfig::default()),
+            miso: Input::new(miso, InputConfig::default()),
+            cs: Output::new(cs, Level::High, OutputConfig::default()),
+            delay: Delay::new(),
+        }
+    }
+
+    /// Send `commands` and read the 12-bit result of each
+    fn read<const N: usize>(&mut self, commands: [u8; N]) -> Option<[u16; N]> {
+        self.cs.set_low();
+        let values = commands.map(|command| self.transfer(command));
+        self.cs.set_high();
+        Some(values)
+    }
+
+    fn transfer(&mut self, command: u8) -> u16 {
+        for bit in (0..8).rev() {
+            self.mosi.set_level(Level::from((command >> bit) & 1 == 1));
+            self.clock();
+        }
+        self.mosi.set_low();
+        // Busy cycle
+        self.clock();
+        let mut value = 0u16;
+        for _ in 0..12 {
+            self.clock();
+            value = (value << 1) | self.miso.is_high() as u16;
+        }
+        // Pad to 24 clocks total
+        for _ in 0..3 {
+            self.clock();
+        }
+        value
+    }
+
+    fn clock(&mut self) {
+        self.sclk.set_high();
+        self.delay.delay_micros(1);
+        self.sclk.set_low();
+        self.delay.delay_micros(1);
+    }
+}
+
+/// The 2.4" and 3.5" CYDs share the display SPI bus with the touch controller
+#[cfg(feature = "touch-on-display-bus")]
+pub struct TouchBus {
+    cs: Output<'static>,
+}
+
+#[cfg(feature = "touch-on-display-bus")]
+impl TouchBus {
+    pub fn new(cs: GPIO33<'static>) -> Self {
+        Self {
+            cs: Output::new(cs, Level::High, OutputConfig::default()),
+        }
+    }
+
+    /// Send `commands` and read the 12-bit result of each, `None` if the bus failed
+    fn read<const N: usize>(&mut self, commands: [u8; N]) -> Option<[u16; N]> {
+        // Each command takes 24 clocks: 8 to send it, a busy cycle, 12 bits and padding
+        let mut bytes = [0u8; TOUCH_BYTES];
+        for (frame, command) in bytes.chunks_exact_mut(3).zip(commands) {
+            frame[0] = command;
+        }
+        if let Err(e) = display::touch_transfer(&mut self.cs, &mut bytes) {
+            log::warn!("touch error: {e:?}");
+            return None;
+        }
+        let mut frames = bytes.chunks_exact(3);
+        Some([(); N].map(|_| {
+            let frame = frames.next().expect("commands fit the transfer");
+            (u16::from_be_bytes([frame[1], frame[2]]) >> 3) & 0xFFF
+        }))
+    }
+}
+
+/// Touch input translated into player actions via gestures
+pub struct Touch {
+    detector: TouchDetector,
+    screen: TouchScreen,
+    recognizer: GestureRecognizer,
+    bindings: Bindings,
+    enabled: bool,
+}
+
+impl Touch {
+    pub fn new(peripherals: Peripherals, screen: Size) -> Self {
+        Self {
+            detector: TouchDetector::new(peripherals.io_mux, peripherals.irq),
+            screen: TouchScreen::new(
+                #[cfg(not(feature = "touch-on-display-bus"))]
+                TouchBus::new(
+                    peripherals.sclk,
+                    peripherals.mosi,
+                    peripherals.miso,
+                    peripherals.cs,
+                ),
+                #[cfg(feature = "touch-on-display-bus")]
+                TouchBus::new(peripherals.cs),
+                Calibration::new(screen),
+            ),
+            recognizer: GestureRecognizer::new(GestureConfig::default()),
+            bindings: Bindings::new(screen),
+            enabled: true,
+        }
+    }
+
+    /// Apply the touch settings and match the calibration to the display rotation
+    pub fn configure(&mut self, settings: &Settings) {
+        self.enabled = settings.touch_enabled;
+        self.bindings.apply(&settings.touch);
+        // The calibration defaults to 270 degrees, 90 degrees turns the panel upside down
+        let upside_down = settings.rotation == 90;
+        self.screen.calibration.invert_x = upside_down;
+        self.screen.calibration.invert_y = upside_down;
+    }
+
+    pub fn bindings_mut(&mut self) -> &mut Bindings {
+        &mut self.bindings
+    }
+
+    /// Poll for a recognized gesture
+    pub fn poll_gesture(&mut self) -> Option<Gesture> {
+        if !self.enabled {
+            return None;
+        }
+        let now_ms = Instant::now().duration_since_epoch().as_millis() as u32;
+        // Only bit-bang the controller once the IRQ line reported a touch
+        let position = if self.detector.was_touched() || self.recognizer.is_active() {
+            self.screen.sample()
+        } else {
+            None
+        };
+        self.recognizer.update(now_ms, position)
+    }
+
+    /// True while the screen is touched or a tap may still become a double tap
+    pub fn is_active(&self) -> bool {
+        self.enabled && self.recognizer.is_active()
+    }
+
+    /// Poll for a recognized gesture and return the action bound to it
+    pub fn poll(&mut self) -> Action {
+        self.poll_gesture()
+            .map(|gesture| self.bindings.action(gesture))
+            .unwrap_or(Action::None)
+    }
+}
+
 #[handler]
 #[ram]
 fn touch_handler() {
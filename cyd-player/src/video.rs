@@ -1,65 +1,379 @@
-use core::{fmt, ops::DerefMut};
+use core::fmt;
 
-use crate::{display::CENTER, error::Error, touch::TouchDetector, video::decoder::Decoder};
-use cyd_encoder::format::FormatHeader;
-use embedded_graphics::{image::Image, pixelcolor::Rgb565, prelude::*};
-use embedded_io::{Read, Seek};
+use crate::{
+    backlight::Backlight, board, display::CENTER, error::Error, osd::Osd, power::Power,
+    touch::Touch, video::decoder::Decoder,
+};
+use cyd_core::{
+    blit::{Blit, BlitTarget, RowsAbove},
+    fade,
+    gesture::Action,
+    stats::{self, FrameTimes, Stats},
+};
+use embedded_graphics::{
+    image::ImageDrawable, pixelcolor::Rgb565, prelude::*, primitives::Rectangle,
+};
 use esp_hal::{
     delay::Delay,
     time::{Duration, Instant},
 };
 
+pub mod any;
 pub mod decoder;
 #[cfg(feature = "mjpeg")]
 pub mod mjpeg;
+#[cfg(any(feature = "yuv", feature = "rgb"))]
+pub mod raw;
 #[cfg(feature = "rgb")]
 pub mod rgb;
 #[cfg(feature = "yuv")]
 pub mod yuv;
 
-#[allow(clippy::type_complexity)]
-pub fn play<R, DT, const HEADER_SIZE: usize, F, const DECODE_SIZE: usize, D>(
-    reader: R,
-    mut display: &mut DT,
-    touch_detector: &TouchDetector,
-) -> Result<(), Error<R::Error, D::DecoderError, DT::Error>>
+// Bad frames in a row that are skipped before giving up on the video
+const MAX_BAD_FRAMES: u32 = 10;
+
+/// How playback of a video ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Played {
+    Finished,
+    Next,
+    Previous,
+    Stopped,
+}
+
+/// Part of a video to play
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Playback {
+    /// Offset in seconds to start playing at
+    pub start: u32,
+    /// Maximum number of seconds to play from `start`
+    pub duration: Option<u32>,
+    /// Frame to resume at, if after `start`
+    pub frame: u32,
+}
+
+/// Play a video, decoding into `buffer` and calling `progress` with the number of each frame
+/// before decoding it. The time spent on each frame is added to `stats`.
+/// The backlight fades in as the video starts, fading out is up to the caller.
+/// Waits between frames go through `power`, which may sleep lightly.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn play<D, DT, const N: usize>(
+    mut decoder: D,
+    buffer: &mut [u8],
+    display: &mut DT,
+    backlight: &mut Backlight,
+    touch: &mut Touch,
+    power: &mut Power,
+    playback: Playback,
+    osd: &mut Osd,
+    stats: &mut Stats<N>,
+    progress: &mut dyn FnMut(u32),
+) -> Result<Played, Error<D::ReadError, D::CodecError, DT::Error>>
 where
-    R: Read + Seek,
-    DT: DrawTarget<Color = Rgb565>,
+    D: Decoder,
+    DT: BlitTarget,
     DT::Error: fmt::Debug,
-    F: FormatHeader<HEADER_SIZE>,
-    D: Decoder<R, DT, HEADER_SIZE, F, DECODE_SIZE>,
 {
     display.clear(Rgb565::BLACK).expect("clear");
     let delay = Delay::new();
     let mut start: Option<Instant> = None;
-    let mut decoder = D::new(reader)?;
-    let frame_duration = Duration::from_micros((1000 * 1000) / decoder.header().fps() as u64);
-    let mut buffer = [0u8; DECODE_SIZE];
-    let mut count: u16 = 0;
+    let fps = decoder.fps() as u32;
+    let frame_duration = Duration::from_micros((1000 * 1000) / fps as u64);
+    decoder.check_buffer(buffer)?;
+    decoder.check_size(&board::BOARD)?;
+    let first = playback.start * fps;
+    let end = playback.duration.map(|duration| first + duration * fps);
+    let mut frame = first.max(playback.frame);
+    let mut bad_frames = 0;
+    if frame > 0 && !decoder.skip(frame, buffer)? {
+        return Ok(Played::Finished);
+    }
+    #[cfg(feature = "dual-core")]
+    let mut offloaded = Offloaded::take();
+    backlight.fade_in();
     loop {
-        if let Some(pixels) = decoder.decode_into(&mut buffer)? {
-            let image = Image::with_center(&pixels, CENTER);
-            if let Some(start) = start {
-                let elapsed = start.elapsed();
-                if frame_duration > elapsed {
-                    delay.delay(frame_duration - elapsed);
-                } else {
-                    log::warn!("lag {:?}", elapsed - frame_duration);
-                }
+        if end.is_some_and(|end| frame >= end) {
+            #[cfg(feature = "dual-core")]
+            finish_offloaded::<D, _>(&mut offloaded, display, &mut bad_frames)?;
+            return Ok(Played::Finished);
+        }
+        progress(frame);
+        frame += 1;
+        let mut lag = Duration::ZERO;
+        let mut idle = Duration::ZERO;
+        let mut timed = Timed::new(display);
+        let decode_start = Instant::now();
+        stats::take_read_time();
+        #[cfg(feature = "dual-core")]
+        let next = {
+            let mut drawn = Ok(());
+            let next = match &mut offloaded {
+                // Draw the bands of the frame decoding on the other core between reads
+                Some(offloaded) => decoder
+                    .decode_into_with(buffer, &mut || offloaded.draw_ready(&mut timed, &mut drawn)),
+                None => decoder.decode_into(buffer),
+            };
+            drawn.map_err(Error::DisplayError)?;
+            finish_offloaded::<D, _>(&mut offloaded, &mut timed, &mut bad_frames)?;
+            next
+        };
+        #[cfg(not(feature = "dual-core"))]
+        let next = decoder.decode_into(buffer);
+        let Some(pixels) = next? else {
+            return Ok(Played::Finished);
+        };
+        let produced = decode_start.elapsed();
+        if let Some(start) = start {
+            let elapsed = start.elapsed();
+            if frame_duration > elapsed {
+                idle = frame_duration - elapsed;
+                power.wait(idle, backlight);
+            } else {
+                lag = elapsed - frame_duration;
+                log::warn!("lag {lag:?}");
             }
-            start = Some(Instant::now());
-            decoder.render(image, display.deref_mut())?;
-        } else {
-            return Ok(());
+        }
+        start = Some(Instant::now());
+        let rows = osd.begin_frame(&mut timed).map_err(Error::DisplayError)?;
+        let render_start = Instant::now();
+        #[cfg(feature = "dual-core")]
+        let drawn_here = !offloaded
+            .as_mut()
+            .is_some_and(|offloaded| offloaded.submit(D::jpeg(&pixels), frame, rows));
+        #[cfg(not(feature = "dual-core"))]
+        let drawn_here = true;
+        if drawn_here {
+            render(&pixels, &mut RowsAbove::new(&mut timed, rows)).map_err(Error::DisplayError)?;
+            check_frame(frame, D::frame_error(&pixels), &mut bad_frames)
+                .map_err(Error::DecodeErrors)?;
+        }
+        let work = produced + render_start.elapsed();
+        // Decoding is whatever else producing the frame took
+        let read = stats::take_read_time();
+        let decode = (work.as_micros() as u32).saturating_sub(read + micros(timed.time));
+        osd.end_frame(&mut timed, frame, decoder.frames(), decoder.fps(), lag)
+            .map_err(Error::DisplayError)?;
+        let times = FrameTimes {
+            read,
+            decode,
+            display: micros(timed.time),
+            idle: micros(idle),
         };
-        count += 1;
-        if count >= 5 {
-            count = 0;
-            if touch_detector.was_touched() {
-                display.clear(Rgb565::BLUE).expect("clear");
-                return Ok(());
+        stats.record(times, lag > Duration::ZERO);
+        backlight.update();
+        let action = touch.poll();
+        if touch.is_active() {
+            osd.touched();
+        }
+        if let Some(played) = ended_by(action) {
+            return Ok(played);
+        }
+        match action {
+            Action::TogglePause => {
+                // Show the whole frame while paused
+                #[cfg(feature = "dual-core")]
+                finish_offloaded::<D, _>(&mut offloaded, display, &mut bad_frames)?;
+                if let Some(played) = ended_by(pause(touch, &delay)) {
+                    return Ok(played);
+                }
+                // Don't count the pause as lag
+                start = None;
+            }
+            Action::BrightnessUp | Action::BrightnessDown => {
+                let brightness = backlight.brightness();
+                backlight.set_brightness(if action == Action::BrightnessUp {
+                    fade::brighter(brightness)
+                } else {
+                    fade::dimmer(brightness)
+                });
+                log::info!("brightness {}%", backlight.brightness());
             }
+            _ => {}
+        }
+    }
+}
+
+/// How playing ends on `action`, if it does
+fn ended_by(action: Action) -> Option<Played> {
+    match action {
+        Action::Next => Some(Played::Next),
+        Action::Previous => Some(Played::Previous),
+        Action::Stop => Some(Played::Stopped),
+        _ => None,
+    }
+}
+
+/// Draw `frame` centered on the display
+pub fn render<F, DT>(frame: &F, display: &mut DT) -> Result<(), DT::Error>
+where
+    F: ImageDrawable<Color = Rgb565> + Blit,
+    DT: BlitTarget,
+{
+    frame.blit(display, top_left(frame))
+}
+
+/// Skip a frame that failed to decode, unless too many failed in a row
+fn check_frame<C: fmt::Debug>(frame: u32, error: Option<C>, bad_frames: &mut u32) -> Result<(), C> {
+    match error {
+        Some(e) if *bad_frames >= MAX_BAD_FRAMES => return Err(e),
+        Some(e) => {
+            log::warn!("frame {frame} decode error: {e:?}");
+            *bad_frames += 1;
+        }
+        None => *bad_frames = 0,
+    }
+    Ok(())
+}
+
+/// JPEG frames decoded on the second core, drawn while the next frame is read
+#[cfg(feature = "dual-core")]
+struct Offloaded<C> {
+    offload: crate::dual_core::Offload,
+    // Number and rows above the overlay of the frame in flight, with the conversion of its error
+    in_flight: Option<(u32, i32, fn(tjpgdec_rs::Error) -> C)>,
+}
+
+#[cfg(feature = "dual-core")]
+impl<C> Offloaded<C> {
+    /// `None` without the second core
+    fn take() -> Option<Self> {
+        Some(Self {
+            offload: crate::dual_core::Offload::take()?,
+            in_flight: None,
+        })
+    }
+
+    /// Decode `jpeg` on the second core, false if the frame must be drawn here
+    fn submit(&mut self, jpeg: Option<decoder::Jpeg<'_, C>>, frame: u32, rows: i32) -> bool {
+        let Some(jpeg) = jpeg else {
+            return false;
+        };
+        if !self.offload.submit(jpeg.data) {
+            return false;
+        }
+        self.in_flight = Some((frame, rows, jpeg.error));
+        true
+    }
+
+    /// Draw the bands of the frame in flight decoded so far
+    fn draw_ready<T: BlitTarget>(&mut self, target: &mut T, result: &mut Result<(), T::Error>) {
+        if let Some((_, rows, _)) = self.in_flight {
+            self.offload
+                .draw_ready(&mut RowsAbove::new(target, rows), result);
+        }
+    }
+
+    /// Draw the rest of the frame in flight, returns its number and decode error
+    fn finish<T: BlitTarget>(
+        &mut self,
+        target: &mut T,
+    ) -> Result<Option<(u32, Option<C>)>, T::Error> {
+        let Some((frame, rows, error)) = self.in_flight.take() else {
+            return Ok(None);
+        };
+        let mut result = Ok(());
+        let decode_error = self
+            .offload
+            .finish(&mut RowsAbove::new(target, rows), &mut result);
+        result?;
+        Ok(Some((frame, decode_error.map(error))))
+    }
+}
+
+/// Draw the rest of the frame decoded on the second core and check it decoded
+#[cfg(feature = "dual-core")]
+#[allow(clippy::type_complexity)]
+fn finish_offloaded<D, DT>(
+    offloaded: &mut Option<Offloaded<D::CodecError>>,
+    display: &mut DT,
+    bad_frames: &mut u32,
+) -> Result<(), Error<D::ReadError, D::CodecError, DT::Error>>
+where
+    D: Decoder,
+    DT: BlitTarget,
+    DT::Error: fmt::Debug,
+{
+    if let Some(offloaded) = offloaded
+        && let Some((frame, error)) = offloaded.finish(display).map_err(Error::DisplayError)?
+    {
+        check_frame(frame, error, bad_frames).map_err(Error::DecodeErrors)?;
+    }
+    Ok(())
+}
+
+fn micros(duration: Duration) -> u32 {
+    duration.as_micros() as u32
+}
+
+/// Display that counts the time spent drawing
+struct Timed<'a, DT> {
+    display: &'a mut DT,
+    time: Duration,
+}
+
+impl<'a, DT: BlitTarget> Timed<'a, DT> {
+    fn new(display: &'a mut DT) -> Self {
+        Self {
+            display,
+            time: Duration::ZERO,
+        }
+    }
+
+    fn timed<R>(&mut self, f: impl FnOnce(&mut DT) -> R) -> R {
+        let start = Instant::now();
+        let result = f(self.display);
+        self.time += start.elapsed();
+        result
+    }
+}
+
+impl<DT: BlitTarget> Dimensions for Timed<'_, DT> {
+    fn bounding_box(&self) -> Rectangle {
+        self.display.bounding_box()
+    }
+}
+
+impl<DT: BlitTarget> DrawTarget for Timed<'_, DT> {
+    type Color = Rgb565;
+    type Error = DT::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.timed(|display| display.draw_iter(pixels))
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        self.timed(|display| display.fill_contiguous(area, colors))
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.timed(|display| display.fill_solid(area, color))
+    }
+}
+
+impl<DT: BlitTarget> BlitTarget for Timed<'_, DT> {
+    fn blit(&mut self, area: &Rectangle, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.timed(|display| display.blit(area, bytes))
+    }
+}
+
+/// Top left corner of `frame` centered on the display
+pub fn top_left<F: OriginDimensions>(frame: &F) -> Point {
+    Rectangle::with_center(CENTER, frame.size()).top_left
+}
+
+/// Wait until playing resumes or ends, returning the action that did it
+fn pause(touch: &mut Touch, delay: &Delay) -> Action {
+    loop {
+        delay.delay_millis(20);
+        let action = touch.poll();
+        if action == Action::TogglePause || ended_by(action).is_some() {
+            return action;
         }
     }
 }
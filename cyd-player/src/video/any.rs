@@ -0,0 +1,354 @@
+//! Decoding of all enabled formats, chosen per file by extension.
+
+use crate::video::decoder::{DecodeError, Decoder};
+use cyd_core::blit::{Blit, BlitTarget};
+use cyd_encoder::format::yuv::{ColorRange, Matrix};
+use embedded_graphics::{
+    image::ImageDrawable, pixelcolor::Rgb565, prelude::*, primitives::Rectangle,
+};
+use embedded_io::{Read, ReadExactError, Seek};
+use embedded_sdmmc::ShortFileName;
+
+#[cfg(feature = "rgb")]
+use crate::video::rgb::{self, RgbDecoder};
+#[cfg(feature = "yuv")]
+use crate::video::yuv::{self, YuvDecoder};
+#[cfg(feature = "mjpeg")]
+use crate::video::{
+    decoder::Jpeg,
+    mjpeg::{self, MjpegDecoder},
+};
+#[cfg(feature = "mjpeg")]
+use cyd_core::jpeg::JpegDrawable;
+
+#[cfg(not(any(feature = "mjpeg", feature = "yuv", feature = "rgb")))]
+compile_error!("enable at least one of the mjpeg, yuv and rgb features");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[cfg(feature = "mjpeg")]
+    Mjpeg,
+    #[cfg(feature = "yuv")]
+    Yuv,
+    #[cfg(feature = "rgb")]
+    Rgb,
+}
+
+impl Format {
+    /// Enabled formats
+    pub const ALL: &'static [Format] = &[
+        #[cfg(feature = "mjpeg")]
+        Format::Mjpeg,
+        #[cfg(feature = "yuv")]
+        Format::Yuv,
+        #[cfg(feature = "rgb")]
+        Format::Rgb,
+    ];
+
+    pub const fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            Format::Mjpeg => "MJP",
+            #[cfg(feature = "yuv")]
+            Format::Yuv => "YUV",
+            #[cfg(feature = "rgb")]
+            Format::Rgb => "RGB",
+        }
+    }
+
+    pub fn from_extension(extension: &[u8]) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.extension().as_bytes() == extension)
+    }
+
+    pub fn from_name(name: &ShortFileName) -> Option<Self> {
+        Self::from_extension(name.extension())
+    }
+}
+
+/// Video directory used without a `directory` setting,
+/// named after the format if only one is enabled
+pub const DEFAULT_DIRECTORY: &str = if Format::ALL.len() == 1 {
+    Format::ALL[0].extension()
+} else {
+    "VIDEO"
+};
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+/// Buffer size for the largest frame of any enabled format
+pub const DECODE_SIZE: usize = {
+    let size = 0;
+    #[cfg(feature = "mjpeg")]
+    let size = max(size, mjpeg::DECODE_SIZE);
+    #[cfg(feature = "yuv")]
+    let size = max(size, yuv::DECODE_SIZE);
+    #[cfg(feature = "rgb")]
+    let size = max(size, rgb::DECODE_SIZE);
+    size
+};
+
+#[derive(Debug)]
+pub enum CodecError {
+    #[cfg(feature = "mjpeg")]
+    Jpeg(tjpgdec_rs::Error),
+}
+
+#[cfg(any(feature = "yuv", feature = "rgb"))]
+fn infallible(e: core::convert::Infallible) -> CodecError {
+    match e {}
+}
+
+/// Decoder for any enabled format
+#[allow(
+    clippy::large_enum_variant,
+    reason = "there is only one decoder, for the video being played"
+)]
+pub enum AnyDecoder<R>
+where
+    R: Read + Seek,
+{
+    #[cfg(feature = "mjpeg")]
+    Mjpeg(MjpegDecoder<R>),
+    #[cfg(feature = "yuv")]
+    Yuv(YuvDecoder<R>),
+    #[cfg(feature = "rgb")]
+    Rgb(RgbDecoder<R>),
+}
+
+impl<R: Read + Seek> AnyDecoder<R> {
+    pub fn new(reader: R, format: Format) -> Result<Self, ReadExactError<R::Error>> {
+        Ok(match format {
+            #[cfg(feature = "mjpeg")]
+            Format::Mjpeg => AnyDecoder::Mjpeg(MjpegDecoder::new(reader)?),
+            #[cfg(feature = "yuv")]
+            Format::Yuv => AnyDecoder::Yuv(YuvDecoder::new(reader)?),
+            #[cfg(feature = "rgb")]
+            Format::Rgb => AnyDecoder::Rgb(RgbDecoder::new(reader)?),
+        })
+    }
+
+    /// Colors `yuv` videos were encoded with, other formats ignore them
+    #[cfg_attr(not(feature = "yuv"), allow(unused_variables))]
+    pub fn with_yuv_colors(self, matrix: Matrix, range: ColorRange) -> Self {
+        match self {
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => AnyDecoder::Yuv(decoder.with_colors(matrix, range)),
+            #[allow(unreachable_patterns)]
+            decoder => decoder,
+        }
+    }
+}
+
+impl<R: Read + Seek> Decoder for AnyDecoder<R> {
+    type ReadError = R::Error;
+    type CodecError = CodecError;
+    type Frame<'a> = AnyFrame<'a>;
+
+    fn fps(&self) -> u8 {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyDecoder::Mjpeg(decoder) => decoder.fps(),
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => decoder.fps(),
+            #[cfg(feature = "rgb")]
+            AnyDecoder::Rgb(decoder) => decoder.fps(),
+        }
+    }
+
+    fn frames(&self) -> Option<u32> {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyDecoder::Mjpeg(decoder) => decoder.frames(),
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => decoder.frames(),
+            #[cfg(feature = "rgb")]
+            AnyDecoder::Rgb(decoder) => decoder.frames(),
+        }
+    }
+
+    fn frame_size(&self) -> Option<Size> {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyDecoder::Mjpeg(decoder) => decoder.frame_size(),
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => decoder.frame_size(),
+            #[cfg(feature = "rgb")]
+            AnyDecoder::Rgb(decoder) => decoder.frame_size(),
+        }
+    }
+
+    fn frame_error(frame: &AnyFrame<'_>) -> Option<CodecError> {
+        match frame {
+            #[cfg(feature = "mjpeg")]
+            AnyFrame::Mjpeg(frame) => frame.take_error().map(CodecError::Jpeg),
+            #[cfg(feature = "yuv")]
+            AnyFrame::Yuv(_) => None,
+            #[cfg(feature = "rgb")]
+            AnyFrame::Rgb(_) => None,
+        }
+    }
+
+    fn buffer_size(&self) -> usize {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyDecoder::Mjpeg(decoder) => decoder.buffer_size(),
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => decoder.buffer_size(),
+            #[cfg(feature = "rgb")]
+            AnyDecoder::Rgb(decoder) => decoder.buffer_size(),
+        }
+    }
+
+    fn decode_into<'a>(
+        &'a mut self,
+        buffer: &'a mut [u8],
+    ) -> Result<Option<Self::Frame<'a>>, DecodeError<R::Error, CodecError>> {
+        Ok(match self {
+            #[cfg(feature = "mjpeg")]
+            AnyDecoder::Mjpeg(decoder) => decoder
+                .decode_into(buffer)
+                .map_err(|e| e.map_codec(CodecError::Jpeg))?
+                .map(AnyFrame::Mjpeg),
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => decoder
+                .decode_into(buffer)
+                .map_err(|e| e.map_codec(infallible))?
+                .map(AnyFrame::Yuv),
+            #[cfg(feature = "rgb")]
+            AnyDecoder::Rgb(decoder) => decoder
+                .decode_into(buffer)
+                .map_err(|e| e.map_codec(infallible))?
+                .map(AnyFrame::Rgb),
+        })
+    }
+
+    fn decode_into_with<'a>(
+        &'a mut self,
+        buffer: &'a mut [u8],
+        between_reads: &mut dyn FnMut(),
+    ) -> Result<Option<Self::Frame<'a>>, DecodeError<R::Error, CodecError>> {
+        Ok(match self {
+            #[cfg(feature = "mjpeg")]
+            AnyDecoder::Mjpeg(decoder) => decoder
+                .decode_into_with(buffer, between_reads)
+                .map_err(|e| e.map_codec(CodecError::Jpeg))?
+                .map(AnyFrame::Mjpeg),
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => decoder
+                .decode_into_with(buffer, between_reads)
+                .map_err(|e| e.map_codec(infallible))?
+                .map(AnyFrame::Yuv),
+            #[cfg(feature = "rgb")]
+            AnyDecoder::Rgb(decoder) => decoder
+                .decode_into_with(buffer, between_reads)
+                .map_err(|e| e.map_codec(infallible))?
+                .map(AnyFrame::Rgb),
+        })
+    }
+
+    #[cfg(feature = "mjpeg")]
+    fn jpeg<'f>(frame: &'f AnyFrame<'_>) -> Option<Jpeg<'f, CodecError>> {
+        match frame {
+            AnyFrame::Mjpeg(frame) => Some(Jpeg {
+                data: MjpegDecoder::<R>::jpeg(frame)?.data,
+                error: CodecError::Jpeg,
+            }),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    fn skip(
+        &mut self,
+        frames: u32,
+        buffer: &mut [u8],
+    ) -> Result<bool, DecodeError<R::Error, CodecError>> {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyDecoder::Mjpeg(decoder) => decoder
+                .skip(frames, buffer)
+                .map_err(|e| e.map_codec(CodecError::Jpeg)),
+            #[cfg(feature = "yuv")]
+            AnyDecoder::Yuv(decoder) => decoder
+                .skip(frames, buffer)
+                .map_err(|e| e.map_codec(infallible)),
+            #[cfg(feature = "rgb")]
+            AnyDecoder::Rgb(decoder) => decoder
+                .skip(frames, buffer)
+                .map_err(|e| e.map_codec(infallible)),
+        }
+    }
+}
+
+/// Decoded frame of any enabled format
+pub enum AnyFrame<'a> {
+    #[cfg(feature = "mjpeg")]
+    Mjpeg(JpegDrawable<'a>),
+    #[cfg(feature = "yuv")]
+    Yuv(yuv::Pixels<'a>),
+    #[cfg(feature = "rgb")]
+    Rgb(rgb::Pixels<'a>),
+}
+
+impl Blit for AnyFrame<'_> {
+    fn blit<T: BlitTarget>(&self, target: &mut T, top_left: Point) -> Result<(), T::Error> {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyFrame::Mjpeg(drawable) => drawable.blit(target, top_left),
+            #[cfg(feature = "yuv")]
+            AnyFrame::Yuv(drawable) => drawable.blit(target, top_left),
+            #[cfg(feature = "rgb")]
+            AnyFrame::Rgb(drawable) => drawable.blit(target, top_left),
+        }
+    }
+}
+
+impl ImageDrawable for AnyFrame<'_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyFrame::Mjpeg(drawable) => drawable.draw(target),
+            #[cfg(feature = "yuv")]
+            AnyFrame::Yuv(drawable) => drawable.draw(target),
+            #[cfg(feature = "rgb")]
+            AnyFrame::Rgb(drawable) => drawable.draw(target),
+        }
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyFrame::Mjpeg(drawable) => drawable.draw_sub_image(target, area),
+            #[cfg(feature = "yuv")]
+            AnyFrame::Yuv(drawable) => drawable.draw_sub_image(target, area),
+            #[cfg(feature = "rgb")]
+            AnyFrame::Rgb(drawable) => drawable.draw_sub_image(target, area),
+        }
+    }
+}
+
+impl OriginDimensions for AnyFrame<'_> {
+    fn size(&self) -> Size {
+        match self {
+            #[cfg(feature = "mjpeg")]
+            AnyFrame::Mjpeg(drawable) => drawable.size(),
+            #[cfg(feature = "yuv")]
+            AnyFrame::Yuv(drawable) => drawable.size(),
+            #[cfg(feature = "rgb")]
+            AnyFrame::Rgb(drawable) => drawable.size(),
+        }
+    }
+}
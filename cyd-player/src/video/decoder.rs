@@ -1,39 +1,146 @@
 use core::fmt;
 
-use crate::error::Error;
-use cyd_encoder::format::FormatHeader;
-use embedded_graphics::{
-    image::{Image, ImageDrawable},
-    pixelcolor::Rgb565,
-    prelude::DrawTarget,
-};
-use embedded_io::{Read, ReadExactError, Seek};
-
-pub trait Decoder<R, D, const HEADER_SIZE: usize, F, const DECODE_SIZE: usize>
-where
-    R: Read + Seek,
-    D: DrawTarget<Color = Rgb565>,
-    D::Error: fmt::Debug,
-    F: FormatHeader<HEADER_SIZE>,
-    Self: Sized,
-{
-    type DecoderError: fmt::Debug;
-    type ImageDrawable<'a>: ImageDrawable + 'a;
-
-    fn new(reader: R) -> Result<Self, ReadExactError<R::Error>>;
-
-    fn header(&self) -> &F;
+use cyd_core::blit::Blit;
+use cyd_encoder::board::Board;
+use embedded_graphics::{geometry::Size, image::ImageDrawable, pixelcolor::Rgb565};
 
+/// Errors while decoding, `IO` from the reader and `C` from the codec
+#[derive(Debug)]
+pub enum DecodeError<IO, C> {
+    Read(IO),
+    Codec(C),
+    /// The buffer is smaller than [`Decoder::buffer_size`]
+    BufferTooSmall {
+        required: usize,
+        available: usize,
+    },
+    /// The frames are empty or larger than the panel, see [`Decoder::check_size`]
+    UnsupportedSize(Size),
+}
+
+impl<IO, C> DecodeError<IO, C> {
+    /// Convert the codec error with `f`, keeping other errors
+    pub fn map_codec<E>(self, f: impl FnOnce(C) -> E) -> DecodeError<IO, E> {
+        match self {
+            DecodeError::Read(e) => DecodeError::Read(e),
+            DecodeError::Codec(e) => DecodeError::Codec(f(e)),
+            DecodeError::BufferTooSmall {
+                required,
+                available,
+            } => DecodeError::BufferTooSmall {
+                required,
+                available,
+            },
+            DecodeError::UnsupportedSize(size) => DecodeError::UnsupportedSize(size),
+        }
+    }
+}
+
+/// Compressed JPEG frame
+#[cfg(feature = "mjpeg")]
+pub struct Jpeg<'f, C> {
+    pub data: &'f [u8],
+    /// Converts errors decoding `data` to the decoder's codec error
+    pub error: fn(tjpgdec_rs::Error) -> C,
+}
+
+/// Decodes video frames into a buffer supplied by the caller.
+/// Frames are drawn separately, so decoders don't depend on the display.
+pub trait Decoder {
+    type ReadError: fmt::Debug;
+    type CodecError: fmt::Debug;
+    type Frame<'a>: ImageDrawable<Color = Rgb565> + Blit + 'a;
+
+    fn fps(&self) -> u8;
+
+    /// Number of frames in the video, an estimate if frames vary in size.
+    /// `None` if it isn't known (yet).
+    fn frames(&self) -> Option<u32> {
+        None
+    }
+
+    /// Size of every frame, `None` if frames may differ in size
+    fn frame_size(&self) -> Option<Size> {
+        None
+    }
+
+    /// Error decoding `frame` while it was drawn, drawing only returns display errors.
+    /// Frames that are decoded before drawing never fail.
+    fn frame_error(_frame: &Self::Frame<'_>) -> Option<Self::CodecError> {
+        None
+    }
+
+    /// Minimum buffer size for [`Decoder::decode_into`] and [`Decoder::skip`]
+    fn buffer_size(&self) -> usize;
+
+    /// Decode the next frame, or `None` at the end of the video.
+    /// Pass the same buffer every time, decoders may keep data read ahead in it.
     #[allow(clippy::type_complexity)]
     fn decode_into<'a>(
-        &mut self,
-        buffer: &'a mut [u8; DECODE_SIZE],
-    ) -> Result<Option<Self::ImageDrawable<'a>>, Error<R::Error, Self::DecoderError, D::Error>>;
+        &'a mut self,
+        buffer: &'a mut [u8],
+    ) -> Result<Option<Self::Frame<'a>>, DecodeError<Self::ReadError, Self::CodecError>>;
 
+    /// Like [`Decoder::decode_into`], calling `between_reads` between reads from the file
+    /// so other work can go on while a frame is read
     #[allow(clippy::type_complexity)]
-    fn render<'a>(
-        &'a self,
-        image: Image<Self::ImageDrawable<'a>>,
-        display: &mut D,
-    ) -> Result<(), Error<R::Error, Self::DecoderError, D::Error>>;
+    fn decode_into_with<'a>(
+        &'a mut self,
+        buffer: &'a mut [u8],
+        _between_reads: &mut dyn FnMut(),
+    ) -> Result<Option<Self::Frame<'a>>, DecodeError<Self::ReadError, Self::CodecError>> {
+        self.decode_into(buffer)
+    }
+
+    /// Compressed data of `frame` if it is a JPEG that can be decoded anywhere,
+    /// with the conversion of its decode errors.
+    /// Frames that read the file while they are drawn are never JPEGs.
+    #[cfg(feature = "mjpeg")]
+    fn jpeg<'f>(_frame: &'f Self::Frame<'_>) -> Option<Jpeg<'f, Self::CodecError>> {
+        None
+    }
+
+    /// Skip `frames` frames without drawing them, returns false if the end was reached
+    fn skip(
+        &mut self,
+        frames: u32,
+        buffer: &mut [u8],
+    ) -> Result<bool, DecodeError<Self::ReadError, Self::CodecError>> {
+        for _ in 0..frames {
+            if self.decode_into(buffer)?.is_none() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Check `buffer` is at least [`Decoder::buffer_size`]
+    fn check_buffer(
+        &self,
+        buffer: &[u8],
+    ) -> Result<(), DecodeError<Self::ReadError, Self::CodecError>> {
+        let required = self.buffer_size();
+        if buffer.len() < required {
+            return Err(DecodeError::BufferTooSmall {
+                required,
+                available: buffer.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check frames of [`Decoder::frame_size`] fit the panel of `board`, videos encoded
+    /// for a larger panel or with a broken header can't be played
+    fn check_size(
+        &self,
+        board: &Board,
+    ) -> Result<(), DecodeError<Self::ReadError, Self::CodecError>> {
+        match self.frame_size() {
+            // Frame sizes are read from 16-bit header fields
+            Some(size) if !board.fits(size.width as u16, size.height as u16) => {
+                Err(DecodeError::UnsupportedSize(size))
+            }
+            _ => Ok(()),
+        }
+    }
 }
@@ -0,0 +1,89 @@
+//! Reading raw frames of a fixed size in bands while they are drawn.
+//!
+//! Frames only hold a small band buffer, so they read from the file as they are drawn.
+//! Drawing can only return display errors, a read error is kept and returned when the
+//! next frame is decoded.
+
+use embedded_io::{Read, ReadExactError, Seek, SeekFrom};
+
+/// Reads the band of a frame at a position in the file
+pub trait ReadAt {
+    /// Fill `buffer` from `position`, false if it couldn't be read
+    fn read_at(&mut self, position: u64, buffer: &mut [u8]) -> bool;
+}
+
+/// Frames of `frame_size` bytes following the header
+pub struct RawReader<R: Read + Seek> {
+    reader: R,
+    frame_size: u64,
+    first_frame: u64,
+    next_frame: u64,
+    end: u64,
+    error: Option<R::Error>,
+}
+
+impl<R: Read + Seek> RawReader<R> {
+    /// Frames start at the current position of `reader`
+    pub fn new(mut reader: R, frame_size: usize) -> Result<Self, ReadExactError<R::Error>> {
+        let first_frame = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            reader,
+            frame_size: frame_size as u64,
+            first_frame,
+            next_frame: first_frame,
+            end,
+            error: None,
+        })
+    }
+
+    /// Number of complete frames
+    pub fn frames(&self) -> u32 {
+        ((self.end - self.first_frame) / self.frame_size) as u32
+    }
+
+    /// Position of the next frame, `None` at the end.
+    /// Returns the error reading the previous frame, if any.
+    pub fn next_frame(&mut self) -> Result<Option<u64>, R::Error> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let frame = self.next_frame;
+        if frame + self.frame_size > self.end {
+            return Ok(None);
+        }
+        self.next_frame += self.frame_size;
+        Ok(Some(frame))
+    }
+
+    /// Skip `frames` frames, returns false if the end was reached
+    pub fn skip(&mut self, frames: u32) -> bool {
+        let target = self.next_frame + self.frame_size * frames as u64;
+        if target > self.end {
+            return false;
+        }
+        self.next_frame = target;
+        true
+    }
+}
+
+impl<R: Read + Seek> ReadAt for RawReader<R> {
+    fn read_at(&mut self, position: u64, buffer: &mut [u8]) -> bool {
+        if self.error.is_some() {
+            return false;
+        }
+        let result = match self.reader.seek(SeekFrom::Start(position)) {
+            Ok(_) => self.reader.read_exact(buffer),
+            Err(e) => Err(ReadExactError::Other(e)),
+        };
+        match result {
+            Ok(()) => true,
+            // The length was checked, so the file was cut short while playing
+            Err(ReadExactError::UnexpectedEof) => false,
+            Err(ReadExactError::Other(e)) => {
+                self.error = Some(e);
+                false
+            }
+        }
+    }
+}
@@ -1,67 +1,156 @@
-use crate::{error::Error, video::decoder::Decoder};
-use core::fmt;
+use crate::{
+    display::SIZE,
+    video::{
+        decoder::{DecodeError, Decoder},
+        raw::{RawReader, ReadAt},
+    },
+};
+use core::{cell::RefCell, convert::Infallible};
+use cyd_core::blit::{Blit, BlitTarget, fill_bytes};
 use cyd_encoder::format::{FormatHeader, rgb::RgbHeader};
 use embedded_graphics::{
-    image::{Image, ImageRaw},
-    pixelcolor::Rgb565,
-    prelude::*,
+    image::ImageDrawable, pixelcolor::Rgb565, prelude::*, primitives::Rectangle,
 };
 use embedded_io::{Read, ReadExactError, Seek};
 
-pub struct RgbDecoder<R> {
+// Lines read and drawn at a time
+const BAND_LINES: usize = 16;
+
+pub struct RgbDecoder<R: Read + Seek> {
     header: RgbHeader,
-    reader: R,
+    reader: RawReader<R>,
 }
 
-pub const DECODE_SIZE: usize = (RgbHeader::MAX_WIDTH * RgbHeader::MAX_HEIGHT) * 2;
-
-impl<R, D> Decoder<R, D, 5, RgbHeader, { DECODE_SIZE }> for RgbDecoder<R>
-where
-    R: Read + Seek,
-    D: DrawTarget<Color = Rgb565>,
-    D::Error: fmt::Debug,
-{
-    type DecoderError = R::Error;
-    type ImageDrawable<'a> = ImageRaw<'a, Rgb565>;
-
-    fn new(mut reader: R) -> Result<Self, ReadExactError<R::Error>> {
+impl<R: Read + Seek> RgbDecoder<R> {
+    pub fn new(mut reader: R) -> Result<Self, ReadExactError<R::Error>> {
         let mut buffer = [0u8; 5];
         reader.read_exact(&mut buffer)?;
         let header = RgbHeader::parse(&buffer);
-        Ok(Self { header, reader })
+        let frame_size = (header.width() as usize * header.height() as usize) * 2;
+        Ok(Self {
+            header,
+            reader: RawReader::new(reader, frame_size)?,
+        })
+    }
+
+    fn band_size(&self) -> usize {
+        self.header.width() as usize * BAND_LINES * 2
     }
+}
+
+// Videos are encoded to fit the panel
+const MAX_WIDTH: usize = SIZE.width as usize;
+
+/// Buffer size for a band of the widest frame
+pub const DECODE_SIZE: usize = MAX_WIDTH * BAND_LINES * 2;
+
+impl<R: Read + Seek> Decoder for RgbDecoder<R> {
+    type ReadError = R::Error;
+    type CodecError = Infallible;
+    type Frame<'a> = Pixels<'a>;
 
-    fn header(&self) -> &RgbHeader {
-        &self.header
+    fn fps(&self) -> u8 {
+        self.header.fps()
+    }
+
+    fn frames(&self) -> Option<u32> {
+        Some(self.reader.frames())
+    }
+
+    fn frame_size(&self) -> Option<Size> {
+        Some(Size::new(
+            self.header.width() as u32,
+            self.header.height() as u32,
+        ))
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.band_size()
     }
 
     fn decode_into<'a>(
+        &'a mut self,
+        buffer: &'a mut [u8],
+    ) -> Result<Option<Self::Frame<'a>>, DecodeError<R::Error, Infallible>> {
+        self.check_buffer(buffer)?;
+        let band_size = self.band_size();
+        let size = Size::new(self.header.width() as u32, self.header.height() as u32);
+        let Some(position) = self.reader.next_frame().map_err(DecodeError::Read)? else {
+            return Ok(None);
+        };
+        Ok(Some(Pixels {
+            reader: RefCell::new(&mut self.reader),
+            buffer: RefCell::new(&mut buffer[..band_size]),
+            position,
+            size,
+        }))
+    }
+
+    fn skip(
         &mut self,
-        buffer: &'a mut [u8; DECODE_SIZE],
-    ) -> Result<Option<Self::ImageDrawable<'a>>, Error<R::Error, Self::DecoderError, D::Error>>
-    {
-        let width = self.header.width() as u32;
-        let height = self.header.height() as u32;
-        let buffer = &mut buffer[..((width * height) * 2) as usize];
-        match self.reader.read_exact(buffer) {
-            Ok(_) => {}
-            Err(ReadExactError::UnexpectedEof) => {
-                return Ok(None);
+        frames: u32,
+        _buffer: &mut [u8],
+    ) -> Result<bool, DecodeError<R::Error, Infallible>> {
+        // Raw frames have a fixed size, so seek instead of reading them
+        Ok(self.reader.skip(frames))
+    }
+}
+
+/// RGB565 frame that is read band by band while it is drawn
+pub struct Pixels<'a> {
+    reader: RefCell<&'a mut dyn ReadAt>,
+    buffer: RefCell<&'a mut [u8]>,
+    position: u64,
+    size: Size,
+}
+
+impl Pixels<'_> {
+    /// Read the frame band by band, calling `f` with the area of each band relative to the frame
+    fn bands<E>(&self, mut f: impl FnMut(Rectangle, &[u8]) -> Result<(), E>) -> Result<(), E> {
+        let mut reader = self.reader.borrow_mut();
+        let mut buffer = self.buffer.borrow_mut();
+        let line_size = self.size.width as usize * 2;
+        for y in (0..self.size.height).step_by(BAND_LINES) {
+            let lines = (self.size.height - y).min(BAND_LINES as u32);
+            let band = &mut buffer[..line_size * lines as usize];
+            let offset = y as u64 * line_size as u64;
+            if !reader.read_at(self.position + offset, band) {
+                break;
             }
-            Err(ReadExactError::Other(e)) => return Err(Error::ReadError(e)),
+            let area = Rectangle::new(Point::new(0, y as i32), Size::new(self.size.width, lines));
+            f(area, band)?;
         }
-        Ok(Some(ImageRaw::<Rgb565>::new(
-            &buffer[..((width * height) * 2) as usize],
-            width,
-        )))
+        Ok(())
     }
+}
 
-    fn render<'a>(
-        &'a self,
-        image: Image<Self::ImageDrawable<'a>>,
-        display: &mut D,
-    ) -> Result<(), Error<R::Error, Self::DecoderError, D::Error>> {
-        image.draw(display).map_err(Error::DisplayError)?;
-        Ok(())
+impl Blit for Pixels<'_> {
+    fn blit<T: BlitTarget>(&self, target: &mut T, top_left: Point) -> Result<(), T::Error> {
+        // Frames are stored as big-endian RGB565 already
+        self.bands(|area, band| target.blit(&area.translate(top_left), band))
+    }
+}
+
+impl ImageDrawable for Pixels<'_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.bands(|area, band| fill_bytes(target, &area, band))
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw(&mut target.translated(-area.top_left).clipped(area))
+    }
+}
+
+impl OriginDimensions for Pixels<'_> {
+    fn size(&self) -> Size {
+        self.size
     }
 }
@@ -1,113 +1,194 @@
-use crate::{error::Error, video::decoder::Decoder};
-use core::fmt;
-use cyd_encoder::format::{FormatHeader, yuv::YuvHeader};
+use crate::{
+    display::SIZE,
+    video::{
+        decoder::{DecodeError, Decoder},
+        raw::{RawReader, ReadAt},
+    },
+};
+use core::{cell::RefCell, convert::Infallible};
+use cyd_core::{
+    blit::{Blit, BlitTarget, fill_bytes},
+    yuv::Converter,
+};
+use cyd_encoder::format::{
+    FormatHeader,
+    yuv::{ColorRange, Matrix, YuvHeader},
+};
 use embedded_graphics::{
-    image::{Image, ImageDrawable},
-    pixelcolor::Rgb565,
-    prelude::*,
-    primitives::Rectangle,
+    image::ImageDrawable, pixelcolor::Rgb565, prelude::*, primitives::Rectangle,
 };
 use embedded_io::{Read, ReadExactError, Seek};
 
-pub struct YuvDecoder<R> {
+// Luma lines read at a time, with half as many lines of each chroma plane
+const BAND_LINES: usize = 16;
+
+pub struct YuvDecoder<R: Read + Seek> {
     header: YuvHeader,
-    reader: R,
+    reader: RawReader<R>,
+    converter: &'static Converter,
 }
 
-pub const DECODE_SIZE: usize = (YuvHeader::MAX_WIDTH * YuvHeader::MAX_HEIGHT)
-    + (YuvHeader::MAX_WIDTH * YuvHeader::MAX_HEIGHT) / 2;
-
-impl<R, D> Decoder<R, D, 5, YuvHeader, { DECODE_SIZE }> for YuvDecoder<R>
-where
-    R: Read + Seek,
-    D: DrawTarget<Color = Rgb565>,
-    D::Error: fmt::Debug,
-{
-    type DecoderError = R::Error;
-    type ImageDrawable<'a> = Pixels<'a>;
-
-    fn new(mut reader: R) -> Result<Self, ReadExactError<R::Error>> {
+impl<R: Read + Seek> YuvDecoder<R> {
+    pub fn new(mut reader: R) -> Result<Self, ReadExactError<R::Error>> {
         let mut buffer = [0u8; 5];
         reader.read_exact(&mut buffer)?;
         let header = YuvHeader::parse(&buffer);
-        Ok(Self { header, reader })
+        let pixels = header.width() as usize * header.height() as usize;
+        Ok(Self {
+            header,
+            reader: RawReader::new(reader, pixels + pixels / 2)?,
+            converter: Converter::get(Matrix::Bt709, ColorRange::Full),
+        })
+    }
+
+    /// Convert colors encoded with `matrix` and `range`, instead of BT.709 full range
+    pub fn with_colors(mut self, matrix: Matrix, range: ColorRange) -> Self {
+        self.converter = Converter::get(matrix, range);
+        self
+    }
+
+    fn band_size(&self) -> usize {
+        let width = self.header.width() as usize;
+        width * BAND_LINES + (width / 2) * BAND_LINES
+    }
+}
+
+// Videos are encoded to fit the panel
+const MAX_WIDTH: usize = SIZE.width as usize;
+
+/// Buffer size for a band of the widest frame
+pub const DECODE_SIZE: usize = MAX_WIDTH * BAND_LINES + (MAX_WIDTH / 2) * BAND_LINES;
+
+impl<R: Read + Seek> Decoder for YuvDecoder<R> {
+    type ReadError = R::Error;
+    type CodecError = Infallible;
+    type Frame<'a> = Pixels<'a>;
+
+    fn fps(&self) -> u8 {
+        self.header.fps()
+    }
+
+    fn frames(&self) -> Option<u32> {
+        Some(self.reader.frames())
+    }
+
+    fn frame_size(&self) -> Option<Size> {
+        Some(Size::new(
+            self.header.width() as u32,
+            self.header.height() as u32,
+        ))
     }
 
-    fn header(&self) -> &YuvHeader {
-        &self.header
+    fn buffer_size(&self) -> usize {
+        self.band_size()
     }
 
     fn decode_into<'a>(
-        &mut self,
-        buffer: &'a mut [u8; DECODE_SIZE],
-    ) -> Result<Option<Self::ImageDrawable<'a>>, Error<R::Error, Self::DecoderError, D::Error>>
-    {
-        let width = self.header.width() as u32;
-        let height = self.header.height() as u32;
-        let buffer = &mut buffer[..((width * height) + (width * height) / 2) as usize];
-        let size = Size::new(width, height);
-        match self.reader.read_exact(buffer) {
-            Ok(_) => {}
-            Err(ReadExactError::UnexpectedEof) => {
-                return Ok(None);
-            }
-            Err(ReadExactError::Other(e)) => return Err(Error::ReadError(e)),
-        }
-        Ok(Some(Pixels::new(buffer, size)))
+        &'a mut self,
+        buffer: &'a mut [u8],
+    ) -> Result<Option<Self::Frame<'a>>, DecodeError<R::Error, Infallible>> {
+        self.check_buffer(buffer)?;
+        let band_size = self.band_size();
+        let size = Size::new(self.header.width() as u32, self.header.height() as u32);
+        let Some(position) = self.reader.next_frame().map_err(DecodeError::Read)? else {
+            return Ok(None);
+        };
+        Ok(Some(Pixels {
+            reader: RefCell::new(&mut self.reader),
+            buffer: RefCell::new(&mut buffer[..band_size]),
+            position,
+            size,
+            converter: self.converter,
+        }))
     }
 
-    fn render<'a>(
-        &'a self,
-        image: Image<Self::ImageDrawable<'a>>,
-        display: &mut D,
-    ) -> Result<(), Error<R::Error, Self::DecoderError, D::Error>> {
-        image.draw(display).map_err(Error::DisplayError)?;
-        Ok(())
+    fn skip(
+        &mut self,
+        frames: u32,
+        _buffer: &mut [u8],
+    ) -> Result<bool, DecodeError<R::Error, Infallible>> {
+        // Raw frames have a fixed size, so seek instead of reading them
+        Ok(self.reader.skip(frames))
     }
 }
 
+/// YUV 4:2:0 frame that is read band by band while it is drawn
 pub struct Pixels<'a> {
-    yuv: &'a [u8],
+    reader: RefCell<&'a mut dyn ReadAt>,
+    buffer: RefCell<&'a mut [u8]>,
+    position: u64,
     size: Size,
+    converter: &'static Converter,
 }
 
-impl<'a> Pixels<'a> {
-    fn new(yuv: &'a [u8], size: Size) -> Self {
-        Self { yuv, size }
+impl Pixels<'_> {
+    /// Read luma lines `y..y + lines` and the chroma lines they share into `buffer`,
+    /// returns the luma, U and V planes of the band
+    fn read_band<'b>(
+        &self,
+        reader: &mut dyn ReadAt,
+        buffer: &'b mut [u8],
+        y: usize,
+        lines: usize,
+    ) -> Option<(&'b [u8], &'b [u8], &'b [u8])> {
+        let width = self.size.width as usize;
+        let height = self.size.height as usize;
+        let chroma_width = width / 2;
+        let chroma_lines = lines.div_ceil(2).min(height / 2 - y / 2);
+        let (luma, chroma) = buffer.split_at_mut(width * lines);
+        let (u, chroma) = chroma.split_at_mut(chroma_width * chroma_lines);
+        let v = &mut chroma[..chroma_width * chroma_lines];
+
+        let u_plane = self.position + (width * height) as u64;
+        let v_plane = u_plane + (chroma_width * (height / 2)) as u64;
+        let chroma_offset = ((y / 2) * chroma_width) as u64;
+        let read = reader.read_at(self.position + (y * width) as u64, luma)
+            && reader.read_at(u_plane + chroma_offset, u)
+            && reader.read_at(v_plane + chroma_offset, v);
+        read.then_some((luma, u, v))
     }
 
-    fn pixels(&'a self) -> impl Iterator<Item = Rgb565> + 'a {
+    /// Convert the frame two rows at a time, which share a row of chroma,
+    /// calling `f` with the area of the rows relative to the frame
+    fn rows<E>(&self, mut f: impl FnMut(Rectangle, &[u8]) -> Result<(), E>) -> Result<(), E> {
+        let mut reader = self.reader.borrow_mut();
+        let mut buffer = self.buffer.borrow_mut();
         let width = self.size.width as usize;
         let height = self.size.height as usize;
-        let y_plane_len = width * height;
-        let uv_plane_len = (width / 2) * (height / 2);
-
-        (0..height).flat_map(move |y| {
-            (0..width).map(move |x| {
-                let y_index = y * width + x;
-                let y_val = self.yuv[y_index] as f32;
-
-                let uv_x = x / 2;
-                let uv_y = y / 2;
-                let uv_index = uv_y * (width / 2) + uv_x;
-
-                let u = self.yuv[y_plane_len + uv_index] as f32 - 128.0;
-                let v = self.yuv[y_plane_len + uv_plane_len + uv_index] as f32 - 128.0;
-
-                // --- BT.709 full-range ---
-                let r = y_val + 1.5748 * v;
-                let g = y_val - 0.1873 * u - 0.4681 * v;
-                let b = y_val + 1.8556 * u;
-
-                let r8 = r.clamp(0.0, 255.0) as u8;
-                let g8 = g.clamp(0.0, 255.0) as u8;
-                let b8 = b.clamp(0.0, 255.0) as u8;
-
-                // Convert 8-bit values to RGB565 bit depths
-                // R: 5 bits (0-31), G: 6 bits (0-63), B: 5 bits (0-31)
-                Rgb565::new(r8 >> 3, g8 >> 2, b8 >> 3)
-            })
-        })
+        let chroma_width = width / 2;
+        let mut rows = [0u8; MAX_WIDTH * 2 * 2];
+
+        for band_y in (0..height).step_by(BAND_LINES) {
+            let band_lines = (height - band_y).min(BAND_LINES);
+            let Some((luma, u, v)) = self.read_band(*reader, &mut buffer, band_y, band_lines)
+            else {
+                break;
+            };
+            for y in (0..band_lines).step_by(2) {
+                let lines = (band_lines - y).min(2);
+                let (top, bottom) = rows[..width * 2 * lines].split_at_mut(width * 2);
+                let (top_luma, bottom_luma) = luma[y * width..(y + lines) * width].split_at(width);
+                let uv = (y / 2) * chroma_width..(y / 2 + 1) * chroma_width;
+                self.converter.convert_rows(
+                    [top_luma, bottom_luma],
+                    u.get(uv.clone()).unwrap_or_default(),
+                    v.get(uv).unwrap_or_default(),
+                    [top, bottom],
+                );
+                let area = Rectangle::new(
+                    Point::new(0, (band_y + y) as i32),
+                    Size::new(self.size.width, lines as u32),
+                );
+                f(area, &rows[..width * 2 * lines])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Blit for Pixels<'_> {
+    fn blit<T: BlitTarget>(&self, target: &mut T, top_left: Point) -> Result<(), T::Error> {
+        self.rows(|area, rows| target.blit(&area.translate(top_left), rows))
     }
 }
 
@@ -118,7 +199,7 @@ impl ImageDrawable for Pixels<'_> {
     where
         D: DrawTarget<Color = Self::Color>,
     {
-        target.fill_contiguous(&self.bounding_box(), self.pixels())
+        self.rows(|area, rows| fill_bytes(target, &area, rows))
     }
 
     fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>